@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::{BufWriter, Result, Seek, Write};
+use std::path::Path;
+
+use crate::apu::audio_stream::AudioStream;
+use crate::recorder::wav::WavWriter;
+
+/// Destination of the interleaved stereo samples generated by the APU
+pub trait AudioSink {
+  fn push_samples(&mut self, samples: &[i16]);
+
+  /// Share of the playback queue waiting to be played, for sinks consumed in real time
+  fn fill_level(&self) -> Option<f64> {
+    None
+  }
+
+  /// Blocks until the sink is ready for more samples, returns false when it does not pace the emulation
+  fn wait_for_playback(&self) -> bool {
+    false
+  }
+
+  fn finish(&mut self) -> Result<()> {
+    Ok(())
+  }
+}
+
+/// Where the APU output goes
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum AudioOutput {
+  /// Default sound device, falls back to `None` when there is none
+  #[default]
+  Device,
+  None,
+  Wav(std::path::PathBuf),
+}
+
+impl AudioOutput {
+  pub fn open_sink(&self, sample_rate: u32) -> Box<dyn AudioSink> {
+    match self {
+      AudioOutput::Device => match AudioStream::new(sample_rate) {
+        Ok(stream) => Box::new(stream),
+        Err(e) => {
+          eprintln!("No audio output: {}", e);
+          Box::new(NullSink)
+        }
+      },
+      AudioOutput::None => Box::new(NullSink),
+      AudioOutput::Wav(path) => {
+        let sink = WavSink::create(path, sample_rate);
+        Box::new(sink.unwrap_or_else(|e| panic!("Failed to create audio file {}: {}", path.display(), e)))
+      }
+    }
+  }
+}
+
+/// Discards the samples
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+  fn push_samples(&mut self, _samples: &[i16]) {}
+}
+
+impl AudioSink for AudioStream {
+  fn push_samples(&mut self, samples: &[i16]) {
+    AudioStream::push_samples(self, samples);
+  }
+
+  fn fill_level(&self) -> Option<f64> {
+    Some(AudioStream::fill_level(self))
+  }
+
+  fn wait_for_playback(&self) -> bool {
+    AudioStream::wait_for_playback(self);
+    true
+  }
+}
+
+/// Writes the samples to a 16-bit stereo WAV file, as fast as they are generated
+pub struct WavSink<W: Write + Seek> {
+  writer: WavWriter<W>,
+}
+
+impl WavSink<BufWriter<File>> {
+  pub fn create(path: &Path, sample_rate: u32) -> Result<WavSink<BufWriter<File>>> {
+    WavSink::new(BufWriter::new(File::create(path)?), sample_rate)
+  }
+}
+
+impl<W: Write + Seek> WavSink<W> {
+  pub fn new(writer: W, sample_rate: u32) -> Result<WavSink<W>> {
+    Ok(WavSink {
+      writer: WavWriter::new(writer, sample_rate, 2)?,
+    })
+  }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+  fn push_samples(&mut self, samples: &[i16]) {
+    self.writer.write_samples(samples).expect("Audio file write error");
+  }
+
+  fn finish(&mut self) -> Result<()> {
+    self.writer.finish()
+  }
+}
@@ -1,35 +1,100 @@
 use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use rodio::buffer::SamplesBuffer;
-use rodio::Sink;
+use rodio::{Sink, Source};
 
+use crate::apu::ring_buffer::{Consumer, Producer, ring_buffer};
+
+// Length of the queue between the emulator and the audio device
+const BUFFER_DURATION_MS: usize = 100;
+// Longest wait for the device to drain the queue, in case the audio thread stalls
+const MAX_WAIT: Duration = Duration::from_millis(50);
+
+/// Endless rodio source playing the queued samples, an empty queue plays silence
+struct RingSource {
+  consumer: Consumer,
+  sample_rate: u32,
+}
+
+impl Iterator for RingSource {
+  type Item = i16;
+
+  fn next(&mut self) -> Option<i16> {
+    Some(self.consumer.pop().unwrap_or(0))
+  }
+}
+
+impl Source for RingSource {
+  fn current_frame_len(&self) -> Option<usize> {
+    None
+  }
+
+  fn channels(&self) -> u16 {
+    2
+  }
+
+  fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  fn total_duration(&self) -> Option<Duration> {
+    None
+  }
+}
+
+/// Streams interleaved stereo samples to the default output device through a single persistent sink
 pub struct AudioStream {
-  tx: Sender<Vec<i16>>,
+  producer: Producer,
 }
 
 impl AudioStream {
-  pub fn new() -> AudioStream {
-    let (tx, rx): (Sender<Vec<i16>>, Receiver<Vec<i16>>) = mpsc::channel();
+  /// Opens the default output device, fails on machines without one
+  pub fn new(sample_rate: u32) -> Result<AudioStream, String> {
+    let capacity = sample_rate as usize * BUFFER_DURATION_MS / 1000 * 2;
+    let (producer, consumer) = ring_buffer(capacity);
+    let (tx, rx) = mpsc::channel();
 
+    // The output stream is not Send, it lives on its own thread
     thread::spawn(move || {
-      let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
-      loop {
-        if let Ok(val) = rx.try_recv() {
-          let new_sink = Sink::try_new(&stream_handle).unwrap();
-          new_sink.append(SamplesBuffer::new(2, 44100, val));
-          new_sink.detach();
+      let opened = rodio::OutputStream::try_default().map_err(|e| e.to_string()).and_then(|(stream, handle)| {
+        Sink::try_new(&handle).map(|sink| (stream, sink)).map_err(|e| e.to_string())
+      });
+      match opened {
+        Ok((_stream, sink)) => {
+          sink.append(RingSource { consumer, sample_rate });
+          let _ = tx.send(Ok(()));
+          // The source never ends, this keeps the stream alive without spinning
+          sink.sleep_until_end();
+        }
+        Err(e) => {
+          let _ = tx.send(Err(e));
         }
       }
     });
 
-    AudioStream {
-      tx,
-    }
+    rx.recv().map_err(|e| e.to_string())??;
+    Ok(AudioStream {
+      producer,
+    })
+  }
+
+  /// Queues samples for playback, the ones that do not fit are dropped when the emulator runs too fast
+  pub fn push_samples(&mut self, samples: &[i16]) {
+    self.producer.push_slice(samples);
   }
 
-  pub fn send_audio_buffer(&mut self, sample: Vec<i16>) {
-    let _ = self.tx.send(sample);
+  /// Share of the queue waiting to be played, between 0.0 and 1.0
+  pub fn fill_level(&self) -> f64 {
+    self.producer.queued() as f64 / self.producer.capacity() as f64
+  }
+
+  /// Blocks until the device has played the queue down to half its length, so the emulation runs at the pace of
+  /// the audio clock
+  pub fn wait_for_playback(&self) {
+    let start = Instant::now();
+    while self.fill_level() > 0.5 && start.elapsed() < MAX_WAIT {
+      thread::sleep(Duration::from_millis(1));
+    }
   }
 }
@@ -0,0 +1,133 @@
+use std::f64::consts::PI;
+
+// Sub-sample positions of the step kernels
+const PHASE_COUNT: usize = 32;
+// Output samples touched by one step
+const KERNEL_WIDTH: usize = 16;
+// Bandwidth of the kernels relative to the output Nyquist frequency
+const CUTOFF: f64 = 0.9;
+// Headroom for output rates raised by rate control
+const MAX_FACTOR_INCREASE: f64 = 1.01;
+
+/// Band-limited step synthesis in the style of blip_buf. Amplitude changes are added as deltas at exact clock
+/// timestamps and spread over a few output samples with a windowed sinc, so the resampled signal does not alias.
+#[derive(Clone)]
+pub struct BlipBuf {
+  // Output samples per clock
+  factor: f64,
+  // Output position of the start of the current frame, only the fractional part is left after reads
+  offset: f64,
+  // One more kernel than phases, steps between two phases are interpolated
+  kernels: [[f64; KERNEL_WIDTH]; PHASE_COUNT + 1],
+  deltas: Vec<f64>,
+  max_factor: f64,
+  integrator: f64,
+}
+
+impl BlipBuf {
+  /// Buffer for frames of up to `max_clocks` clocks
+  pub fn new(clock_rate: u32, sample_rate: u32, max_clocks: u32) -> BlipBuf {
+    let factor = f64::from(sample_rate) / f64::from(clock_rate);
+    let max_samples = (f64::from(max_clocks) * factor * MAX_FACTOR_INCREASE).ceil() as usize + 1;
+
+    let mut kernels = [[0.0; KERNEL_WIDTH]; PHASE_COUNT + 1];
+    for (phase, kernel) in kernels.iter_mut().enumerate() {
+      let center = (KERNEL_WIDTH / 2) as f64 + phase as f64 / PHASE_COUNT as f64;
+      for (idx, value) in kernel.iter_mut().enumerate() {
+        let x = idx as f64 - center;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * CUTOFF * x).sin() / (PI * CUTOFF * x) };
+        // Blackman window over the kernel width
+        let w = 2.0 * PI * (x / KERNEL_WIDTH as f64 + 0.5);
+        let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+        *value = sinc * window.max(0.0);
+      }
+      // Every kernel adds up to the full step, whatever the phase
+      let sum: f64 = kernel.iter().sum();
+      kernel.iter_mut().for_each(|value| *value /= sum);
+    }
+
+    BlipBuf {
+      factor,
+      offset: 0.0,
+      kernels,
+      deltas: vec![0.0; max_samples + KERNEL_WIDTH],
+      max_factor: factor * MAX_FACTOR_INCREASE,
+      integrator: 0.0,
+    }
+  }
+
+  /// Changes the resampling ratio, takes effect from the next frame
+  pub fn set_rates(&mut self, clock_rate: u32, sample_rate: f64) {
+    self.factor = (sample_rate / f64::from(clock_rate)).min(self.max_factor);
+  }
+
+  /// Adds an amplitude change at `time` clocks after the start of the current frame
+  pub fn add_delta(&mut self, time: u32, delta: f64) {
+    let position = self.offset + f64::from(time) * self.factor;
+    let idx = position as usize;
+    let phase = (position - idx as f64) * PHASE_COUNT as f64;
+    let (lo, hi) = (&self.kernels[phase as usize], &self.kernels[phase as usize + 1]);
+    let interpolation = phase.fract();
+    for (k, sample) in self.deltas[idx..idx + KERNEL_WIDTH].iter_mut().enumerate() {
+      *sample += delta * (lo[k] + (hi[k] - lo[k]) * interpolation);
+    }
+  }
+
+  /// Ends the current frame after `clocks` clocks, its whole output samples become available
+  pub fn end_frame(&mut self, clocks: u32) {
+    self.offset += f64::from(clocks) * self.factor;
+  }
+
+  pub fn samples_avail(&self) -> usize {
+    self.offset as usize
+  }
+
+  /// Moves the available samples to `out`, steps are delayed by half the kernel width
+  pub fn read_samples(&mut self, out: &mut Vec<f64>) {
+    let count = self.samples_avail();
+    for delta in &self.deltas[..count] {
+      self.integrator += delta;
+      out.push(self.integrator);
+    }
+    self.deltas.copy_within(count.., 0);
+    let len = self.deltas.len();
+    self.deltas[len - count..].fill(0.0);
+    self.offset -= count as f64;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::apu::blip_buf::BlipBuf;
+
+  #[test]
+  fn step_settles_to_amplitude() {
+    let mut blip = BlipBuf::new(1_789_773, 44_100, 4096);
+    blip.add_delta(100, 1.0);
+    blip.add_delta(1000, -0.25);
+    blip.end_frame(4096);
+
+    let mut out = Vec::new();
+    blip.read_samples(&mut out);
+    assert_eq!(out.len(), 100);
+    assert!(out[..2].iter().all(|sample| sample.abs() < 1e-9));
+    assert!((out[20] - 1.0).abs() < 0.01);
+    assert!((out[99] - 0.75).abs() < 1e-9);
+  }
+
+  #[test]
+  fn frequencies_above_nyquist_do_not_alias() {
+    let mut blip = BlipBuf::new(1_789_773, 44_100, 4096);
+    let mut out = Vec::new();
+    for _ in 0..4 {
+      // Square wave at half the clock rate
+      for time in 0..4096 {
+        blip.add_delta(time, if time % 2 == 0 { 1.0 } else { -1.0 });
+      }
+      blip.end_frame(4096);
+      blip.read_samples(&mut out);
+    }
+    // Only its average of 0.5 is left
+    assert!(out[20..].iter().all(|sample| (sample - 0.5).abs() < 0.02));
+  }
+}
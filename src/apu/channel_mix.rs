@@ -0,0 +1,184 @@
+use std::fmt;
+
+/// Sound channels as the mixer sees them, all expansion chip channels count as one
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Channel {
+  Pulse0,
+  Pulse1,
+  Triangle,
+  Noise,
+  Dmc,
+  Expansion,
+}
+
+impl Channel {
+  pub const ALL: [Channel; 6] = [
+    Channel::Pulse0,
+    Channel::Pulse1,
+    Channel::Triangle,
+    Channel::Noise,
+    Channel::Dmc,
+    Channel::Expansion,
+  ];
+
+  pub fn from_name(name: &str) -> Option<Channel> {
+    match name.to_lowercase().as_str() {
+      "pulse_0" | "pulse0" | "square1" => Some(Channel::Pulse0),
+      "pulse_1" | "pulse1" | "square2" => Some(Channel::Pulse1),
+      "triangle" => Some(Channel::Triangle),
+      "noise" => Some(Channel::Noise),
+      "dmc" => Some(Channel::Dmc),
+      "expansion" => Some(Channel::Expansion),
+      _ => None,
+    }
+  }
+
+  fn index(self) -> usize {
+    self as usize
+  }
+}
+
+impl fmt::Display for Channel {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Channel::Pulse0 => "pulse_0",
+      Channel::Pulse1 => "pulse_1",
+      Channel::Triangle => "triangle",
+      Channel::Noise => "noise",
+      Channel::Dmc => "dmc",
+      Channel::Expansion => "expansion",
+    };
+    f.write_str(name)
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct ChannelSettings {
+  volume: f64,
+  // -1.0 is left, 1.0 is right
+  pan: f64,
+  is_muted: bool,
+}
+
+const DEFAULT_CHANNEL: ChannelSettings = ChannelSettings { volume: 1.0, pan: 0.0, is_muted: false };
+
+/// Listening controls for each channel. They only change the mix, the channels keep running as the game programs
+/// them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MixSettings {
+  channels: [ChannelSettings; 6],
+  solo: Option<Channel>,
+}
+
+impl Default for MixSettings {
+  fn default() -> Self {
+    MixSettings {
+      channels: [DEFAULT_CHANNEL; 6],
+      solo: None,
+    }
+  }
+}
+
+impl MixSettings {
+  /// Untouched settings leave the output of the console as is
+  pub fn is_default(&self) -> bool {
+    *self == MixSettings::default()
+  }
+
+  /// Any channel panned off center, the output needs separate left and right mixes
+  pub fn is_stereo(&self) -> bool {
+    self.channels.iter().any(|channel| channel.pan != 0.0)
+  }
+
+  pub fn is_muted(&self, channel: Channel) -> bool {
+    self.channels[channel.index()].is_muted
+  }
+
+  pub fn set_muted(&mut self, channel: Channel, is_muted: bool) {
+    self.channels[channel.index()].is_muted = is_muted;
+  }
+
+  pub fn toggle_mute(&mut self, channel: Channel) {
+    self.set_muted(channel, !self.is_muted(channel));
+  }
+
+  /// Only the solo channel is heard, `None` lets every unmuted channel through
+  pub fn set_solo(&mut self, solo: Option<Channel>) {
+    self.solo = solo;
+  }
+
+  pub fn toggle_solo(&mut self, channel: Channel) {
+    self.solo = if self.solo == Some(channel) { None } else { Some(channel) };
+  }
+
+  /// Volume from 0.0, 1.0 is the console level
+  pub fn set_volume(&mut self, channel: Channel, volume: f64) {
+    self.channels[channel.index()].volume = volume.max(0.0);
+  }
+
+  /// Pan from -1.0 on the left to 1.0 on the right
+  pub fn set_pan(&mut self, channel: Channel, pan: f64) {
+    self.channels[channel.index()].pan = pan.clamp(-1.0, 1.0);
+  }
+
+  pub fn is_audible(&self, channel: Channel) -> bool {
+    !self.is_muted(channel) && self.solo.is_none_or(|solo| solo == channel)
+  }
+
+  /// Left and right gains of the channel, zero when it is not heard
+  pub fn gains(&self, channel: Channel) -> [f64; 2] {
+    if !self.is_audible(channel) {
+      return [0.0, 0.0];
+    }
+    let ChannelSettings { volume, pan, .. } = self.channels[channel.index()];
+    [volume * (1.0 - pan).min(1.0), volume * (1.0 + pan).min(1.0)]
+  }
+
+  /// Parses `channel=value` pairs separated by commas, like `triangle=0.5,noise=0`
+  pub fn parse_channel_values(spec: &str) -> Result<Vec<(Channel, f64)>, String> {
+    spec.split(',').map(|pair| {
+      let (name, value) = pair.split_once('=').ok_or_else(|| format!("Expected channel=value, got {}", pair))?;
+      let channel = Channel::from_name(name.trim()).ok_or_else(|| format!("Unknown channel {}", name))?;
+      let value = value.trim().parse::<f64>().map_err(|_| format!("Invalid value {}", value))?;
+      Ok((channel, value))
+    }).collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::apu::channel_mix::{Channel, MixSettings};
+
+  #[test]
+  fn mute_solo_and_pan_gains() {
+    let mut settings = MixSettings::default();
+    assert!(settings.is_default());
+    assert_eq!(settings.gains(Channel::Noise), [1.0, 1.0]);
+
+    settings.toggle_mute(Channel::Noise);
+    assert_eq!(settings.gains(Channel::Noise), [0.0, 0.0]);
+
+    settings.toggle_solo(Channel::Triangle);
+    assert_eq!(settings.gains(Channel::Pulse0), [0.0, 0.0]);
+    assert_eq!(settings.gains(Channel::Triangle), [1.0, 1.0]);
+    // Muting wins over solo
+    settings.toggle_solo(Channel::Noise);
+    assert_eq!(settings.gains(Channel::Noise), [0.0, 0.0]);
+    settings.set_solo(None);
+    settings.toggle_mute(Channel::Noise);
+    assert!(settings.is_default());
+
+    settings.set_volume(Channel::Pulse1, 0.5);
+    settings.set_pan(Channel::Pulse1, -0.5);
+    assert!(settings.is_stereo());
+    assert_eq!(settings.gains(Channel::Pulse1), [0.5, 0.25]);
+  }
+
+  #[test]
+  fn parse_channel_values() {
+    let values = MixSettings::parse_channel_values("triangle=0.5, pulse_0=-1,dmc=0").unwrap();
+    assert_eq!(values, [(Channel::Triangle, 0.5), (Channel::Pulse0, -1.0), (Channel::Dmc, 0.0)]);
+    assert!(MixSettings::parse_channel_values("bass=1").is_err());
+    assert!(MixSettings::parse_channel_values("noise").is_err());
+  }
+}
@@ -0,0 +1,202 @@
+use crate::apu::sequencer::Sequencer;
+
+/// Delta modulation channel, plays 1-bit delta encoded samples fetched from CPU memory by DMA
+pub struct Dmc {
+  sequencer: Sequencer,
+  // Timer periods in CPU cycles
+  rates: [u16; 16],
+  is_irq_enabled: bool,
+  pub irq_flag: bool,
+  is_loop: bool,
+  output_level: u8,
+  sample_address: u16,
+  sample_length: u16,
+  // Memory reader
+  current_address: u16,
+  bytes_remaining: u16,
+  sample_buffer: Option<u8>,
+  // Output unit
+  shift_register: u8,
+  bits_remaining: u8,
+  is_silenced: bool,
+}
+
+impl Dmc {
+  pub fn new(rates: [u16; 16]) -> Dmc {
+    let mut sequencer = Sequencer::new(1);
+    sequencer.period = rates[0] - 1;
+
+    Dmc {
+      sequencer,
+      rates,
+      is_irq_enabled: false,
+      irq_flag: false,
+      is_loop: false,
+      output_level: 0,
+      sample_address: 0xC000,
+      sample_length: 1,
+      current_address: 0xC000,
+      bytes_remaining: 0,
+      sample_buffer: None,
+      shift_register: 0,
+      bits_remaining: 8,
+      is_silenced: true,
+    }
+  }
+
+  pub fn dmc_write_reg_u8(&mut self, address: u16, data: u8) {
+    match address {
+      0x4010 => {
+        self.is_irq_enabled = data & 0x80 > 0;
+        if !self.is_irq_enabled {
+          self.irq_flag = false;
+        }
+        self.is_loop = data & 0x40 > 0;
+        // The sequencer reloads with period + 1 cycles
+        self.sequencer.period = self.rates[usize::from(data & 0x0F)] - 1;
+      }
+      0x4011 => self.output_level = data & 0x7F,
+      0x4012 => self.sample_address = 0xC000 | (u16::from(data) << 6),
+      0x4013 => self.sample_length = (u16::from(data) << 4) | 0x01,
+      _ => panic!("Invalid dmc_write_reg_u8 address 0x{:04X}", address),
+    }
+  }
+
+  pub fn sample(&self) -> u8 {
+    self.output_level
+  }
+
+  /// Clocked every CPU cycle, the rates are in CPU cycles too
+  pub fn step_sequencer(&mut self) {
+    if !self.sequencer.step(true) {
+      return;
+    }
+
+    if !self.is_silenced {
+      if self.shift_register & 0x01 > 0 {
+        if self.output_level <= 125 {
+          self.output_level += 2;
+        }
+      } else if self.output_level >= 2 {
+        self.output_level -= 2;
+      }
+    }
+    self.shift_register >>= 1;
+
+    self.bits_remaining -= 1;
+    if self.bits_remaining == 0 {
+      self.bits_remaining = 8;
+      match self.sample_buffer.take() {
+        Some(data) => {
+          self.is_silenced = false;
+          self.shift_register = data;
+        }
+        None => self.is_silenced = true,
+      }
+    }
+  }
+
+  /// Address the memory reader wants to fetch, the sample buffer is empty and the sample is not over yet
+  pub fn dma_address(&self) -> Option<u16> {
+    if self.sample_buffer.is_none() && self.bytes_remaining > 0 {
+      Some(self.current_address)
+    } else {
+      None
+    }
+  }
+
+  /// Completes the DMA started for `dma_address`
+  pub fn load_sample_byte(&mut self, data: u8) {
+    self.sample_buffer = Some(data);
+    // The address wraps around to 0x8000, not 0x0000
+    self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+    self.bytes_remaining -= 1;
+
+    if self.bytes_remaining == 0 {
+      if self.is_loop {
+        self.restart();
+      } else if self.is_irq_enabled {
+        self.irq_flag = true;
+      }
+    }
+  }
+
+  fn restart(&mut self) {
+    self.current_address = self.sample_address;
+    self.bytes_remaining = self.sample_length;
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.bytes_remaining > 0
+  }
+
+  /// Writes to 0x4015 stop the sample or restart it when it is over, and always acknowledge the IRQ
+  pub fn set_enabled(&mut self, value: bool) {
+    self.irq_flag = false;
+    if !value {
+      self.bytes_remaining = 0;
+    } else if self.bytes_remaining == 0 {
+      self.restart();
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::apu::dmc::Dmc;
+  use crate::nes::region::Region;
+
+  fn play_sample(dmc: &mut Dmc, memory: &[u8]) -> Vec<u8> {
+    let mut fetches = Vec::new();
+    for _ in 0..54 * 8 * 20 {
+      dmc.step_sequencer();
+      if let Some(address) = dmc.dma_address() {
+        fetches.push(address);
+        dmc.load_sample_byte(memory[usize::from(address - 0xC000)]);
+      }
+    }
+    fetches.iter().map(|address| (address - 0xC000) as u8).collect()
+  }
+
+  #[test]
+  fn sample_playback_and_irq() {
+    let mut dmc = Dmc::new(Region::Ntsc.dmc_rates());
+    dmc.dmc_write_reg_u8(0x4010, 0x8F);
+    dmc.dmc_write_reg_u8(0x4011, 0x41);
+    dmc.dmc_write_reg_u8(0x4012, 0x00);
+    dmc.dmc_write_reg_u8(0x4013, 0x01);
+    dmc.set_enabled(true);
+    assert!(dmc.is_playing());
+
+    let fetches = play_sample(&mut dmc, &[0xFF; 0x11]);
+    assert_eq!(fetches, (0..0x11).collect::<Vec<u8>>());
+    assert!(!dmc.is_playing());
+    assert!(dmc.irq_flag);
+    // Every bit raises the level until it saturates
+    assert_eq!(dmc.sample(), 0x7F);
+
+    dmc.set_enabled(true);
+    assert!(!dmc.irq_flag);
+    assert!(dmc.is_playing());
+  }
+
+  #[test]
+  fn looping_sample() {
+    let mut dmc = Dmc::new(Region::Pal.dmc_rates());
+    dmc.dmc_write_reg_u8(0x4010, 0xCF);
+    dmc.dmc_write_reg_u8(0x4011, 0x40);
+    dmc.dmc_write_reg_u8(0x4013, 0x00);
+    dmc.set_enabled(true);
+
+    let fetches = play_sample(&mut dmc, &[0x00]);
+    assert!(fetches.len() > 1);
+    assert!(fetches.iter().all(|&address| address == 0));
+    assert!(dmc.is_playing());
+    assert!(!dmc.irq_flag);
+    assert_eq!(dmc.sample(), 0);
+
+    dmc.set_enabled(false);
+    assert!(!dmc.is_playing());
+    assert_eq!(dmc.dma_address(), None);
+  }
+}
@@ -7,6 +7,7 @@ bitfield! {
   pub loop_flag,          _: 5, 5;
 }
 
+#[derive(Clone)]
 pub struct Envelope {
   ctrl: EnvelopeCtrl,
   length_counter: u8,
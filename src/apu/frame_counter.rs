@@ -1,3 +1,5 @@
+use crate::nes::region::Region;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum Mode {
   Zero,
@@ -11,76 +13,115 @@ pub enum FrameResult {
   Half,
 }
 
+// CPU cycles after a write to 0x4017 before the sequence restarts, on even and odd cycles
+const WRITE_DELAY: [u8; 2] = [3, 4];
+// Cycles after a quarter or half frame during which the restart of the 5-step sequence does not clock again
+const BLOCK_TICK_CYCLES: u8 = 2;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub struct FrameCounter {
-  pub counter: i32,
-  pub cycles: u32,
+  // CPU cycles since the sequence started
+  counter: u32,
   pub irq_enabled: bool,
   pub public_irq_flag: bool,
   pub private_irq_flag: bool,
   mode: Mode,
+  steps: [u32; 5],
+  // Mode written to 0x4017 and the cycles left until it takes effect
+  pending_write: Option<(Mode, u8)>,
+  block_tick: u8,
 }
 
 impl FrameCounter {
-  pub fn new() -> Self {
+  pub fn new(region: Region) -> Self {
     FrameCounter {
       counter: 0,
-      cycles: 0,
       irq_enabled: true,
       public_irq_flag: false,
       private_irq_flag: false,
       mode: Mode::Zero,
+      steps: region.frame_counter_steps(),
+      pending_write: None,
+      block_tick: 0,
     }
   }
 
-  pub fn write_register(&mut self, value: u8, cycles: u32) -> FrameResult {
+  /// Console reset, as if the last mode was written again with the IRQ allowed
+  pub fn reset(&mut self) {
+    self.irq_enabled = true;
+    self.public_irq_flag = false;
+    self.private_irq_flag = false;
+    self.counter = 0;
+    self.block_tick = 0;
+    self.pending_write = Some((self.mode, WRITE_DELAY[0]));
+  }
+
+  /// The IRQ inhibit flag applies at once, the sequence restarts 3 or 4 cycles later depending on the jitter of
+  /// the write
+  pub fn write_register(&mut self, value: u8, cycles: u32) {
     self.irq_enabled = value & 0x40 == 0;
     if !self.irq_enabled {
       self.public_irq_flag = false;
       self.private_irq_flag = false;
     }
 
-    self.mode = if value & 0x80 == 0 {
+    let mode = if value & 0x80 == 0 {
       Mode::Zero
     } else {
       Mode::One
     };
-
-    self.counter = if cycles % 2 == 0 { 0 } else { -1 };
-
-    match self.mode {
-      Mode::Zero => FrameResult::None,
-      Mode::One => FrameResult::Half,
-    }
+    self.pending_write = Some((mode, WRITE_DELAY[(cycles % 2) as usize]));
   }
 
   pub fn step(&mut self) -> FrameResult {
-    let result = match self.mode {
+    self.counter += 1;
+    let mut result = match self.mode {
       Mode::Zero => self.tick_mode_zero(),
       Mode::One => self.tick_mode_one(),
     };
-    self.counter = self.counter.wrapping_add(1);
+    if result != FrameResult::None {
+      self.block_tick = BLOCK_TICK_CYCLES;
+    }
+
+    if let Some((mode, delay)) = self.pending_write {
+      if delay > 1 {
+        self.pending_write = Some((mode, delay - 1));
+      } else {
+        self.pending_write = None;
+        self.mode = mode;
+        self.counter = 0;
+        // The 5-step sequence clocks the units right away, unless they were just clocked
+        if mode == Mode::One && self.block_tick == 0 {
+          result = FrameResult::Half;
+          self.block_tick = BLOCK_TICK_CYCLES;
+        }
+      }
+    }
+
+    self.block_tick = self.block_tick.saturating_sub(1);
     result
   }
 
+  // The IRQ flag is raised on three cycles in a row, so a read of 0x4015 during them does not clear it for good
   fn tick_mode_zero(&mut self) -> FrameResult {
+    let [quarter, half, three_quarters, last, _] = self.steps;
     match self.counter {
-      0x1D23 => FrameResult::Quarter,
-      0x3A43 => FrameResult::Half,
-      0x5765 => FrameResult::Quarter,
-      0x7486 => {
+      c if c == quarter => FrameResult::Quarter,
+      c if c == half => FrameResult::Half,
+      c if c == three_quarters => FrameResult::Quarter,
+      c if c == last - 1 => {
         self.trigger_irq();
         FrameResult::None
       }
-      0x7487 => {
+      c if c == last => {
         self.trigger_irq();
         self.publish_irq();
         FrameResult::Half
       }
-      0x7488 => {
+      c if c == last + 1 => {
         self.trigger_irq();
         self.publish_irq();
-        self.counter = 2;
+        self.counter = 0;
         FrameResult::None
       }
       _ => FrameResult::None,
@@ -88,13 +129,15 @@ impl FrameCounter {
   }
 
   fn tick_mode_one(&mut self) -> FrameResult {
+    let [quarter, half, three_quarters, _, last] = self.steps;
     match self.counter {
-      0x1D23 => FrameResult::Quarter,
-      0x3A43 => FrameResult::Half,
-      0x5765 => FrameResult::Quarter,
-      0x91A3 => {
-        self.counter = 1;
-        FrameResult::Half
+      c if c == quarter => FrameResult::Quarter,
+      c if c == half => FrameResult::Half,
+      c if c == three_quarters => FrameResult::Quarter,
+      c if c == last => FrameResult::Half,
+      c if c == last + 1 => {
+        self.counter = 0;
+        FrameResult::None
       }
       _ => FrameResult::None,
     }
@@ -109,3 +152,80 @@ impl FrameCounter {
     self.public_irq_flag = self.private_irq_flag;
   }
 }
+
+#[cfg(test)]
+mod test {
+  use crate::apu::frame_counter::{FrameCounter, FrameResult};
+  use crate::nes::region::Region;
+
+  // Cycles after a write on cycle `write_cycle` at which the frame counter clocks something or sets its IRQ flag
+  fn events(value: u8, write_cycle: u32, cycles: u32) -> Vec<(u32, FrameResult)> {
+    let mut frame_counter = FrameCounter::new(Region::Ntsc);
+    frame_counter.write_register(value, write_cycle);
+    (1..=cycles).filter_map(|cycle| {
+      let was_set = frame_counter.private_irq_flag;
+      let result = frame_counter.step();
+      if result != FrameResult::None {
+        Some((cycle, result))
+      } else if frame_counter.private_irq_flag && !was_set {
+        Some((cycle, FrameResult::None))
+      } else {
+        None
+      }
+    }).collect()
+  }
+
+  #[test]
+  fn four_step_sequence() {
+    assert_eq!(events(0x00, 0, 29_840), [
+      (7_460, FrameResult::Quarter),
+      (14_916, FrameResult::Half),
+      (22_374, FrameResult::Quarter),
+      (29_831, FrameResult::None),
+      (29_832, FrameResult::Half),
+    ]);
+    // A write on an odd cycle restarts the sequence one cycle later
+    assert_eq!(events(0x00, 1, 7_461), [(7_461, FrameResult::Quarter)]);
+    // The next sequence follows 29830 cycles later
+    assert_eq!(events(0x00, 0, 29_833 + 7_457)[5], (29_833 + 7_457, FrameResult::Quarter));
+  }
+
+  #[test]
+  fn five_step_sequence() {
+    assert_eq!(events(0x80, 0, 37_290), [
+      (3, FrameResult::Half),
+      (7_460, FrameResult::Quarter),
+      (14_916, FrameResult::Half),
+      (22_374, FrameResult::Quarter),
+      (37_284, FrameResult::Half),
+    ]);
+  }
+
+  #[test]
+  fn irq_flag() {
+    let mut frame_counter = FrameCounter::new(Region::Ntsc);
+    frame_counter.write_register(0x00, 0);
+    for _ in 0..29_831 {
+      frame_counter.step();
+    }
+    assert!(frame_counter.private_irq_flag);
+    assert!(!frame_counter.public_irq_flag);
+    frame_counter.step();
+    assert!(frame_counter.public_irq_flag);
+
+    // Inhibiting the IRQ clears the flag at once
+    frame_counter.write_register(0x40, 0);
+    assert!(!frame_counter.private_irq_flag && !frame_counter.public_irq_flag);
+    for _ in 0..40_000 {
+      frame_counter.step();
+    }
+    assert!(!frame_counter.private_irq_flag);
+
+    // Reset restores the 4-step mode last written with the IRQ allowed
+    frame_counter.reset();
+    for _ in 0..29_831 {
+      frame_counter.step();
+    }
+    assert!(frame_counter.private_irq_flag);
+  }
+}
@@ -1,16 +1,18 @@
-
 const LENGTH_TABLE: [u8; 32] = [
   0xA, 0xFE, 0x14, 0x02, 0x28, 0x04, 0x50, 0x06, 0xA0, 0x08, 0x3C, 0xA, 0x0E, 0x0C, 0x1A, 0xE,
   0xC, 0x10, 0x18, 0x12, 0x30, 0x14, 0x60, 0x16, 0xC0, 0x18, 0x48, 0x1A, 0x10, 0x1C, 0x20, 0x1E
 ];
 
 
+/// Register writes come after the frame counter in a cycle, so a halt flag written while the counter is clocked
+/// only applies from the next clock, and a reload written then is lost if the clock decremented the counter
+#[derive(Clone)]
 pub struct LengthCounter {
   pub is_enabled: bool,
   is_halt: bool,
   frame_counter: u8,
-  is_pending: Option<bool>,
-  is_pending_reg: Option<u8>,
+  // The counter was decremented in the current cycle
+  is_clocked: bool,
 }
 
 impl LengthCounter {
@@ -19,17 +21,18 @@ impl LengthCounter {
       is_enabled: false,
       is_halt: false,
       frame_counter: 0,
-      is_pending: None,
-      is_pending_reg: None,
+      is_clocked: false,
     }
   }
 
   pub fn write_register(&mut self, val: u8) {
-    self.is_pending_reg = Some(val);
+    if self.is_enabled && !self.is_clocked {
+      self.frame_counter = LENGTH_TABLE[usize::from(val >> 3)];
+    }
   }
 
   pub fn set_halted(&mut self, val: bool) {
-    self.is_pending = Some(val);
+    self.is_halt = val;
   }
 
   pub fn set_enabled(&mut self, val: bool) {
@@ -40,30 +43,15 @@ impl LengthCounter {
   }
 
   pub fn step(&mut self) {
-    if self.is_pending.is_some() {
-      if self.frame_counter == 0 {
-        return;
-      } else {
-        self.is_pending = None;
-      }
-    }
-    if self.is_enabled && !self.is_halt && self.frame_counter > 0 {
-      self.frame_counter = self.frame_counter.wrapping_sub(1);
+    if !self.is_halt && self.frame_counter > 0 {
+      self.frame_counter -= 1;
+      self.is_clocked = true;
     }
   }
 
-  pub fn update_pending(&mut self) {
-    if let Some(v) = self.is_pending {
-      self.is_halt = v;
-      self.is_pending = None;
-    }
-
-    if let Some(val) = self.is_pending_reg {
-      if self.is_enabled {
-        self.frame_counter = LENGTH_TABLE[usize::from(val >> 3)];
-      }
-      self.is_pending_reg = None;
-    }
+  /// Called at the start of every CPU cycle, before the frame counter
+  pub fn start_cycle(&mut self) {
+    self.is_clocked = false;
   }
 
   pub fn active(&self) -> bool {
@@ -74,3 +62,56 @@ impl LengthCounter {
     self.frame_counter > 0
   }
 }
+
+#[cfg(test)]
+mod test {
+  use crate::apu::length_counter::LengthCounter;
+
+  fn loaded_counter() -> LengthCounter {
+    let mut length_counter = LengthCounter::new();
+    length_counter.set_enabled(true);
+    length_counter.start_cycle();
+    // Length 2
+    length_counter.write_register(0x18);
+    length_counter
+  }
+
+  #[test]
+  fn reload_during_clock() {
+    // Lost when the clock decremented the counter in the same cycle
+    let mut length_counter = loaded_counter();
+    length_counter.step();
+    length_counter.write_register(0xF8);
+    length_counter.start_cycle();
+    length_counter.step();
+    assert!(!length_counter.playing());
+
+    // Applied when the counter was already zero
+    length_counter.start_cycle();
+    length_counter.step();
+    length_counter.write_register(0x18);
+    assert!(length_counter.playing());
+
+    // Ignored while the channel is disabled
+    length_counter.set_enabled(false);
+    length_counter.start_cycle();
+    length_counter.write_register(0x18);
+    assert!(!length_counter.playing());
+  }
+
+  #[test]
+  fn halt_during_clock() {
+    // The clock of the cycle sees the old halt flag
+    let mut length_counter = loaded_counter();
+    length_counter.step();
+    length_counter.set_halted(true);
+    length_counter.start_cycle();
+    length_counter.step();
+    assert!(length_counter.playing());
+
+    length_counter.set_halted(false);
+    length_counter.start_cycle();
+    length_counter.step();
+    assert!(!length_counter.playing());
+  }
+}
@@ -0,0 +1,121 @@
+use crate::apu::channel_mix::{Channel, MixSettings};
+
+/// Nonlinear DAC of the APU, approximated with the nesdev lookup tables
+pub struct Mixer {
+  pulse_table: [f64; 31],
+  tnd_table: [f64; 203],
+}
+
+impl Mixer {
+  pub fn new() -> Mixer {
+    let mut pulse_table = [0.0; 31];
+    for (n, value) in pulse_table.iter_mut().enumerate().skip(1) {
+      *value = 95.52 / (8128.0 / n as f64 + 100.0);
+    }
+
+    let mut tnd_table = [0.0; 203];
+    for (n, value) in tnd_table.iter_mut().enumerate().skip(1) {
+      *value = 163.67 / (24329.0 / n as f64 + 100.0);
+    }
+
+    Mixer {
+      pulse_table,
+      tnd_table,
+    }
+  }
+
+  /// Output level between 0.0 and 1.0 for the raw channel outputs. Expansion audio, in units of a full volume
+  /// pulse channel, is added linearly as the cartridge port does.
+  pub fn mix(&self, pulse_0: u8, pulse_1: u8, triangle: u8, noise: u8, dmc: u8, expansion: f64) -> f64 {
+    let pulse = self.pulse_table[usize::from(pulse_0 + pulse_1)];
+    let tnd = self.tnd_table[3 * usize::from(triangle) + 2 * usize::from(noise) + usize::from(dmc)];
+    pulse + tnd + expansion * self.pulse_table[15]
+  }
+
+  /// Left and right output levels with the listening settings applied. The channels that are heard go through the
+  /// nonlinear DAC together, then each side gets the share of the channels weighted by their gains on that side.
+  pub fn mix_channels(&self, outputs: [u8; 5], expansion: f64, settings: &MixSettings) -> [f64; 2] {
+    let [pulse_0, pulse_1, triangle, noise, dmc] = outputs;
+    if settings.is_default() {
+      let level = self.mix(pulse_0, pulse_1, triangle, noise, dmc, expansion);
+      return [level, level];
+    }
+
+    let gains = Channel::ALL.map(|channel| settings.gains(channel));
+    let heard = |idx: usize, output: u8| if gains[idx] == [0.0, 0.0] { 0 } else { output };
+    let outputs = [
+      heard(0, pulse_0),
+      heard(1, pulse_1),
+      heard(2, triangle),
+      heard(3, noise),
+      heard(4, dmc),
+    ];
+    let expansion = if gains[5] == [0.0, 0.0] { 0.0 } else { expansion };
+    let total = self.mix(outputs[0], outputs[1], outputs[2], outputs[3], outputs[4], expansion);
+
+    // Level of each channel playing alone
+    let levels = [
+      self.pulse_table[usize::from(outputs[0])],
+      self.pulse_table[usize::from(outputs[1])],
+      self.tnd_table[3 * usize::from(outputs[2])],
+      self.tnd_table[2 * usize::from(outputs[3])],
+      self.tnd_table[usize::from(outputs[4])],
+      expansion * self.pulse_table[15],
+    ];
+    let sum: f64 = levels.iter().sum();
+    if sum == 0.0 {
+      return [0.0, 0.0];
+    }
+    let side = |side: usize| total * levels.iter().zip(gains).map(|(level, gain)| level * gain[side]).sum::<f64>() / sum;
+    [side(0), side(1)]
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::apu::channel_mix::{Channel, MixSettings};
+  use crate::apu::mixer::Mixer;
+
+  #[test]
+  fn lookup_table_levels() {
+    let mixer = Mixer::new();
+    assert_eq!(mixer.mix(0, 0, 0, 0, 0, 0.0), 0.0);
+    assert!((mixer.mix(15, 15, 0, 0, 0, 0.0) - 0.2575).abs() < 0.0005);
+    assert!((mixer.mix(0, 0, 15, 15, 127, 0.0) - 0.7425).abs() < 0.0005);
+    // The DAC is nonlinear, two channels together are quieter than the sum of each one alone
+    assert!(mixer.mix(15, 15, 0, 0, 0, 0.0) < 2.0 * mixer.mix(15, 0, 0, 0, 0, 0.0));
+  }
+
+  #[test]
+  fn expansion_audio_level() {
+    let mixer = Mixer::new();
+    assert_eq!(mixer.mix(0, 0, 0, 0, 0, 1.0), mixer.mix(15, 0, 0, 0, 0, 0.0));
+    assert_eq!(mixer.mix(0, 0, 15, 0, 0, 0.5), mixer.mix(0, 0, 15, 0, 0, 0.0) + mixer.mix(15, 0, 0, 0, 0, 0.0) / 2.0);
+  }
+
+  #[test]
+  fn channel_settings() {
+    let mixer = Mixer::new();
+    let outputs = [15, 8, 10, 4, 64];
+    let mut settings = MixSettings::default();
+    let level = mixer.mix(15, 8, 10, 4, 64, 0.5);
+    assert_eq!(mixer.mix_channels(outputs, 0.5, &settings), [level, level]);
+
+    // A muted channel does not reach the DAC at all
+    settings.set_muted(Channel::Pulse0, true);
+    settings.set_muted(Channel::Expansion, true);
+    let level = mixer.mix(0, 8, 10, 4, 64, 0.0);
+    let [left, right] = mixer.mix_channels(outputs, 0.5, &settings);
+    assert!((left - level).abs() < 1e-12 && (right - level).abs() < 1e-12);
+
+    settings.set_solo(Some(Channel::Triangle));
+    let level = mixer.mix(0, 0, 10, 0, 0, 0.0);
+    let [left, right] = mixer.mix_channels(outputs, 0.5, &settings);
+    assert!((left - level).abs() < 1e-12 && (right - level).abs() < 1e-12);
+
+    settings.set_volume(Channel::Triangle, 0.5);
+    settings.set_pan(Channel::Triangle, 1.0);
+    let [left, right] = mixer.mix_channels(outputs, 0.5, &settings);
+    assert!(left == 0.0 && (right - level / 2.0).abs() < 1e-12);
+  }
+}
@@ -1,60 +1,160 @@
 use crate::apu::{pulse::Pulse, sweep::Mode};
+use crate::apu::blip_buf::BlipBuf;
+use crate::apu::channel_mix::MixSettings;
+use crate::apu::dmc::Dmc;
 use crate::apu::frame_counter::{FrameCounter, FrameResult};
+use crate::apu::mixer::Mixer;
+use crate::apu::noise::Noise;
 use crate::apu::signal_filter::SignalFilter;
 use crate::apu::triangle::Triangle;
 
-use crate::apu::audio_stream::AudioStream;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 
-pub mod audio_stream;
+use crate::apu::audio_sink::AudioSink;
+use crate::nes::region::Region;
+use crate::recorder::vgm::VgmWriter;
+
+pub mod audio_sink;
+mod audio_stream;
+mod blip_buf;
+pub mod channel_mix;
+mod dmc;
 mod envelope;
 mod signal_filter;
 mod frame_counter;
 mod length_counter;
-mod pulse;
+mod mixer;
+mod noise;
+pub(crate) mod pulse;
+mod ring_buffer;
 mod sequencer;
 mod sweep;
 mod triangle;
 
 pub struct Apu {
-  audio_stream: AudioStream,
-  buf: Vec<i16>,
-  filters: [SignalFilter; 3],
+  audio_sink: Box<dyn AudioSink>,
+  clock_rate: u32,
+  sample_rate: u32,
+  recorded_samples: Option<Vec<i16>>,
+  vgm_writer: Option<VgmWriter<BufWriter<File>>>,
+  mixer: Mixer,
+  mix_settings: MixSettings,
+  // One output for mono, left and right once a channel is panned
+  outputs: Vec<Output>,
+  // CPU cycles since the start of the current synthesis frame
+  blip_time: u32,
   pub pulse_0: Pulse,
   pub pulse_1: Pulse,
   frame_counter: FrameCounter,
-  pub triangle: Triangle
+  pub triangle: Triangle,
+  pub noise: Noise,
+  pub dmc: Dmc,
+}
+
+// Band-limited synthesis and console filters of one side of the output
+#[derive(Clone)]
+struct Output {
+  blip_buf: BlipBuf,
+  amplitude: f64,
+  filters: [SignalFilter; 3],
+}
+
+impl Output {
+  fn new(clock_rate: u32, sample_rate: u32) -> Output {
+    Output {
+      blip_buf: BlipBuf::new(clock_rate, sample_rate, BLIP_FRAME_CYCLES),
+      amplitude: 0.0,
+      filters: [
+        SignalFilter::hi_pass(f64::from(sample_rate), 90.0),
+        SignalFilter::hi_pass(f64::from(sample_rate), 440.0),
+        SignalFilter::lo_pass(f64::from(sample_rate), 14_000.0),
+      ],
+    }
+  }
+
+  fn set_amplitude(&mut self, time: u32, amplitude: f64) {
+    if amplitude != self.amplitude {
+      self.blip_buf.add_delta(time, amplitude - self.amplitude);
+      self.amplitude = amplitude;
+    }
+  }
+
+  fn end_frame(&mut self, clocks: u32) -> Vec<i16> {
+    self.blip_buf.end_frame(clocks);
+    let mut samples = Vec::with_capacity(self.blip_buf.samples_avail());
+    self.blip_buf.read_samples(&mut samples);
+    samples.into_iter().map(|sample| self.filter(sample)).collect()
+  }
+
+  /// Runs the output rate filters of the console on a band-limited sample
+  fn filter(&mut self, sample: f64) -> i16 {
+    let mut output = sample * 65535.0;
+
+    for i in 0..3 {
+      output = self.filters[i].step(output);
+    }
+
+    output as i16
+  }
 }
 
-const AUDIO_BUFFER_LIMIT: usize = 1470;
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+// CPU cycles synthesized before the output samples are read, about 2 ms
+const BLIP_FRAME_CYCLES: u32 = 4096;
+// Largest change of the resampling ratio made to keep the playback queue half full
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+// CPU cycles between the frame counter restart on reset and the 8 cycles of the CPU reset sequence
+const RESET_RESTART_LEAD: u32 = 2;
 
 impl Apu {
-  pub fn new() -> Apu {
-    let audio_stream = AudioStream::new();
+  pub fn new(region: Region, sample_rate: u32, audio_sink: Box<dyn AudioSink>) -> Apu {
+    let clock_rate = region.cpu_clock_rate();
 
     Apu {
-      audio_stream,
-      buf: Vec::new(),
-      frame_counter: FrameCounter::new(),
+      audio_sink,
+      clock_rate,
+      sample_rate,
+      recorded_samples: None,
+      vgm_writer: None,
+      mixer: Mixer::new(),
+      mix_settings: MixSettings::default(),
+      outputs: vec![Output::new(clock_rate, sample_rate)],
+      blip_time: 0,
+      frame_counter: FrameCounter::new(region),
       pulse_0: Pulse::new(Mode::OnesComplement),
       pulse_1: Pulse::new(Mode::TwosComplement),
       triangle: Triangle::new(),
-      filters: [
-        SignalFilter::hi_pass(44100.0, 90.0),
-        SignalFilter::hi_pass(44100.0, 440.0),
-        SignalFilter::lo_pass(44100.0, 14_000.0),
-      ],
+      noise: Noise::new(region.noise_periods()),
+      dmc: Dmc::new(region.dmc_rates()),
     }
   }
 
+  /// Silences the channels and restarts the frame counter in the mode last written, as the reset button does. The
+  /// restart is an even cycle write to 0x4017 two cycles ahead of the CPU reset sequence, so the first instruction
+  /// runs 10 cycles after it, within the 9 to 12 of the console.
   pub fn reset(&mut self) {
-    self.apu_write_reg(0x4017, 0, 0);
-    for idx in 0..=0x0A {
-      self.step(idx);
+    self.apu_write_reg(0x4015, 0, 0);
+    self.frame_counter.reset();
+    for cycle in 0..RESET_RESTART_LEAD {
+      self.step(cycle, 0.0);
     }
   }
 
-  pub fn step(&mut self, cycle: u32) {
+  /// `expansion_audio` is the output of the cartridge sound chip during this cycle, see `Mapper::audio_output`
+  pub fn step(&mut self, cycle: u32, expansion_audio: f64) {
+    if let Some(vgm_writer) = self.vgm_writer.as_mut() {
+      vgm_writer.clock();
+    }
+    self.pulse_0.start_cycle();
+    self.pulse_1.start_cycle();
+    self.triangle.start_cycle();
+    self.noise.start_cycle();
+
     self.triangle.step_sequencer();
+    self.noise.step_sequencer();
+    self.dmc.step_sequencer();
     if cycle % 2 == 1 {
       self.pulse_0.step_sequencer();
       self.pulse_1.step_sequencer();
@@ -63,27 +163,137 @@ impl Apu {
     let frame_res = self.frame_counter.step();
     self.handle_frame_result(frame_res);
 
-    self.pulse_0.update_length_counter();
-    self.pulse_1.update_length_counter();
-    self.triangle.update_length_counter();
+    let amplitudes = self.mix(expansion_audio);
+    for (output, amplitude) in self.outputs.iter_mut().zip(amplitudes) {
+      output.set_amplitude(self.blip_time, amplitude);
+    }
+    self.blip_time += 1;
+    if self.blip_time == BLIP_FRAME_CYCLES {
+      self.end_blip_frame();
+    }
+  }
+
+  /// Rate of the samples generated by `step`
+  pub fn sample_rate(&self) -> u32 {
+    self.sample_rate
+  }
+
+  pub fn mix_settings(&self) -> &MixSettings {
+    &self.mix_settings
+  }
+
+  /// Changes what is heard of each channel, the channels themselves keep running as programmed
+  pub fn set_mix_settings(&mut self, mix_settings: MixSettings) {
+    if mix_settings.is_stereo() && self.outputs.len() == 1 {
+      // The right side starts from the state of the mono output, so the switch does not click
+      self.outputs.push(self.outputs[0].clone());
+    } else if !mix_settings.is_stereo() {
+      self.outputs.truncate(1);
+    }
+    self.mix_settings = mix_settings;
+  }
+
+  // Left and right levels, mono output only uses the left one
+  fn mix(&self, expansion_audio: f64) -> [f64; 2] {
+    let outputs = [
+      self.pulse_0.sample(),
+      self.pulse_1.sample(),
+      self.triangle.sample(),
+      self.noise.sample(),
+      self.dmc.sample(),
+    ];
+    self.mixer.mix_channels(outputs, expansion_audio, &self.mix_settings)
+  }
+
+  fn end_blip_frame(&mut self) {
+    let clocks = self.blip_time;
+    self.blip_time = 0;
+
+    let sides: Vec<Vec<i16>> = self.outputs.iter_mut().map(|output| output.end_frame(clocks)).collect();
+    let (left, right) = (&sides[0], sides.last().unwrap());
+    let mut output = Vec::with_capacity(left.len() * 2);
+    for (&left, &right) in left.iter().zip(right) {
+      output.push(left);
+      output.push(right);
+    }
+    // Recording keeps every sample, the playback queue drops them when the emulator runs too fast
+    if let Some(recorded) = self.recorded_samples.as_mut() {
+      recorded.extend_from_slice(&output);
+    }
+    self.audio_sink.push_samples(&output);
+
+    self.update_resampling_ratio();
+  }
 
-    if cycle % 40 == 0 && self.buf.len() < AUDIO_BUFFER_LIMIT {
-      let sample = self.sample();
-      self.buf.push(sample);
-      self.buf.push(sample);
+  /// Dynamic rate control, the device and the emulator clocks drift apart, so the output rate is nudged to keep
+  /// the playback queue half full. Recordings keep the exact rate.
+  fn update_resampling_ratio(&mut self) {
+    let ratio = match self.audio_sink.fill_level() {
+      Some(fill_level) if self.recorded_samples.is_none() => 1.0 + MAX_RATE_ADJUSTMENT * (1.0 - 2.0 * fill_level),
+      _ => 1.0,
+    };
+    for output in self.outputs.iter_mut() {
+      output.blip_buf.set_rates(self.clock_rate, f64::from(self.sample_rate) * ratio);
     }
   }
 
-  pub fn flush_samples(&mut self) {
-    self.audio_stream.send_audio_buffer(self.buf.to_vec());
-    self.buf.clear();
+  /// Waits for the audio device to play the queued samples, returns false when the sink does not pace the emulation
+  pub fn wait_for_playback(&self) -> bool {
+    self.audio_sink.wait_for_playback()
+  }
+
+  /// Completes the output of the sink and the VGM log, like the header of a WAV file
+  pub fn finish_audio(&mut self) -> std::io::Result<()> {
+    if let Some(vgm_writer) = self.vgm_writer.as_mut() {
+      vgm_writer.finish()?;
+    }
+    self.audio_sink.finish()
+  }
+
+  /// Logs the register writes from now on to a VGM file
+  pub fn start_vgm_log(&mut self, path: &Path) -> std::io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    self.vgm_writer = Some(VgmWriter::new(file, self.clock_rate)?);
+    Ok(())
+  }
+
+  /// Sends DMC sample bytes read from `address` to the VGM log
+  pub fn log_dmc_sample(&mut self, address: u16, bytes: &[u8]) {
+    if let Some(vgm_writer) = self.vgm_writer.as_mut() {
+      vgm_writer.write_sample(address, bytes).expect("VGM file write error");
+    }
+  }
+
+  /// Logs a write to the sound registers of an expansion chip, only FDS ones have a VGM equivalent
+  pub fn log_expansion_write(&mut self, address: u16, data: u8) {
+    if let Some(vgm_writer) = self.vgm_writer.as_mut() {
+      vgm_writer.write_register(address, data).expect("VGM file write error");
+    }
+  }
+
+  pub fn set_recording(&mut self, is_recording: bool) {
+    self.recorded_samples = if is_recording { Some(Vec::new()) } else { None };
+  }
+
+  /// Interleaved stereo samples generated since the previous call
+  pub fn take_recorded_samples(&mut self) -> Vec<i16> {
+    self.recorded_samples.as_mut().map(std::mem::take).unwrap_or_default()
   }
 
   pub fn apu_read_reg(&mut self) -> u8 {
     let mut res = 0;
+    if self.dmc.irq_flag {
+      res |= 0x80;
+    }
     if self.frame_counter.private_irq_flag {
       res |= 0x40;
     }
+    if self.dmc.is_playing() {
+      res |= 0x10;
+    }
+    if self.noise.is_playing() {
+      res |= 0x08;
+    }
     if self.triangle.is_playing() {
       res |= 0x04;
     }
@@ -99,19 +309,24 @@ impl Apu {
   }
 
   pub fn apu_write_reg(&mut self, address: u16, data: u8, cycle: u32) {
+    if let Some(vgm_writer) = self.vgm_writer.as_mut() {
+      vgm_writer.write_register(address, data).expect("VGM file write error");
+    }
     match address {
       0x4000..=0x4003 => self.pulse_0.pulse_write_reg_u8(address, data),
       0x4004..=0x4007 => self.pulse_1.pulse_write_reg_u8(address, data),
       0x4008..=0x400B => self.triangle.triangle_write_reg_u8(address, data),
-      0x400C..=0x4013 => (),
+      0x400C..=0x400F => self.noise.noise_write_reg_u8(address, data),
+      0x4010..=0x4013 => self.dmc.dmc_write_reg_u8(address, data),
       0x4015 => {
         self.pulse_0.set_enabled(data & 0x01 > 0);
         self.pulse_1.set_enabled(data & 0x02 > 0);
         self.triangle.set_enabled(data & 0x04 > 0);
+        self.noise.set_enabled(data & 0x08 > 0);
+        self.dmc.set_enabled(data & 0x10 > 0);
       }
       0x4017 => {
-        let res = self.frame_counter.write_register(data, cycle);
-        self.handle_frame_result(res);
+        self.frame_counter.write_register(data, cycle);
       }
       _ => panic!("Invalid write_reg address 0x{:04X}", address),
     }
@@ -123,6 +338,7 @@ impl Apu {
         self.pulse_0.step_quarter_frame();
         self.pulse_1.step_quarter_frame();
         self.triangle.step_quarter_frame();
+        self.noise.step_quarter_frame();
       }
       FrameResult::Half => {
         self.pulse_0.step_quarter_frame();
@@ -131,29 +347,132 @@ impl Apu {
         self.pulse_1.step_half_frame();
         self.triangle.step_quarter_frame();
         self.triangle.step_half_frame();
+        self.noise.step_quarter_frame();
+        self.noise.step_half_frame();
       }
       FrameResult::None => (),
     }
   }
 
   pub fn get_irq_flag(&self) -> bool {
-    self.frame_counter.public_irq_flag
+    self.frame_counter.public_irq_flag || self.dmc.irq_flag
   }
+}
 
-  fn sample(&mut self) -> i16 {
-    let pulse_0 = self.pulse_0.sample() as f64;
-    let pulse_1 = self.pulse_1.sample() as f64;
-    let triangle = self.triangle.sample() as f64;
+#[cfg(test)]
+mod test {
+  use std::f64::consts::PI;
+  use std::fs;
 
-    let pulse_output = 95.88 / ((8218.0 / (pulse_0 + pulse_1)) + 100.0);
-    let t_output = 159.79 / ((1.0 / (triangle / 8227.0 / 12241.0 / 22638.0)) + 100.0);
+  use crate::apu::{Apu, DEFAULT_SAMPLE_RATE, RESET_RESTART_LEAD};
+  use crate::apu::audio_sink::{NullSink, WavSink};
+  use crate::nes::region::Region;
 
-    let mut output =  (pulse_output + t_output) * 65535.0;
+  // Left channel of the WAV file of 100 ms of APU output after the register writes
+  fn render_wav(name: &str, writes: &[(u16, u8)]) -> Vec<i16> {
+    let path = std::env::temp_dir().join(format!("nes-emulator-apu-{}.wav", name));
+    let sink = WavSink::create(&path, DEFAULT_SAMPLE_RATE).unwrap();
+    let mut apu = Apu::new(Region::Ntsc, DEFAULT_SAMPLE_RATE, Box::new(sink));
+    for &(address, data) in writes {
+      apu.apu_write_reg(address, data, 0);
+    }
+    for cycle in 0..Region::Ntsc.cpu_clock_rate() / 10 {
+      apu.step(cycle, 0.0);
+    }
+    apu.finish_audio().unwrap();
 
-    for i in 0..3 {
-      output = self.filters[i].step(output);
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let word = |idx: usize| u32::from_le_bytes([bytes[idx], bytes[idx + 1], bytes[idx + 2], bytes[idx + 3]]);
+    assert_eq!((&bytes[0..4], &bytes[8..16], &bytes[36..40]), (&b"RIFF"[..], &b"WAVEfmt "[..], &b"data"[..]));
+    // PCM, 2 channels, 16 bits
+    assert_eq!((word(20), word(24), word(32)), (0x0002_0001, DEFAULT_SAMPLE_RATE, 0x0010_0004));
+    assert_eq!((word(4), word(40)), (bytes.len() as u32 - 8, bytes.len() as u32 - 44));
+
+    let samples = bytes[44..].chunks_exact(4).map(|frame| i16::from_le_bytes([frame[0], frame[1]])).collect::<Vec<i16>>();
+    // About 4400 samples, the end of the last synthesis frame is not read
+    assert!(samples.len() > 4300 && samples.len() <= 4410, "{}", samples.len());
+    samples
+  }
+
+  // Amplitude of one frequency in the samples, a single bin of the DFT
+  fn tone_level(samples: &[i16], frequency: f64) -> f64 {
+    let step = 2.0 * PI * frequency / f64::from(DEFAULT_SAMPLE_RATE);
+    let (re, im) = samples.iter().enumerate().fold((0.0, 0.0), |(re, im), (idx, &sample)| {
+      let phase = step * idx as f64;
+      (re + f64::from(sample) * phase.cos(), im + f64::from(sample) * phase.sin())
+    });
+    2.0 * re.hypot(im) / samples.len() as f64
+  }
+
+  #[test]
+  fn first_quarter_frame_after_reset() {
+    let mut apu = Apu::new(Region::Ntsc, DEFAULT_SAMPLE_RATE, Box::new(NullSink));
+    apu.reset();
+    // Pulse with the envelope silent until its first clock, the sequencer on a high step by then
+    apu.apu_write_reg(0x4015, 0x01, 0);
+    apu.apu_write_reg(0x4000, 0xC0, 0);
+    apu.apu_write_reg(0x4002, 0xFF, 0);
+    apu.apu_write_reg(0x4003, 0x0B, 0);
+
+    // Cycles since the reset wrote 0x4017, the sequence starts 3 cycles after the write
+    let first_clock = (RESET_RESTART_LEAD..20_000).find(|&cycle| {
+      apu.step(cycle, 0.0);
+      apu.pulse_0.sample() > 0
+    }).map(|cycle| cycle + 1);
+    assert_eq!(first_clock, Some(3 + 7457));
+    // Counted from the first instruction, after the CPU reset sequence
+    assert_eq!(first_clock.map(|cycles| cycles - RESET_RESTART_LEAD - 8), Some(7450));
+  }
+
+  #[test]
+  fn length_counter_status() {
+    let mut apu = Apu::new(Region::Ntsc, DEFAULT_SAMPLE_RATE, Box::new(NullSink));
+    // Not loaded while the channel is disabled
+    apu.apu_write_reg(0x4003, 0x18, 0);
+    assert_eq!(apu.apu_read_reg() & 0x01, 0);
+
+    apu.apu_write_reg(0x4015, 0x01, 0);
+    apu.apu_write_reg(0x4003, 0x18, 0);
+    assert_eq!(apu.apu_read_reg() & 0x01, 0x01);
+
+    // The 5-step mode clocks the length counter when the sequence restarts, 3 cycles after an even write
+    apu.apu_write_reg(0x4017, 0x80, 0);
+    apu.step(1, 0.0);
+    apu.step(2, 0.0);
+    apu.step(3, 0.0);
+    assert_eq!(apu.apu_read_reg() & 0x01, 0x01);
+    apu.apu_write_reg(0x4017, 0x80, 3);
+    for cycle in 4..8 {
+      apu.step(cycle, 0.0);
     }
+    assert_eq!(apu.apu_read_reg() & 0x01, 0x00);
 
-    output as i16
+    // Reset silences the channels
+    apu.apu_write_reg(0x4003, 0x18, 8);
+    apu.reset();
+    assert_eq!(apu.apu_read_reg() & 0x01, 0x00);
+  }
+
+  #[test]
+  fn wav_output_of_tones() {
+    assert!(render_wav("silence", &[]).iter().all(|&sample| sample == 0));
+
+    let writes = [
+      (0x4015, 0x0F),
+      // Pulse 1, 50% duty, constant volume 15, 440 Hz
+      (0x4000, 0xBF), (0x4002, 0xFD), (0x4003, 0x08),
+      // Triangle at 110 Hz
+      (0x4008, 0xFF), (0x400A, 0xFB), (0x400B, 0x01),
+      // Noise, constant volume 8
+      (0x400C, 0x38), (0x400E, 0x05), (0x400F, 0x08),
+    ];
+    let tones = render_wav("tones", &writes);
+    let level = |frequency: f64| tone_level(&tones, frequency);
+    // A square wave has only odd harmonics
+    assert!(level(440.0) > 8.0 * level(495.0) && level(440.0) > 8.0 * level(385.0));
+    assert!(level(1320.0) > 8.0 * level(880.0));
+    assert!(level(110.0) > 4.0 * level(220.0));
+    assert!(tones.iter().all(|&sample| sample > i16::MIN && sample < i16::MAX));
   }
 }
@@ -0,0 +1,135 @@
+use crate::apu::envelope::Envelope;
+use crate::apu::length_counter::LengthCounter;
+use crate::apu::sequencer::Sequencer;
+
+pub struct Noise {
+  envelope: Envelope,
+  sequencer: Sequencer,
+  length_counter: LengthCounter,
+  // Timer periods in CPU cycles
+  periods: [u16; 16],
+  shift_register: u16,
+  is_short_mode: bool,
+}
+
+impl Noise {
+  pub fn new(periods: [u16; 16]) -> Noise {
+    let mut sequencer = Sequencer::new(1);
+    sequencer.period = periods[0] - 1;
+
+    Noise {
+      envelope: Envelope::new(),
+      sequencer,
+      length_counter: LengthCounter::new(),
+      periods,
+      shift_register: 1,
+      is_short_mode: false,
+    }
+  }
+
+  pub fn noise_write_reg_u8(&mut self, address: u16, data: u8) {
+    match address {
+      0x400C => {
+        self.envelope.write_reg(data);
+        self.length_counter.set_halted((data & 0x20) > 0);
+      }
+      0x400D => (),
+      0x400E => {
+        self.is_short_mode = data & 0x80 > 0;
+        // The sequencer reloads with period + 1 cycles
+        self.sequencer.period = self.periods[usize::from(data & 0x0F)] - 1;
+      }
+      0x400F => {
+        self.length_counter.write_register(data);
+        self.envelope.start();
+      }
+      _ => panic!("Invalid noise_write_reg_u8 address 0x{:04X}", address),
+    }
+  }
+
+  pub fn sample(&self) -> u8 {
+    if self.length_counter.active() && self.shift_register & 0x01 == 0 {
+      self.envelope.get_volume_level()
+    } else {
+      0
+    }
+  }
+
+  /// Clocked every CPU cycle, the periods are in CPU cycles too
+  pub fn step_sequencer(&mut self) {
+    if self.sequencer.step(true) {
+      // Short mode taps bit 6 instead of bit 1, which gives a 93 or 31 step sequence
+      let tap = if self.is_short_mode { 6 } else { 1 };
+      let feedback = (self.shift_register ^ (self.shift_register >> tap)) & 0x01;
+      self.shift_register = (self.shift_register >> 1) | (feedback << 14);
+    }
+  }
+
+  pub fn step_quarter_frame(&mut self) {
+    self.envelope.step();
+  }
+
+  pub fn step_half_frame(&mut self) {
+    self.length_counter.step();
+  }
+
+  pub fn is_playing(&self) -> bool {
+    self.length_counter.playing()
+  }
+
+  pub fn set_enabled(&mut self, value: bool) {
+    self.length_counter.set_enabled(value);
+  }
+
+  pub fn start_cycle(&mut self) {
+    self.length_counter.start_cycle();
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::apu::noise::Noise;
+  use crate::nes::region::Region;
+
+  fn sequence_length(noise: &mut Noise) -> usize {
+    let start = noise.shift_register;
+    (1..=0x8000).find(|_| {
+      for _ in 0..4 {
+        noise.step_sequencer();
+      }
+      noise.shift_register == start
+    }).unwrap()
+  }
+
+  #[test]
+  fn lfsr_sequence_lengths() {
+    let mut noise = Noise::new(Region::Ntsc.noise_periods());
+    assert_eq!(sequence_length(&mut noise), 32767);
+
+    noise.noise_write_reg_u8(0x400E, 0x80);
+    // Walk into the short loop first, the starting state may not be on it
+    for _ in 0..4 * 200 {
+      noise.step_sequencer();
+    }
+    assert_eq!(sequence_length(&mut noise), 93);
+  }
+
+  #[test]
+  fn length_counter_and_volume() {
+    let mut noise = Noise::new(Region::Pal.noise_periods());
+    noise.set_enabled(true);
+    noise.noise_write_reg_u8(0x400C, 0x17);
+    noise.noise_write_reg_u8(0x400F, 0x08);
+    assert!(noise.is_playing());
+    // The output is muted while bit 0 of the shift register is set, as it is at power up
+    assert_eq!(noise.sample(), 0);
+    for _ in 0..4 {
+      noise.step_sequencer();
+    }
+    assert_eq!(noise.sample(), 7);
+
+    noise.set_enabled(false);
+    assert!(!noise.is_playing());
+    assert_eq!(noise.sample(), 0);
+  }
+}
@@ -11,9 +11,11 @@ const SEQUENCE_LOOKUP_TABLE: [[u8; 8]; 4] = [
   [1, 0, 0, 1, 1, 1, 1, 1],
 ];
 
+#[derive(Clone)]
 pub struct Pulse {
   envelope: Envelope,
-  sweep: Sweep,
+  // The pulse channels of the MMC5 have no sweep unit, and nothing mutes them
+  sweep: Option<Sweep>,
   sequencer: Sequencer,
   length_counter: LengthCounter,
   cycle: usize,
@@ -23,13 +25,20 @@ impl Pulse {
   pub fn new(channel: Mode) -> Pulse {
     Pulse {
       envelope: Envelope::new(),
-      sweep: Sweep::new(channel),
+      sweep: Some(Sweep::new(channel)),
       sequencer: Sequencer::new(SEQUENCE_LOOKUP_TABLE[0].len()),
       length_counter: LengthCounter::new(),
       cycle: 0,
     }
   }
 
+  pub fn without_sweep() -> Pulse {
+    Pulse {
+      sweep: None,
+      ..Pulse::new(Mode::TwosComplement)
+    }
+  }
+
   pub fn pulse_write_reg_u8(&mut self, address: u16, data: u8) {
     match address % 4 {
       0x00 => {
@@ -38,7 +47,9 @@ impl Pulse {
         self.length_counter.set_halted((data & 0x20) > 0)
       }
       0x01 => {
-        self.sweep.write_reg(data);
+        if let Some(sweep) = self.sweep.as_mut() {
+          sweep.write_reg(data);
+        }
       }
       0x02 => {
         self.sequencer.set_period_lo(data);
@@ -54,7 +65,8 @@ impl Pulse {
   }
 
   pub fn sample(&self) -> u8 {
-    if self.length_counter.active() && self.sequencer.period > 7 && self.sweep.target_period(&self.sequencer) < 0x0800 {
+    let is_muted = self.sweep.as_ref().is_some_and(|sweep| sweep.is_muting(&self.sequencer));
+    if self.length_counter.active() && !is_muted {
       SEQUENCE_LOOKUP_TABLE[self.cycle][self.sequencer.current_step] * self.envelope.get_volume_level()
     } else {
       0
@@ -67,7 +79,9 @@ impl Pulse {
 
   pub fn step_half_frame(&mut self) {
     self.length_counter.step();
-    self.sweep.step(&mut self.sequencer);
+    if let Some(sweep) = self.sweep.as_mut() {
+      sweep.step(&mut self.sequencer);
+    }
   }
 
   pub fn step_sequencer(&mut self) {
@@ -82,7 +96,7 @@ impl Pulse {
     self.length_counter.set_enabled(value);
   }
 
-  pub fn update_length_counter(&mut self) {
-    self.length_counter.update_pending();
+  pub fn start_cycle(&mut self) {
+    self.length_counter.start_cycle();
   }
 }
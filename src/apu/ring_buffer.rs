@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI16, AtomicUsize, Ordering};
+
+/// Lock-free single producer single consumer queue of samples between the emulator and the audio thread
+struct RingBuffer {
+  slots: Box<[AtomicI16]>,
+  // Positions only ever grow, the slot is the position modulo the capacity
+  read_pos: AtomicUsize,
+  write_pos: AtomicUsize,
+}
+
+pub struct Producer {
+  ring: Arc<RingBuffer>,
+}
+
+pub struct Consumer {
+  ring: Arc<RingBuffer>,
+}
+
+pub fn ring_buffer(capacity: usize) -> (Producer, Consumer) {
+  let ring = Arc::new(RingBuffer {
+    slots: (0..capacity).map(|_| AtomicI16::new(0)).collect(),
+    read_pos: AtomicUsize::new(0),
+    write_pos: AtomicUsize::new(0),
+  });
+  (Producer { ring: ring.clone() }, Consumer { ring })
+}
+
+impl RingBuffer {
+  fn len(&self) -> usize {
+    self.write_pos.load(Ordering::Acquire).wrapping_sub(self.read_pos.load(Ordering::Acquire))
+  }
+}
+
+impl Producer {
+  pub fn capacity(&self) -> usize {
+    self.ring.slots.len()
+  }
+
+  /// Samples queued and not read yet
+  pub fn queued(&self) -> usize {
+    self.ring.len()
+  }
+
+  /// Queues as many samples as fit, returns how many were queued. The count is kept even so interleaved stereo
+  /// frames are never split.
+  pub fn push_slice(&mut self, samples: &[i16]) -> usize {
+    let ring = &self.ring;
+    let write_pos = ring.write_pos.load(Ordering::Relaxed);
+    let free = ring.slots.len() - write_pos.wrapping_sub(ring.read_pos.load(Ordering::Acquire));
+    let count = samples.len().min(free) & !0x01;
+    for (idx, &sample) in samples[..count].iter().enumerate() {
+      ring.slots[write_pos.wrapping_add(idx) % ring.slots.len()].store(sample, Ordering::Relaxed);
+    }
+    ring.write_pos.store(write_pos.wrapping_add(count), Ordering::Release);
+    count
+  }
+}
+
+impl Consumer {
+  pub fn pop(&mut self) -> Option<i16> {
+    let ring = &self.ring;
+    let read_pos = ring.read_pos.load(Ordering::Relaxed);
+    if read_pos == ring.write_pos.load(Ordering::Acquire) {
+      return None;
+    }
+    let sample = ring.slots[read_pos % ring.slots.len()].load(Ordering::Relaxed);
+    ring.read_pos.store(read_pos.wrapping_add(1), Ordering::Release);
+    Some(sample)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::thread;
+
+  use crate::apu::ring_buffer::ring_buffer;
+
+  #[test]
+  fn push_and_pop_wrap_around() {
+    let (mut producer, mut consumer) = ring_buffer(6);
+    assert_eq!(producer.push_slice(&[1, 2, 3, 4]), 4);
+    assert_eq!(consumer.pop(), Some(1));
+    assert_eq!(consumer.pop(), Some(2));
+    // Only whole stereo frames are queued
+    assert_eq!(producer.push_slice(&[5, 6, 7, 8, 9, 10]), 4);
+    assert_eq!(producer.queued(), 6);
+    let popped: Vec<_> = (0..7).map(|_| consumer.pop()).collect();
+    assert_eq!(popped, [Some(3), Some(4), Some(5), Some(6), Some(7), Some(8), None]);
+    assert_eq!(producer.queued(), 0);
+  }
+
+  #[test]
+  fn samples_cross_threads_in_order() {
+    let (mut producer, mut consumer) = ring_buffer(64);
+    let reader = thread::spawn(move || {
+      let mut samples = Vec::new();
+      while samples.len() < 10_000 {
+        if let Some(sample) = consumer.pop() {
+          samples.push(sample);
+        }
+      }
+      samples
+    });
+
+    let expected: Vec<i16> = (0..10_000).map(|idx| idx as i16).collect();
+    let mut pending = &expected[..];
+    while !pending.is_empty() {
+      let count = producer.push_slice(&pending[..pending.len().min(16)]);
+      pending = &pending[count..];
+    }
+    assert_eq!(reader.join().unwrap(), expected);
+  }
+}
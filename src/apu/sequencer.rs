@@ -1,4 +1,5 @@
 
+#[derive(Clone)]
 pub struct Sequencer {
   pub frame_counter: u16,
   pub period: u16,
@@ -1,5 +1,6 @@
 use std::f64::consts::PI;
 
+#[derive(Clone)]
 pub struct SignalFilter {
   b_0: f64,
   b_1: f64,
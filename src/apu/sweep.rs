@@ -15,6 +15,7 @@ impl Mode {
   }
 }
 
+#[derive(Clone)]
 pub struct Sweep {
   is_enabled: bool,
   is_reload: bool,
@@ -38,13 +39,10 @@ impl Sweep {
     }
   }
 
+  /// The new period only applies from the next reload of the timer
   pub fn step(&mut self, sequencer: &mut Sequencer) {
-    if self.current_period == 0 && self.is_enabled && self.shift_amount > 0 && sequencer.period > 7 {
-      let next_period = self.target_period(sequencer);
-      if next_period < 0x0800 {
-        sequencer.period = next_period;
-        sequencer.frame_counter = next_period;
-      }
+    if self.frame_counter == 0 && self.is_enabled && self.shift_amount > 0 && !self.is_muting(sequencer) {
+      sequencer.period = self.target_period(sequencer);
     }
 
     if self.frame_counter > 0 && !self.is_reload {
@@ -63,12 +61,68 @@ impl Sweep {
     self.is_reload = true;
   }
 
+  /// Negated targets below zero are clamped, they never mute the channel
   pub fn target_period(&self, sequencer: &Sequencer) -> u16 {
     let period = sequencer.period;
     if self.is_negate {
-      period - (period >> self.shift_amount) - self.negate_mode.value()
+      period.saturating_sub((period >> self.shift_amount) + self.negate_mode.value())
     } else {
       period + (period >> self.shift_amount)
     }
   }
+
+  /// Periods below 8 and targets past 0x7FF silence the channel, even with the sweep disabled or a zero shift
+  pub fn is_muting(&self, sequencer: &Sequencer) -> bool {
+    sequencer.period < 8 || self.target_period(sequencer) > 0x07FF
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::apu::sequencer::Sequencer;
+  use crate::apu::sweep::{Mode, Sweep};
+
+  fn sequencer(period: u16) -> Sequencer {
+    let mut sequencer = Sequencer::new(8);
+    sequencer.period = period;
+    sequencer
+  }
+
+  #[test]
+  fn overflow_mutes() {
+    let mut sweep = Sweep::new(Mode::OnesComplement);
+    // Disabled, shift 0: the target is twice the period
+    assert!(!sweep.is_muting(&sequencer(0x3FF)));
+    assert!(sweep.is_muting(&sequencer(0x400)));
+    assert!(sweep.is_muting(&sequencer(7)));
+
+    // Negated targets never mute, and the ones' complement of pulse 1 does not underflow
+    sweep.write_reg(0x08);
+    assert!(!sweep.is_muting(&sequencer(0x7FF)));
+    assert_eq!(sweep.target_period(&sequencer(0)), 0);
+  }
+
+  #[test]
+  fn period_updates() {
+    let mut sweep = Sweep::new(Mode::TwosComplement);
+    // Enabled, divider period 1, shift 1
+    sweep.write_reg(0x91);
+    let mut sequencer = sequencer(0x100);
+    sequencer.frame_counter = 0x20;
+    // Updated when the divider is zero, then every second step
+    sweep.step(&mut sequencer);
+    assert_eq!(sequencer.period, 0x180);
+    sweep.step(&mut sequencer);
+    assert_eq!(sequencer.period, 0x180);
+    sweep.step(&mut sequencer);
+    assert_eq!(sequencer.period, 0x240);
+    // The timer keeps counting down the old period
+    assert_eq!(sequencer.frame_counter, 0x20);
+
+    // A muting target stops the updates
+    let mut sequencer = self::sequencer(0x600);
+    sweep.step(&mut sequencer);
+    sweep.step(&mut sequencer);
+    assert_eq!(sequencer.period, 0x600);
+  }
 }
@@ -89,7 +89,7 @@ impl Triangle {
     self.length_counter.set_enabled(value);
   }
 
-  pub fn update_length_counter(&mut self) {
-    self.length_counter.update_pending();
+  pub fn start_cycle(&mut self) {
+    self.length_counter.start_cycle();
   }
 }
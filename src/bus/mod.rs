@@ -5,6 +5,7 @@ use std::rc::Rc;
 use crate::apu::Apu;
 use crate::cartridge::Cartridge;
 use crate::nes::controller::Controller;
+use crate::ppu::Ppu;
 use crate::ppu::registers::Registers;
 
 pub const MEM_SIZE: usize = 0x0800;
@@ -16,12 +17,13 @@ pub struct Bus {
   apu: Rc<RefCell<Apu>>,
   controller: Rc<RefCell<Controller>>,
   registers: Rc<RefCell<Registers>>,
+  ppu: Rc<RefCell<Ppu>>,
   pub dma_transfer: bool,
   dma_page: u8,
 }
 
 impl Bus {
-  pub fn new(cartridge: Rc<RefCell<Box<Cartridge>>>, registers: Rc<RefCell<Registers>>, controller: Rc<RefCell<Controller>>, apu: Rc<RefCell<Apu>>) -> Bus {
+  pub fn new(cartridge: Rc<RefCell<Box<Cartridge>>>, registers: Rc<RefCell<Registers>>, ppu: Rc<RefCell<Ppu>>, controller: Rc<RefCell<Controller>>, apu: Rc<RefCell<Apu>>) -> Bus {
     let ram = [0u8; MEM_SIZE];
     let dma_transfer = false;
     let dma_page = 0x00;
@@ -32,6 +34,7 @@ impl Bus {
       apu,
       controller,
       registers,
+      ppu,
       dma_transfer,
       dma_page,
     }
@@ -57,11 +60,22 @@ impl Bus {
     self.registers.borrow_mut()
   }
 
+  // The PPU may be behind on the current scanline, it has to catch up before anything it depends on changes
+  fn sync_ppu(&mut self) {
+    self.ppu.borrow_mut().sync();
+  }
+
   pub fn write_u8(&mut self, address: u16, data: u8, cycles: u32) {
+    // Mappers switch banks anywhere in cartridge space, NINA-03/06 and others below 0x8000
+    if (0x2000..=0x3FFF).contains(&address) || address == 0x4014 || address >= 0x4020 {
+      self.sync_ppu();
+    }
+
     if (0x0000..=0x1FFF).contains(&address) {
       self.ram[usize::from(address & 0x07FF)] = data;
     } else if (0x2000..=0x3FFF).contains(&address) {
-      self.get_mut_registers().bus_write_ppu_reg(address, data)
+      self.get_mut_registers().bus_write_ppu_reg(address, data);
+      self.get_mut_cartridge().mapper.snoop_ppu_register(address, data);
     } else if address == 0x4014 {
       self.dma_page = data;
       self.get_mut_registers().oam_address = 0x00;
@@ -72,6 +86,9 @@ impl Bus {
       self.get_controller().write(data);
     } else if 0x4017 == address {
       self.get_mut_apu().apu_write_reg(address, data, cycles);
+    } else if (0x4020..=0x5FFF).contains(&address) {
+      self.get_mut_apu().log_expansion_write(address, data);
+      self.get_mut_cartridge().mapper.write_expansion_u8(address, data);
     } else if (0x6000..=0xFFFF).contains(&address) {
       self.get_mut_cartridge().mapper.mapped_write_cpu_u8(address, data);
     }
@@ -81,6 +98,7 @@ impl Bus {
     if (0x0000..=0x1FFF).contains(&address) {
       self.ram[usize::from(address & 0x07FF)]
     } else if (0x2000..=0x3FFF).contains(&address) {
+      self.sync_ppu();
       self.get_mut_registers().bus_read_ppu_reg(address)
     } else if address == 0x4015 {
       self.get_mut_apu().apu_read_reg()
@@ -88,6 +106,8 @@ impl Bus {
       self.get_controller().read()
     } else if 0x4017 == address {
       0
+    } else if (0x4020..=0x5FFF).contains(&address) {
+      self.get_mut_cartridge().mapper.read_expansion_u8(address)
     } else if (0x6000..=0xFFFF).contains(&address) {
       self.get_cartridge().mapper.mapped_read_cpu_u8(address)
     } else {
@@ -104,6 +124,7 @@ impl Bus {
 
   pub fn oam_dma_access(&mut self, system_cycles: u32) -> u32 {
     let cpu_dma_cycles = 513 + (system_cycles % 2);
+    self.sync_ppu();
     for idx in 0..=255 {
       let addr = (u16::from(self.dma_page) << 8) + idx;
       let dma_data = self.read_u8(addr);
@@ -112,4 +133,113 @@ impl Bus {
     self.dma_transfer = false;
     cpu_dma_cycles
   }
+
+  /// Refills the DMC sample buffer when it runs empty, returns the CPU cycles stolen by the fetch
+  pub fn dmc_dma_access(&mut self) -> u32 {
+    let address = self.get_mut_apu().dmc.dma_address();
+    match address {
+      Some(address) => {
+        let data = self.read_u8(address);
+        let mut apu = self.get_mut_apu();
+        // VGM players read DMC samples from their own copy of the memory, the byte goes there as it is fetched so
+        // it comes from the bank mapped at that time
+        apu.log_dmc_sample(address, &[data]);
+        apu.dmc.load_sample_byte(data);
+        4
+      }
+      None => 0,
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::fs;
+  use std::rc::Rc;
+
+  use crate::apu::Apu;
+  use crate::apu::audio_sink::NullSink;
+  use crate::bus::Bus;
+  use crate::cartridge::Cartridge;
+  use crate::mapper::mapper140::Mapper140;
+  use crate::mapper::pager::PageSize::{Eight, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+  use crate::nes::OffScreenBuffer;
+  use crate::nes::constants::{SCREEN_RES_X, SCREEN_RES_Y};
+  use crate::nes::controller::Controller;
+  use crate::nes::region::Region;
+  use crate::ppu::{Ppu, PpuState};
+  use crate::ppu::registers::Registers;
+
+  fn jaleco_bus(is_catch_up: bool) -> (Bus, Rc<RefCell<OffScreenBuffer>>) {
+    let rom = numbered_rom(140, 4, ThirtyTwo, 16, Eight);
+    let rom_header = rom.borrow().rom_header;
+    let cart = Rc::new(RefCell::new(Box::new(Cartridge { mapper: Box::new(Mapper140::new(rom)), rom_header })));
+
+    let mut registers = Registers::new(cart.clone(), Region::Ntsc);
+    for idx in 0..0x20 {
+      registers.ppu_write_reg(0x3F00 + idx, idx as u8 * 2);
+    }
+    registers.bus_write_ppu_reg(0x2001, 0x0A);
+    let registers = Rc::new(RefCell::new(registers));
+
+    let off_screen_pixels = Rc::new(RefCell::new([[0u8; 3]; (SCREEN_RES_X * SCREEN_RES_Y) as usize]));
+    let mut ppu = Ppu::new(registers.clone(), off_screen_pixels.clone(), Region::Ntsc);
+    ppu.is_catch_up = is_catch_up;
+    let ppu = Rc::new(RefCell::new(ppu));
+    let controller = Rc::new(RefCell::new(Controller::new()));
+    let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc, 44_100, Box::new(NullSink))));
+    (Bus::new(cart, registers, ppu, controller, apu), off_screen_pixels)
+  }
+
+  #[test]
+  fn mid_line_bank_switch_below_0x8000() {
+    let (mut catch_up, catch_up_pixels) = jaleco_bus(true);
+    let (mut dot_accurate, dot_accurate_pixels) = jaleco_bus(false);
+
+    for bus in [&mut catch_up, &mut dot_accurate] {
+      // Two frames, switching the CHR bank in the middle of every line of the second one
+      for frame in 0..2 {
+        let mut dot = 0;
+        while bus.ppu.borrow_mut().clock() != PpuState::Render {
+          dot += 1;
+          if frame == 1 && dot % 341 == 150 {
+            bus.write_u8(0x6000, (dot / 341 % 16) as u8, 0);
+          }
+        }
+      }
+    }
+    assert!(catch_up_pixels.borrow().iter().eq(dot_accurate_pixels.borrow().iter()));
+  }
+
+  #[test]
+  fn dmc_bytes_logged_from_the_bank_they_are_fetched_from() {
+    let (mut bus, _) = jaleco_bus(true);
+    let path = std::env::temp_dir().join("nes-emulator-bus-dmc.vgm");
+    bus.get_mut_apu().start_vgm_log(&path).unwrap();
+
+    // 17 bytes from 0xC000 at the fastest rate, the bank changes after the first few
+    bus.write_u8(0x4010, 0x0F, 0);
+    bus.write_u8(0x4013, 0x01, 0);
+    bus.write_u8(0x4015, 0x10, 0);
+    for cycle in 0..17 * 54 * 8 {
+      if cycle == 4 * 54 * 8 {
+        bus.write_u8(0x6000, 0x20, cycle);
+      }
+      bus.get_mut_apu().step(cycle, 0.0);
+      bus.dmc_dma_access();
+    }
+    bus.get_mut_apu().finish_audio().unwrap();
+
+    let bytes = fs::read(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let logged: Vec<(u16, u8)> = bytes.windows(10)
+      .filter(|block| block[..7] == [0x67, 0x66, 0xC2, 0x03, 0x00, 0x00, 0x00])
+      .map(|block| (u16::from_le_bytes([block[7], block[8]]), block[9]))
+      .collect();
+    assert_eq!(logged.len(), 17);
+    assert_eq!(logged[0], (0xC000, 0));
+    assert_eq!(logged[16], (0xC010, 2));
+  }
 }
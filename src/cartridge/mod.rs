@@ -1,9 +1,12 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::cartridge::rom_reading::{Rom, RomHeader, Mirroring};
+use crate::cartridge::rom_reading::{NameTableSource, Rom, RomHeader};
 use crate::cartridge::rom_with_pager::RomData;
-use crate::mapper::{Mapper, mapper0::Mapper0, mapper1::Mapper1, mapper2::Mapper2, mapper3::Mapper3, mapper4::Mapper4};
+use crate::mapper::{Mapper, mapper0::Mapper0, mapper1::Mapper1, mapper2::Mapper2, mapper3::Mapper3, mapper4::Mapper4, mapper5::Mapper5, mapper7::Mapper7,
+  mapper11::Mapper11, mapper34::Mapper34, mapper66::Mapper66, mapper71::Mapper71, mapper79::Mapper79, mapper140::Mapper140};
+use crate::mapper::nsf_mapper::NsfMapper;
+use crate::nsf::Nsf;
 
 pub mod rom_reading;
 pub mod rom_with_pager;
@@ -13,7 +16,6 @@ pub const CHR_ROM_BANK_SIZE: usize = 0x2000;
 #[derive(Clone)]
 pub struct Cartridge {
   pub mapper: Box<dyn Mapper>,
-  #[allow(dead_code)]
   pub rom_header: RomHeader,
 }
 
@@ -29,22 +31,46 @@ impl Cartridge {
       1 => Box::new(Mapper1::new(rom_ref)),
       2 => Box::new(Mapper2::new(rom_ref)),
       3 => Box::new(Mapper3::new(rom_ref)),
-      4 => Box::new(Mapper4::new(rom_ref)),
+      4 | 118 | 119 | 206 => Box::new(Mapper4::new(rom_ref)),
+      5 => Box::new(Mapper5::new(rom_ref)),
+      7 => Box::new(Mapper7::new(rom_ref)),
+      11 => Box::new(Mapper11::new(rom_ref)),
+      34 => Box::new(Mapper34::new(rom_ref)),
+      66 => Box::new(Mapper66::new(rom_ref)),
+      71 => Box::new(Mapper71::new(rom_ref)),
+      79 => Box::new(Mapper79::new(rom_ref)),
+      140 => Box::new(Mapper140::new(rom_ref)),
       _ => panic!("Mapper {} not implemented", rom_header.mapper),
     };
 
     Box::from(Cartridge { mapper, rom_header })
   }
 
+  /// Cartridge of the NSF player with the program of the music file
+  pub fn from_nsf(nsf: &Nsf) -> Box<Cartridge> {
+    let (prg_rom, banks) = nsf.prg_image();
+    let rom = Rom::from_nsf_image(prg_rom, nsf.region);
+    let rom_header = rom.rom_header;
+
+    let rom_ref = Rc::new(RefCell::new(RomData::new(rom)));
+    let mapper = Box::new(NsfMapper::new(rom_ref, banks, nsf.expansion_chips));
+
+    Box::from(Cartridge { mapper, rom_header })
+  }
+
   pub fn irq_flag(&self) -> bool {
     self.mapper.irq_flag()
   }
 
-  pub fn clear_irq_flag(&mut self) { self.mapper.clear_irq_flag() }
+  /// Clocks the mapper for one CPU cycle and returns its expansion audio output
+  pub fn clock_cpu(&mut self) -> f64 {
+    self.mapper.clock_cpu();
+    self.mapper.audio_output()
+  }
 
 
-  pub fn get_mirror_mode(&self) -> Mirroring {
-    self.mapper.mirroring()
+  pub fn name_tables(&self) -> [NameTableSource; 4] {
+    self.mapper.name_tables()
   }
 }
 
@@ -54,13 +80,18 @@ mod test {
   use std::rc::Rc;
 
   use crate::cartridge::Cartridge;
-  use crate::cartridge::rom_reading::Rom;
+  use crate::cartridge::rom_reading::{Mirroring, Rom};
   use crate::cartridge::rom_with_pager::RomData;
   use crate::mapper::mapper0::Mapper0;
 
   impl Cartridge {
     pub fn mock_cartridge() -> Cartridge {
-      let rom = Rom::mock_rom();
+      Cartridge::mock_cartridge_with_mirroring(Mirroring::Horizontal)
+    }
+
+    pub fn mock_cartridge_with_mirroring(mirroring: Mirroring) -> Cartridge {
+      let mut rom = Rom::mock_rom();
+      rom.rom_header.mirroring = mirroring;
 
       let rom_header = rom.rom_header;
       let rom_ref = Rc::new(RefCell::new(RomData::new(rom)));
@@ -1,5 +1,7 @@
 use std::iter::Iterator;
 
+use crate::nes::region::Region;
+
 #[derive(Copy, Clone, Debug)]
 pub struct RomHeader {
   pub prg_rom_len: usize,
@@ -7,7 +9,10 @@ pub struct RomHeader {
   pub prg_ram_len: usize,
   pub chr_ram_len: usize,
   pub mirroring: Mirroring,
-  pub mapper: u8,
+  pub mapper: u16,
+  pub submapper: u8,
+  /// Timing from the header, `None` when unknown or multi-region
+  pub region: Option<Region>,
   #[allow(dead_code)]
   pub flag_persistent: bool,
   #[allow(dead_code)]
@@ -48,13 +53,10 @@ impl Rom {
     let flags_6 = bytes.next().unwrap_or_else(|| panic!("flags_6 read error"));
     let flags_7 = bytes.next().unwrap_or_else(|| panic!("flags_7 read error"));
     let flags_8 = bytes.next().unwrap_or_else(|| panic!("flags_8 read error"));
-    let _flags_9 = bytes.next().unwrap_or_else(|| panic!("flags_9 read error"));
+    let flags_9 = bytes.next().unwrap_or_else(|| panic!("flags_9 read error"));
     let flags_10 = bytes.next().unwrap_or_else(|| panic!("flags_10 read error"));
-
-    let zeros = (&mut bytes).take(5);
-    if [0, 0, 0, 0, 0].iter().cloned().ne(zeros) {
-      panic!("Non-zero bits found on unused block")
-    }
+    let flags_11 = bytes.next().unwrap_or_else(|| panic!("flags_11 read error"));
+    let flags_12 = bytes.next().unwrap_or_else(|| panic!("flags_12 read error"));
 
     let flag_mirror = (flags_6 & 0x01) > 0x00;
     let flag_persistent = (flags_6 & 0x02) > 0x00;
@@ -67,28 +69,55 @@ impl Rom {
     let flag_rom_format = (flags_7 & 0x0C) >> 2;
     let mapper_hi = flags_7 & 0xF0;
 
-    let flag_bus_conflicts = (flags_10 & 0x20) > 0x00;
-
-    if flag_rom_format == 2 {
-      unimplemented!("NES 2.0 ROM format not implemented");
+    let is_nes_2 = flag_rom_format == 2;
+    let rest = (&mut bytes).take(3).collect::<Vec<u8>>();
+    if !is_nes_2 && (flags_11 != 0 || flags_12 != 0 || rest.iter().any(|b| *b != 0)) {
+      panic!("Non-zero bits found on unused block")
     }
 
-    let prg_rom_len = prg_rom_pagse as usize * PRG_ROM_PAGE_SIZE;
-    let chr_rom_len = chr_rom_pages as usize * CHR_ROM_PAGE_SIZE;
+    let (prg_rom_len, chr_rom_len, prg_ram_len, chr_ram_len, mapper, submapper, region, flag_bus_conflicts);
+    if is_nes_2 {
+      prg_rom_len = nes_2_rom_len(prg_rom_pagse, flags_9 & 0x0F, PRG_ROM_PAGE_SIZE);
+      chr_rom_len = nes_2_rom_len(chr_rom_pages, flags_9 >> 4, CHR_ROM_PAGE_SIZE);
+
+      // Volatile and battery backed RAM live in the same pager, in whole 8K pages. Smaller chips are mirrored on the
+      // board, the extra bytes only hold the same values again
+      let ram_len = nes_2_ram_len(flags_10 & 0x0F) + nes_2_ram_len(flags_10 >> 4);
+      prg_ram_len = ram_len.max(1).next_multiple_of(PRG_RAM_PAGE_SIZE);
+      let ram_len = nes_2_ram_len(flags_11 & 0x0F) + nes_2_ram_len(flags_11 >> 4);
+      chr_ram_len = if ram_len > 0 { ram_len } else { chr_rom_len.max(CHR_RAM_PAGE_SIZE) };
 
-    let prg_ram_size = if flags_8 > 0 { flags_8 } else { 1 };
-    let prg_ram_len = prg_ram_size as usize * PRG_RAM_PAGE_SIZE;
+      mapper = u16::from(mapper_lo | mapper_hi) | (u16::from(flags_8 & 0x0F) << 8);
+      submapper = flags_8 >> 4;
+      region = match flags_12 & 0x03 {
+        0 => Some(Region::Ntsc),
+        1 => Some(Region::Pal),
+        3 => Some(Region::Dendy),
+        _ => None,
+      };
+      flag_bus_conflicts = false;
+    } else {
+      prg_rom_len = prg_rom_pagse as usize * PRG_ROM_PAGE_SIZE;
+      chr_rom_len = chr_rom_pages as usize * CHR_ROM_PAGE_SIZE;
 
-    let chr_ram_len = if chr_rom_pages == 0 { CHR_RAM_PAGE_SIZE } else { chr_rom_pages as usize * CHR_RAM_PAGE_SIZE };
+      let prg_ram_size = if flags_8 > 0 { flags_8 } else { 1 };
+      prg_ram_len = prg_ram_size as usize * PRG_RAM_PAGE_SIZE;
+
+      chr_ram_len = if chr_rom_pages == 0 { CHR_RAM_PAGE_SIZE } else { chr_rom_pages as usize * CHR_RAM_PAGE_SIZE };
+
+      mapper = u16::from(mapper_lo | mapper_hi);
+      submapper = 0;
+      // Most dumps leave the TV system bit clear, so unset doesn't mean NTSC
+      region = if flags_9 & 0x01 > 0 { Some(Region::Pal) } else { None };
+      flag_bus_conflicts = (flags_10 & 0x20) > 0x00;
+    }
 
     let mirroring = match (flag_mirror, flag_four_screen_vram) {
+      (_, true) => Mirroring::FourScreen,
       (true, false) => Mirroring::Vertical,
       (false, false) => Mirroring::Horizontal,
-      _ => panic!("Mirroring mode {}, {} not supported", flag_mirror, flag_four_screen_vram)
     };
 
-    let mapper = mapper_lo | mapper_hi;
-
     let rom_header = RomHeader {
       prg_rom_len,
       chr_rom_len,
@@ -96,6 +125,8 @@ impl Rom {
       chr_ram_len,
       mirroring,
       mapper,
+      submapper,
+      region,
       flag_persistent,
       flag_trainer,
       flag_vs_unisystem,
@@ -130,6 +161,33 @@ impl Rom {
     }
   }
 
+  /// NSF program image with the work RAM and pattern table RAM of the player
+  pub fn from_nsf_image(prg_rom: Vec<u8>, region: Option<Region>) -> Rom {
+    let rom_header = RomHeader {
+      prg_rom_len: prg_rom.len(),
+      chr_rom_len: 0,
+      prg_ram_len: PRG_RAM_PAGE_SIZE,
+      chr_ram_len: CHR_RAM_PAGE_SIZE,
+      mirroring: Mirroring::Horizontal,
+      mapper: 0,
+      submapper: 0,
+      region,
+      flag_persistent: false,
+      flag_trainer: false,
+      flag_vs_unisystem: false,
+      flag_playchoice_10: false,
+      flag_bus_conflicts: false,
+    };
+
+    Rom {
+      rom_header,
+      prg_rom,
+      prg_ram: vec![0u8; PRG_RAM_PAGE_SIZE],
+      chr_rom: Vec::new(),
+      chr_ram: vec![0u8; CHR_RAM_PAGE_SIZE],
+    }
+  }
+
   #[allow(dead_code)]
   pub fn mock_rom() -> Rom {
     let rom_header = RomHeader {
@@ -139,6 +197,8 @@ impl Rom {
       chr_ram_len: CHR_RAM_PAGE_SIZE,
       mirroring: Mirroring::Horizontal,
       mapper: 0,
+      submapper: 0,
+      region: None,
       flag_persistent: false,
       flag_trainer: false,
       flag_vs_unisystem: false,
@@ -156,8 +216,93 @@ impl Rom {
   }
 }
 
+// NES 2.0 ROM size, the MSB nibble 0xF switches the LSB byte to exponent-multiplier notation
+fn nes_2_rom_len(lsb: u8, msb: u8, page_size: usize) -> usize {
+  if msb == 0x0F {
+    (1usize << (lsb >> 2)) * (usize::from(lsb & 0x03) * 2 + 1)
+  } else {
+    ((usize::from(msb) << 8) | usize::from(lsb)) * page_size
+  }
+}
+
+// NES 2.0 RAM size as shift count, 64 << shift bytes
+fn nes_2_ram_len(shift: u8) -> usize {
+  if shift == 0 { 0 } else { 64 << shift }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Mirroring {
   Vertical,
   Horizontal,
+  SingleScreenLower,
+  SingleScreenUpper,
+  /// Cartridge provides 2K extra VRAM so all four nametables are unique
+  FourScreen,
+}
+
+impl Mirroring {
+  /// Nametable behind each 1K quadrant of $2000-$2FFF
+  pub fn name_tables(self) -> [NameTableSource; 4] {
+    use NameTableSource::Vram;
+
+    match self {
+      Mirroring::Vertical => [Vram(0), Vram(1), Vram(0), Vram(1)],
+      Mirroring::Horizontal => [Vram(0), Vram(0), Vram(1), Vram(1)],
+      Mirroring::SingleScreenLower => [Vram(0); 4],
+      Mirroring::SingleScreenUpper => [Vram(1); 4],
+      Mirroring::FourScreen => [Vram(0), Vram(1), Vram(2), Vram(3)],
+    }
+  }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NameTableSource {
+  /// 1K page of nametable memory, 0 and 1 are the console VRAM, 2 and 3 the four-screen VRAM on the cartridge
+  Vram(usize),
+  /// Mapper provided memory, accessed with `Mapper::read_name_table` and `Mapper::write_name_table`
+  Cartridge,
+}
+
+#[cfg(test)]
+mod test {
+  use crate::cartridge::rom_reading::Rom;
+  use crate::nes::region::Region;
+
+  fn rom_bytes(header: [u8; 12]) -> Vec<u8> {
+    let mut bytes = b"NES\x1A".to_vec();
+    bytes.extend_from_slice(&header);
+    bytes.extend(vec![0u8; usize::from(header[0]) * 0x4000 + usize::from(header[1]) * 0x2000]);
+    bytes
+  }
+
+  #[test]
+  fn ines_pal_bit() {
+    let rom = Rom::read_from_file(rom_bytes([1, 1, 0x10, 0, 0, 1, 0, 0, 0, 0, 0, 0]).into_iter());
+    assert_eq!(rom.rom_header.mapper, 1);
+    assert_eq!(rom.rom_header.region, Some(Region::Pal));
+
+    let rom = Rom::read_from_file(rom_bytes([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).into_iter());
+    assert_eq!(rom.rom_header.region, None);
+  }
+
+  #[test]
+  fn nes_2_header() {
+    // Mapper 0x104, submapper 2, 32K PRG RAM, 8K CHR RAM, Dendy
+    let rom = Rom::read_from_file(rom_bytes([2, 0, 0x40, 0x08, 0x21, 0, 0x09, 0x07, 0x03, 0, 0, 0]).into_iter());
+    let header = rom.rom_header;
+    assert_eq!(header.mapper, 0x104);
+    assert_eq!(header.submapper, 2);
+    assert_eq!(header.prg_rom_len, 0x8000);
+    assert_eq!(header.prg_ram_len, 0x8000);
+    assert_eq!(header.chr_ram_len, 0x2000);
+    assert_eq!(header.region, Some(Region::Dendy));
+  }
+
+  #[test]
+  fn nes_2_small_prg_ram() {
+    // 2K PRG RAM is rounded up to an 8K page
+    let rom = Rom::read_from_file(rom_bytes([2, 0, 0x10, 0x08, 0, 0, 0x05, 0, 0, 0, 0, 0]).into_iter());
+    assert_eq!(rom.rom_header.prg_ram_len, 0x2000);
+    assert_eq!(rom.prg_ram.len(), 0x2000);
+  }
 }
@@ -20,6 +20,13 @@ pub struct Cpu {
   pub cycle: u8,
   lookup: LookUpTable,
   system_cycle: u32,
+  // Fetched opcode waiting to be executed on the last cycle of the instruction
+  is_executing: bool,
+  nmi_line: bool,
+  nmi_detected: bool,
+  nmi_pending: bool,
+  irq_line: bool,
+  irq_pending: bool,
 }
 
 impl Cpu {
@@ -41,6 +48,12 @@ impl Cpu {
       cycle: 0u8,
       lookup,
       system_cycle: 0,
+      is_executing: false,
+      nmi_line: false,
+      nmi_detected: false,
+      nmi_pending: false,
+      irq_line: false,
+      irq_pending: false,
     }
   }
 
@@ -111,33 +124,85 @@ impl Cpu {
     0
   }
 
+  // Read-modify-write instructions write the unmodified value back before the result, which mappers like MMC1 see
   fn return_or_write_memory(&mut self, val: u16) {
     if self.addr_mode() == AddrMode6502::Imp {
       self.acc = u8::try_from(val & 0xFF).unwrap();
     } else {
+      self.bus_write_u8(self.addr_abs, self.fetched);
       self.bus_write_u8(self.addr_abs, u8::try_from(val & 0xFF).unwrap());
     }
   }
 
+  /// Instructions are executed on their last cycle, where loads and stores access the bus on real hardware, so
+  /// register reads and writes land on the right PPU dot. Interrupts are polled on the second to last cycle and
+  /// serviced between instructions.
   pub fn clock(&mut self, system_cycle: u32) {
+    self.system_cycle = system_cycle;
+
     if self.cycle == 0 {
-      self.system_cycle = system_cycle;
-      self.opcode = self.bus_mut_read_u8(self.pc);
+      if self.nmi_pending {
+        self.nmi_pending = false;
+        self.nmi();
+      } else if self.irq_pending {
+        self.irq_pending = false;
+        self.irq();
+      } else {
+        self.opcode = self.bus_mut_read_u8(self.pc);
+        self.pc_increment();
+        self.cycle = self.lookup.get_cycles(usize::from(self.opcode));
+        self.is_executing = true;
+      }
+    }
 
-      self.set_flag(&Flag6502::U, true);
-      self.pc_increment();
+    if self.is_executing && self.cycle == 1 {
+      self.is_executing = false;
+      self.execute();
+    }
 
-      let opcode_idx = usize::from(self.opcode);
-      self.cycle = self.lookup.get_cycles(opcode_idx);
+    self.cycle -= 1;
 
-      let addr_mode = *self.lookup.get_addr_mode(opcode_idx);
-      let operate = *self.lookup.get_operate(opcode_idx);
+    if self.cycle == 1 {
+      if self.nmi_detected {
+        self.nmi_detected = false;
+        self.nmi_pending = true;
+      }
+      self.irq_pending = self.irq_line && !self.get_flag(&Flag6502::I);
+    }
+  }
 
-      self.cycle += self.addr_mode_value(addr_mode) & self.op_code_value(operate);
+  fn execute(&mut self) {
+    let opcode_idx = usize::from(self.opcode);
+    let addr_mode = *self.lookup.get_addr_mode(opcode_idx);
+    let operate = *self.lookup.get_operate(opcode_idx);
 
-      self.set_flag(&Flag6502::U, true);
+    self.set_flag(&Flag6502::U, true);
+    self.cycle += self.addr_mode_value(addr_mode) & self.op_code_value(operate);
+    self.set_flag(&Flag6502::U, true);
+  }
+
+  /// Samples the NMI line once per cycle, the interrupt triggers on its rising edge
+  pub fn set_nmi_line(&mut self, level: bool) {
+    if level && !self.nmi_line {
+      self.nmi_detected = true;
     }
-    self.cycle -= 1;
+    self.nmi_line = level;
+  }
+
+  /// Samples the IRQ line once per cycle, a level held by its sources until the program acknowledges them
+  pub fn set_irq_line(&mut self, level: bool) {
+    self.irq_line = level;
+  }
+
+  /// Calls a subroutine from outside the program like JSR would, RTS resumes at `return_address`. Must be called
+  /// between instructions.
+  pub fn call(&mut self, address: u16, return_address: u16) {
+    let pushed_address = return_address.wrapping_sub(1);
+    self.bus_write_u8(self.get_stack_address(), u8::try_from(pushed_address >> 8).unwrap());
+    self.stack_pointer_decrement();
+    self.bus_write_u8(self.get_stack_address(), u8::try_from(pushed_address & 0xFF).unwrap());
+    self.stack_pointer_decrement();
+    self.pc = address;
   }
 
   #[allow(dead_code)]
@@ -200,32 +265,35 @@ impl Cpu {
     self.fetched = 0x00;
 
     self.cycle = 8;
+    self.is_executing = false;
+    self.nmi_detected = false;
+    self.nmi_pending = false;
+    self.irq_pending = false;
   }
 
-  pub fn irq(&mut self) {
-    if self.get_flag(&Flag6502::I) || self.bus.get_mut_apu().get_irq_flag() {
-      self.bus_write_u8(self.get_stack_address(), u8::try_from((self.pc >> 8) & 0x00FF).unwrap());
-      self.stack_pointer_decrement();
-      self.bus_write_u8(self.get_stack_address(), u8::try_from(self.pc & 0x00FF).unwrap());
-      self.stack_pointer_decrement();
+  fn irq(&mut self) {
+    self.bus_write_u8(self.get_stack_address(), u8::try_from((self.pc >> 8) & 0x00FF).unwrap());
+    self.stack_pointer_decrement();
+    self.bus_write_u8(self.get_stack_address(), u8::try_from(self.pc & 0x00FF).unwrap());
+    self.stack_pointer_decrement();
 
-      self.set_flag(&Flag6502::B, false);
-      self.set_flag(&Flag6502::U, true);
-      self.set_flag(&Flag6502::I, true);
-      self.bus_write_u8(self.get_stack_address(), self.status_register);
-      self.stack_pointer_decrement();
+    // The pushed flags still have interrupts enabled, RTI turns them back on
+    self.set_flag(&Flag6502::B, false);
+    self.set_flag(&Flag6502::U, true);
+    self.bus_write_u8(self.get_stack_address(), self.status_register);
+    self.stack_pointer_decrement();
+    self.set_flag(&Flag6502::I, true);
 
-      self.addr_abs = 0xFFFE;
-      let lo_byte = self.bus_mut_read_u8(self.addr_abs) as u16;
-      let hi_byte = self.bus_mut_read_u8(self.addr_abs + 1) as u16;
-      self.pc = (hi_byte << 8) | lo_byte;
+    self.addr_abs = 0xFFFE;
+    let lo_byte = self.bus_mut_read_u8(self.addr_abs) as u16;
+    let hi_byte = self.bus_mut_read_u8(self.addr_abs + 1) as u16;
+    self.pc = (hi_byte << 8) | lo_byte;
 
-      self.cycle = 7;
-    }
+    self.cycle = 7;
   }
 
   /// Non-maskable interrupt
-  pub fn nmi(&mut self) {
+  fn nmi(&mut self) {
     self.bus_write_u8(self.get_stack_address(), u8::try_from((self.pc >> 8) & 0xFF).unwrap());
     self.stack_pointer_decrement();
     self.bus_write_u8(self.get_stack_address(), u8::try_from(self.pc & 0xFF).unwrap());
@@ -233,17 +301,17 @@ impl Cpu {
 
     self.set_flag(&Flag6502::B, false);
     self.set_flag(&Flag6502::U, true);
-    self.set_flag(&Flag6502::I, true);
 
     self.bus_write_u8(self.get_stack_address(), self.status_register);
     self.stack_pointer_decrement();
+    self.set_flag(&Flag6502::I, true);
 
     self.addr_abs = 0xFFFA;
     let lo_byte = self.bus_mut_read_u8(self.addr_abs) as u16;
     let hi_byte = self.bus_mut_read_u8(self.addr_abs.wrapping_add(1)) as u16;
     self.pc = (hi_byte << 8) | lo_byte;
 
-    self.cycle = 8;
+    self.cycle = 7;
   }
 
   /// ADDRESS MODES
@@ -529,7 +597,6 @@ impl Cpu {
   pub fn brk(&mut self) -> u8 {
     self.pc_increment();
 
-    self.set_flag(&Flag6502::I, true);
     self.bus_write_u8(self.get_stack_address(), u8::try_from((self.pc >> 8) & 0xFF).unwrap());
     self.stack_pointer_decrement();
 
@@ -540,6 +607,7 @@ impl Cpu {
     self.bus_write_u8(self.get_stack_address(), self.status_register);
     self.stack_pointer_decrement();
     self.set_flag(&Flag6502::B, false);
+    self.set_flag(&Flag6502::I, true);
 
     self.pc = u16::from(self.bus_mut_read_u8(0xFFFE)) | u16::from(self.bus_mut_read_u8(0xFFFF)) << 8;
     0
@@ -610,7 +678,7 @@ impl Cpu {
   pub fn dec(&mut self) -> u8 {
     self.fetch();
     let val = u16::from(self.fetched).wrapping_sub(1);
-    self.bus_write_u8(self.addr_abs, u8::try_from(val & 0xFF).unwrap());
+    self.return_or_write_memory(val);
     self.set_flags_zero_and_negative(val);
     0
   }
@@ -641,7 +709,7 @@ impl Cpu {
   pub fn inc(&mut self) -> u8 {
     self.fetch();
     let val = u16::from(self.fetched.wrapping_add(1));
-    self.bus_write_u8(self.addr_abs, u8::try_from(val & 0xFF).unwrap());
+    self.return_or_write_memory(val);
     self.set_flags_zero_and_negative(val);
     0
   }
@@ -1016,3 +1084,122 @@ impl Cpu {
 //     (lo_byte, hi_byte)
 //   }
 }
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::apu::Apu;
+  use crate::apu::audio_sink::NullSink;
+  use crate::bus::Bus;
+  use crate::cartridge::Cartridge;
+  use crate::cartridge::rom_reading::Rom;
+  use crate::cartridge::rom_with_pager::RomData;
+  use crate::cpu::Cpu;
+  use crate::cpu::instruction_table::Flag6502;
+  use crate::mapper::mapper0::Mapper0;
+  use crate::nes::controller::Controller;
+  use crate::nes::region::Region;
+  use crate::ppu::Ppu;
+  use crate::ppu::registers::Registers;
+
+  const NMI_HANDLER: u16 = 0x9000;
+  const IRQ_HANDLER: u16 = 0x9100;
+
+  // Runs `program` from 0x8000 once the reset sequence is over, both handlers increment a counter in zero page
+  // (0x10 for NMI, 0x11 for IRQ) and return
+  fn cpu(program: &[u8]) -> Cpu {
+    let mut rom = Rom::mock_rom();
+    rom.prg_rom[..program.len()].copy_from_slice(program);
+    rom.prg_rom[0x1000..0x1003].copy_from_slice(&[0xE6, 0x10, 0x40]);
+    rom.prg_rom[0x1100..0x1103].copy_from_slice(&[0xE6, 0x11, 0x40]);
+    rom.prg_rom[0x3FFA..].copy_from_slice(&[0x00, 0x90, 0x00, 0x80, 0x00, 0x91]);
+    let rom_header = rom.rom_header;
+    let mapper = Box::new(Mapper0::new(Rc::new(RefCell::new(RomData::new(rom)))));
+
+    let cart = Rc::new(RefCell::new(Box::new(Cartridge { mapper, rom_header })));
+    let registers = Rc::new(RefCell::new(Registers::new(cart.clone(), Region::Ntsc)));
+    let off_screen_pixels = Rc::new(RefCell::new([[0u8; 3]; 256 * 240]));
+    let ppu = Rc::new(RefCell::new(Ppu::new(registers.clone(), off_screen_pixels, Region::Ntsc)));
+    let controller = Rc::new(RefCell::new(Controller::new()));
+    let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc, 44_100, Box::new(NullSink))));
+    let mut cpu = Cpu::new(Bus::new(cart, registers, ppu, controller, apu));
+    cpu.reset();
+    run(&mut cpu, 8);
+    cpu
+  }
+
+  fn run(cpu: &mut Cpu, cycles: u32) {
+    for cycle in 0..cycles {
+      cpu.clock(cycle);
+    }
+  }
+
+  fn return_address(cpu: &Cpu) -> u16 {
+    u16::from(cpu.bus.ram[0x1FD]) << 8 | u16::from(cpu.bus.ram[0x1FC])
+  }
+
+  #[test]
+  fn nmi_edge_polled_before_last_cycle() {
+    // Edge seen by the poll of the first NOP, the NMI follows it
+    let mut cpu = cpu(&[0xEA; 4]);
+    cpu.set_nmi_line(true);
+    run(&mut cpu, 3);
+    assert_eq!((cpu.pc, return_address(&cpu)), (NMI_HANDLER, 0x8001));
+
+    // Edge during the last cycle of the first NOP, one more instruction runs first
+    let mut cpu = self::cpu(&[0xEA; 4]);
+    run(&mut cpu, 1);
+    cpu.set_nmi_line(true);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.pc, 0x8002);
+    run(&mut cpu, 1);
+    assert_eq!((cpu.pc, return_address(&cpu)), (NMI_HANDLER, 0x8002));
+
+    // A line held high is a single edge
+    run(&mut cpu, 40);
+    assert_eq!(cpu.bus.ram[0x10], 1);
+  }
+
+  #[test]
+  fn irq_masked_by_interrupt_disable() {
+    // NOP, NOP, CLI, NOP, NOP
+    let mut cpu = cpu(&[0xEA, 0xEA, 0x58, 0xEA, 0xEA]);
+    cpu.set_flag(&Flag6502::I, true);
+    cpu.set_irq_line(true);
+    run(&mut cpu, 6);
+    assert_eq!(cpu.pc, 0x8003);
+
+    // CLI polls before clearing the flag, the next instruction still runs
+    run(&mut cpu, 2);
+    assert_eq!(cpu.pc, 0x8004);
+    run(&mut cpu, 1);
+    assert_eq!((cpu.pc, return_address(&cpu)), (IRQ_HANDLER, 0x8004));
+    // Pushed with interrupts enabled
+    assert_eq!(cpu.bus.ram[0x1FB] & Flag6502::I.value(), 0);
+  }
+
+  #[test]
+  fn irq_acknowledged_before_cli() {
+    // NOP, NOP, CLI, then NOPs
+    let mut program = [0xEA; 64];
+    program[2] = 0x58;
+    let mut cpu = cpu(&program);
+    cpu.set_flag(&Flag6502::I, true);
+    cpu.set_irq_line(true);
+    run(&mut cpu, 2);
+    cpu.set_irq_line(false);
+    run(&mut cpu, 20);
+    assert_eq!(cpu.bus.ram[0x11], 0);
+
+    // Held until acknowledged by the handler, not taken again after RTI
+    cpu.set_irq_line(true);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.pc, IRQ_HANDLER);
+    cpu.set_irq_line(false);
+    run(&mut cpu, 40);
+    assert_eq!(cpu.bus.ram[0x11], 1);
+    assert!(cpu.pc > 0x8000 && cpu.pc < 0x8040);
+  }
+}
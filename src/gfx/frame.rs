@@ -0,0 +1,39 @@
+use crate::nes::constants::{SCREEN_RES_X, SCREEN_RES_Y};
+use crate::nes::OffScreenBuffer;
+
+/// RGB frame handed to the window. Rows are stored bottom-up, the same way as `OffScreenBuffer`
+/// and the OpenGL texture.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Frame {
+  pub width: u32,
+  pub height: u32,
+  pub pixels: Vec<u8>,
+}
+
+impl Frame {
+  pub fn new(width: u32, height: u32) -> Frame {
+    Frame {
+      width,
+      height,
+      pixels: vec![0u8; (width * height * 3) as usize],
+    }
+  }
+
+  pub fn from_off_screen(off_screen: &OffScreenBuffer) -> Frame {
+    Frame {
+      width: SCREEN_RES_X,
+      height: SCREEN_RES_Y,
+      pixels: off_screen.iter().flat_map(|p| *p).collect::<Vec<u8>>(),
+    }
+  }
+
+  pub fn get_pixel(&self, x: u32, y: u32) -> [u8; 3] {
+    let idx = ((y * self.width + x) * 3) as usize;
+    [self.pixels[idx], self.pixels[idx + 1], self.pixels[idx + 2]]
+  }
+
+  pub fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 3]) {
+    let idx = ((y * self.width + x) * 3) as usize;
+    self.pixels[idx..idx + 3].copy_from_slice(&color);
+  }
+}
@@ -1,12 +1,19 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::rc::Rc;
 use glium::{implement_vertex, Display, Texture2d};
-use glium::texture::RawImage2d;
+use glium::texture::{ClientFormat, RawImage2d};
 use glium::vertex::VertexBufferAny;
 use glutin::surface::WindowSurface;
 use winit::event_loop::EventLoop;
+use crate::gfx::frame::Frame;
 use crate::nes::constants::{SCALING_FACTOR, SCREEN_RES_Y, SCREEN_RES_X};
 
+pub mod frame;
+pub mod ntsc;
+pub mod scaler;
+pub mod screenshot;
+
 const VERTEX_SHADER_SRC: &str = r#"
         #version 140
 
@@ -85,9 +92,13 @@ impl WindowContext {
         }
     }
 
-    pub fn update_image_buffer(&mut self, pixels: Vec<u8>) {
-        let raw_image = RawImage2d::from_raw_rgb(pixels, (SCREEN_RES_X, SCREEN_RES_Y));
-        self.texture.write(glium::Rect { left: 0, bottom: 0, width: SCREEN_RES_X, height: SCREEN_RES_Y }, raw_image);
+    pub fn update_image_buffer(&mut self, frame: &Frame) {
+        let (width, height) = (frame.width, frame.height);
+        if self.texture.width() != width || self.texture.height() != height {
+            self.texture = Texture2d::empty(&self.display, width, height).unwrap();
+        }
+        let raw_image = RawImage2d { data: Cow::Borrowed(&frame.pixels), width, height, format: ClientFormat::U8U8U8 };
+        self.texture.write(glium::Rect { left: 0, bottom: 0, width, height }, raw_image);
     }
 
     pub fn update_screen_size(&mut self) {
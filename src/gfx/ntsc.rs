@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+
+use crate::gfx::frame::Frame;
+use crate::nes::constants::{COLORS, SCREEN_RES_X, SCREEN_RES_Y};
+use crate::nes::PaletteIndexBuffer;
+
+// The PPU outputs 8 signal samples per pixel at 12 times the color subcarrier frequency
+const SAMPLES_PER_PIXEL: usize = 8;
+const SUBCARRIER_PHASES: usize = 12;
+const LINE_SAMPLES: usize = SCREEN_RES_X as usize * SAMPLES_PER_PIXEL;
+// 341 dots * 8 samples per scanline, modulo 12
+const SCANLINE_PHASE_SHIFT: usize = 4;
+// Same output width as blargg's nes_ntsc for 256 input pixels
+pub const NTSC_OUTPUT_WIDTH: u32 = 602;
+
+// Voltage levels, relative to sync voltage
+const BLACK: f32 = 0.518;
+const WHITE: f32 = 1.962;
+const ATTENUATION: f32 = 0.746;
+const LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+
+// Shifts the demodulator so that hues line up with the RGB palette
+const HUE_OFFSET: f32 = 3.9;
+const GAMMA: f32 = 2.0;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NtscPreset {
+  Composite,
+  SVideo,
+  Rgb,
+}
+
+impl NtscPreset {
+  pub fn from_name(name: &str) -> Option<NtscPreset> {
+    match name.to_lowercase().as_str() {
+      "composite" => Some(NtscPreset::Composite),
+      "svideo" | "s-video" => Some(NtscPreset::SVideo),
+      "rgb" => Some(NtscPreset::Rgb),
+      _ => None,
+    }
+  }
+}
+
+/// Turns 9-bit PPU pixels (`0bEEE_LL_CCCC`, emphasis, luma level and hue) into a simulated NTSC signal and decodes
+/// it back to RGB, reproducing the chroma/luma crosstalk and dot crawl of the real video output.
+pub struct NtscFilter {
+  preset: NtscPreset,
+  signal_table: Vec<[f32; SUBCARRIER_PHASES]>,
+  luma_table: Vec<f32>,
+  cos_table: [f32; SUBCARRIER_PHASES],
+  sin_table: [f32; SUBCARRIER_PHASES],
+  signal: Vec<f32>,
+  luma: Vec<f32>,
+  frame: Frame,
+}
+
+impl NtscFilter {
+  pub fn new(preset: NtscPreset) -> NtscFilter {
+    let signal_table = (0..0x200u16)
+      .map(|pixel| {
+        let mut phases = [0.0; SUBCARRIER_PHASES];
+        for (phase, level) in phases.iter_mut().enumerate() {
+          *level = signal_level(pixel, phase);
+        }
+        phases
+      })
+      .collect::<Vec<[f32; SUBCARRIER_PHASES]>>();
+    let luma_table = signal_table.iter()
+      .map(|phases| phases.iter().sum::<f32>() / SUBCARRIER_PHASES as f32)
+      .collect::<Vec<f32>>();
+
+    let mut cos_table = [0.0; SUBCARRIER_PHASES];
+    let mut sin_table = [0.0; SUBCARRIER_PHASES];
+    for phase in 0..SUBCARRIER_PHASES {
+      let angle = PI * (phase as f32 + HUE_OFFSET) / 6.0;
+      cos_table[phase] = angle.cos();
+      sin_table[phase] = angle.sin();
+    }
+
+    NtscFilter {
+      preset,
+      signal_table,
+      luma_table,
+      cos_table,
+      sin_table,
+      signal: vec![0.0; LINE_SAMPLES],
+      luma: vec![0.0; LINE_SAMPLES],
+      frame: Frame::new(NTSC_OUTPUT_WIDTH, SCREEN_RES_Y),
+    }
+  }
+
+  /// Filters a whole frame. `is_even_frame` selects the starting subcarrier phase, which alternates between frames
+  /// and causes the dot crawl.
+  pub fn apply(&mut self, pixels: &PaletteIndexBuffer, is_even_frame: bool) -> &Frame {
+    let frame_phase = if is_even_frame { 0 } else { SCANLINE_PHASE_SHIFT };
+    let width = SCREEN_RES_X as usize;
+
+    for row in 0..SCREEN_RES_Y as usize {
+      // Rows are stored bottom-up, the phase follows the scanline order
+      let scan_line = SCREEN_RES_Y as usize - 1 - row;
+      let phase = (frame_phase + scan_line * SCANLINE_PHASE_SHIFT) % SUBCARRIER_PHASES;
+      let line = &pixels[row * width..(row + 1) * width];
+
+      match self.preset {
+        NtscPreset::Rgb => self.render_rgb_line(line, row),
+        NtscPreset::Composite | NtscPreset::SVideo => {
+          self.encode_line(line, phase);
+          self.decode_line(row, phase);
+        }
+      }
+    }
+    &self.frame
+  }
+
+  fn encode_line(&mut self, line: &[u16], phase: usize) {
+    for (x, pixel) in line.iter().enumerate() {
+      let levels = &self.signal_table[usize::from(*pixel & 0x1FF)];
+      let luma = self.luma_table[usize::from(*pixel & 0x1FF)];
+      for sample in 0..SAMPLES_PER_PIXEL {
+        let idx = x * SAMPLES_PER_PIXEL + sample;
+        self.signal[idx] = levels[(phase + idx) % SUBCARRIER_PHASES];
+        self.luma[idx] = luma;
+      }
+    }
+  }
+
+  fn decode_line(&mut self, row: usize, phase: usize) {
+    let is_svideo = self.preset == NtscPreset::SVideo;
+
+    for x in 0..NTSC_OUTPUT_WIDTH as usize {
+      let center = (x * LINE_SAMPLES + LINE_SAMPLES / 2) / NTSC_OUTPUT_WIDTH as usize;
+      // Always demodulate a full subcarrier cycle, a partial window would leak luma into chroma at the edges
+      let begin = center.saturating_sub(SUBCARRIER_PHASES / 2).min(LINE_SAMPLES - SUBCARRIER_PHASES);
+      let end = begin + SUBCARRIER_PHASES;
+
+      let (mut y, mut i, mut q) = (0.0f32, 0.0f32, 0.0f32);
+      for idx in begin..end {
+        let luma = self.luma[idx];
+        // S-Video keeps luma and chroma on separate wires, composite mixes them together
+        let (y_level, c_level) = if is_svideo {
+          (luma, self.signal[idx] - luma)
+        } else {
+          (self.signal[idx], self.signal[idx])
+        };
+        let p = (phase + idx) % SUBCARRIER_PHASES;
+        y += y_level;
+        i += c_level * self.cos_table[p];
+        q += c_level * self.sin_table[p];
+      }
+      let n = SUBCARRIER_PHASES as f32;
+      let color = yiq_to_rgb(y / n, i / n, q / n);
+      self.frame.set_pixel(x as u32, row as u32, color);
+    }
+  }
+
+  fn render_rgb_line(&mut self, line: &[u16], row: usize) {
+    for x in 0..NTSC_OUTPUT_WIDTH {
+      let pixel = line[(x * SCREEN_RES_X / NTSC_OUTPUT_WIDTH) as usize];
+      let color = COLORS[usize::from(pixel & 0x3F)].to_value();
+      self.frame.set_pixel(x, row as u32, color);
+    }
+  }
+}
+
+fn in_color_phase(color: usize, phase: usize) -> bool {
+  (color + phase) % SUBCARRIER_PHASES < 6
+}
+
+fn signal_level(pixel: u16, phase: usize) -> f32 {
+  let color = usize::from(pixel & 0x0F);
+  let level = if color > 13 { 1 } else { usize::from((pixel >> 4) & 0x03) };
+  let emphasis = (pixel >> 6) & 0x07;
+
+  let mut low = LEVELS[level];
+  let mut high = LEVELS[4 + level];
+  if color == 0 {
+    low = high;
+  }
+  if color > 12 {
+    high = low;
+  }
+
+  let mut signal = if in_color_phase(color, phase) { high } else { low };
+
+  if (emphasis & 0x01 > 0 && in_color_phase(0, phase))
+    || (emphasis & 0x02 > 0 && in_color_phase(4, phase))
+    || (emphasis & 0x04 > 0 && in_color_phase(8, phase)) {
+    signal *= ATTENUATION;
+  }
+
+  (signal - BLACK) / (WHITE - BLACK)
+}
+
+fn yiq_to_rgb(y: f32, i: f32, q: f32) -> [u8; 3] {
+  let gamma_fix = |f: f32| if f <= 0.0 { 0.0 } else { f.powf(2.2 / GAMMA) };
+  let clamp = |f: f32| (255.95 * gamma_fix(f)).clamp(0.0, 255.0) as u8;
+
+  [
+    clamp(y + 0.946_882 * i + 0.623_557 * q),
+    clamp(y - 0.274_788 * i - 0.635_691 * q),
+    clamp(y - 1.108_545 * i + 1.709_007 * q),
+  ]
+}
+
+#[cfg(test)]
+mod test {
+  use crate::gfx::ntsc::{NTSC_OUTPUT_WIDTH, NtscFilter, NtscPreset};
+  use crate::nes::constants::{COLORS, SCREEN_RES_X, SCREEN_RES_Y};
+
+  #[test]
+  fn rgb_preset_keeps_palette_colors() {
+    let mut filter = NtscFilter::new(NtscPreset::Rgb);
+    let pixels = [0x16u16; (SCREEN_RES_X * SCREEN_RES_Y) as usize];
+    let frame = filter.apply(&pixels, true);
+
+    assert_eq!(frame.width, NTSC_OUTPUT_WIDTH);
+    assert_eq!(frame.height, SCREEN_RES_Y);
+    assert!(frame.pixels.chunks(3).all(|p| p == COLORS[0x16].to_value()));
+  }
+
+  #[test]
+  fn composite_gray_has_no_chroma() {
+    let mut filter = NtscFilter::new(NtscPreset::Composite);
+    let pixels = [0x00u16; (SCREEN_RES_X * SCREEN_RES_Y) as usize];
+    let frame = filter.apply(&pixels, false);
+
+    assert!(frame.pixels.chunks(3).all(|p| p[0] == p[1] && p[1] == p[2]));
+  }
+}
@@ -0,0 +1,375 @@
+use std::borrow::Cow;
+
+use crate::gfx::frame::Frame;
+
+// Maximum YUV differences for two colors to count as the same, the thresholds of hqx
+const SIMILAR_THRESHOLD_Y: i32 = 48;
+const SIMILAR_THRESHOLD_U: i32 = 7;
+const SIMILAR_THRESHOLD_V: i32 = 6;
+
+/// Pixel-art upscalers run on the CPU before the frame is uploaded to the texture. Scale2x, Scale3x and xBR level 1 at
+/// 2x follow their reference algorithms. There is no hqNx, its pattern tables are not reproduced; `Blend2x` only
+/// borrows its color comparison. Higher xBR levels and factors are not implemented either.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Scaler {
+  #[default]
+  None,
+  Scale2x,
+  Scale3x,
+  Blend2x,
+  Xbr2x,
+}
+
+impl Scaler {
+  pub fn from_name(name: &str) -> Option<Scaler> {
+    match name.to_lowercase().as_str() {
+      "none" => Some(Scaler::None),
+      "scale2x" => Some(Scaler::Scale2x),
+      "scale3x" => Some(Scaler::Scale3x),
+      "blend2x" => Some(Scaler::Blend2x),
+      "xbr2x" | "xbr" => Some(Scaler::Xbr2x),
+      _ => None,
+    }
+  }
+
+  /// Next scaler in the hotkey cycle
+  pub fn next(self) -> Scaler {
+    match self {
+      Scaler::None => Scaler::Scale2x,
+      Scaler::Scale2x => Scaler::Scale3x,
+      Scaler::Scale3x => Scaler::Blend2x,
+      Scaler::Blend2x => Scaler::Xbr2x,
+      Scaler::Xbr2x => Scaler::None,
+    }
+  }
+
+  pub fn factor(self) -> u32 {
+    match self {
+      Scaler::None => 1,
+      Scaler::Scale2x | Scaler::Blend2x | Scaler::Xbr2x => 2,
+      Scaler::Scale3x => 3,
+    }
+  }
+
+  /// Scaled copy of the frame, or the frame itself without a scaler
+  pub fn scale(self, src: &Frame) -> Cow<'_, Frame> {
+    match self {
+      Scaler::None => Cow::Borrowed(src),
+      scaler => Cow::Owned(scaler.apply(src)),
+    }
+  }
+
+  pub fn apply(self, src: &Frame) -> Frame {
+    let factor = self.factor();
+    let mut dst = Frame::new(src.width * factor, src.height * factor);
+    // Converted once, the blending scalers compare every pixel with all of its neighbours
+    let yuv = match self {
+      Scaler::Blend2x | Scaler::Xbr2x => src.pixels.chunks_exact(3).map(|p| to_yuv([p[0], p[1], p[2]])).collect(),
+      _ => Vec::new(),
+    };
+    let radius = if self == Scaler::Xbr2x { 2 } else { 1 };
+    let mut block = [[0u8; 3]; 9];
+
+    for y in 0..src.height {
+      for x in 0..src.width {
+        let window = Window::new(src, &yuv, x as i32, y as i32, radius);
+        match self {
+          Scaler::None => block[0] = window.rgb(0, 0),
+          Scaler::Scale2x => block[..4].copy_from_slice(&scale2x(&window)),
+          Scaler::Scale3x => block = scale3x(&window),
+          Scaler::Blend2x => block[..4].copy_from_slice(&blend2x(&window)),
+          Scaler::Xbr2x => block[..4].copy_from_slice(&xbr2x(&window)),
+        }
+        for (idx, color) in block.iter().take((factor * factor) as usize).enumerate() {
+          let (dx, dy) = (idx as u32 % factor, idx as u32 / factor);
+          dst.set_pixel(x * factor + dx, y * factor + dy, *color);
+        }
+      }
+    }
+    dst
+  }
+}
+
+type Rgb = [u8; 3];
+type Yuv = [i32; 3];
+
+const WINDOW_RADIUS: i32 = 2;
+const WINDOW_SIDE: usize = 2 * WINDOW_RADIUS as usize + 1;
+
+/// Up to 5x5 pixels around the one being scaled, with their YUV values when the scaler compares colors. Pixels
+/// outside the frame repeat its edge.
+struct Window {
+  rgb: [Rgb; WINDOW_SIDE * WINDOW_SIDE],
+  yuv: [Yuv; WINDOW_SIDE * WINDOW_SIDE],
+}
+
+impl Window {
+  fn new(src: &Frame, yuv: &[Yuv], x: i32, y: i32, radius: i32) -> Window {
+    let mut window = Window {
+      rgb: [[0; 3]; WINDOW_SIDE * WINDOW_SIDE],
+      yuv: [[0; 3]; WINDOW_SIDE * WINDOW_SIDE],
+    };
+    for v in -radius..=radius {
+      let row = (y + v).clamp(0, src.height as i32 - 1) as usize * src.width as usize;
+      for u in -radius..=radius {
+        let src_idx = row + (x + u).clamp(0, src.width as i32 - 1) as usize;
+        let idx = Window::index(u, v);
+        window.rgb[idx] = [src.pixels[src_idx * 3], src.pixels[src_idx * 3 + 1], src.pixels[src_idx * 3 + 2]];
+        if let Some(color) = yuv.get(src_idx) {
+          window.yuv[idx] = *color;
+        }
+      }
+    }
+    window
+  }
+
+  fn index(u: i32, v: i32) -> usize {
+    ((v + WINDOW_RADIUS) as usize) * WINDOW_SIDE + (u + WINDOW_RADIUS) as usize
+  }
+
+  fn rgb(&self, u: i32, v: i32) -> Rgb {
+    self.rgb[Window::index(u, v)]
+  }
+
+  fn is_similar(&self, (u_0, v_0): (i32, i32), (u_1, v_1): (i32, i32)) -> bool {
+    is_similar(self.yuv[Window::index(u_0, v_0)], self.yuv[Window::index(u_1, v_1)])
+  }
+
+  fn distance(&self, (u_0, v_0): (i32, i32), (u_1, v_1): (i32, i32)) -> i32 {
+    distance(self.yuv[Window::index(u_0, v_0)], self.yuv[Window::index(u_1, v_1)])
+  }
+}
+
+/// AdvMAME2x/Scale2x, neighbours named
+/// ```text
+///   B
+/// D E F
+///   H
+/// ```
+fn scale2x(window: &Window) -> [Rgb; 4] {
+  let b = window.rgb(0, -1);
+  let d = window.rgb(-1, 0);
+  let e = window.rgb(0, 0);
+  let f = window.rgb(1, 0);
+  let h = window.rgb(0, 1);
+
+  if b != h && d != f {
+    [
+      if d == b { d } else { e },
+      if b == f { f } else { e },
+      if d == h { d } else { e },
+      if h == f { f } else { e },
+    ]
+  } else {
+    [e; 4]
+  }
+}
+
+/// AdvMAME3x/Scale3x, neighbours named
+/// ```text
+/// A B C
+/// D E F
+/// G H I
+/// ```
+fn scale3x(window: &Window) -> [Rgb; 9] {
+  let a = window.rgb(-1, -1);
+  let b = window.rgb(0, -1);
+  let c = window.rgb(1, -1);
+  let d = window.rgb(-1, 0);
+  let e = window.rgb(0, 0);
+  let f = window.rgb(1, 0);
+  let g = window.rgb(-1, 1);
+  let h = window.rgb(0, 1);
+  let i = window.rgb(1, 1);
+
+  if b != h && d != f {
+    [
+      if d == b { d } else { e },
+      if (d == b && e != c) || (b == f && e != a) { b } else { e },
+      if b == f { f } else { e },
+      if (d == b && e != g) || (d == h && e != a) { d } else { e },
+      e,
+      if (b == f && e != i) || (h == f && e != c) { f } else { e },
+      if d == h { d } else { e },
+      if (d == h && e != i) || (h == f && e != g) { h } else { e },
+      if h == f { f } else { e },
+    ]
+  } else {
+    [e; 9]
+  }
+}
+
+/// Corner blending at 2x. Colors are compared in YUV with the hqx thresholds, but each output pixel picks one of three
+/// rules (edge blend, diagonal blend or copy) from its corner neighbours instead of the 256 entry pattern table of hq2x.
+fn blend2x(window: &Window) -> [Rgb; 4] {
+  let corner = |sx: i32, sy: i32| {
+    let (a, b, d) = ((sx, sy), (0, sy), (sx, 0));
+    let (e_rgb, a_rgb, b_rgb, d_rgb) = (window.rgb(0, 0), window.rgb(sx, sy), window.rgb(0, sy), window.rgb(sx, 0));
+    if window.is_similar(b, d) && !window.is_similar((0, 0), b) {
+      if window.is_similar((0, 0), a) {
+        blend(&[(e_rgb, 6), (b_rgb, 1), (d_rgb, 1)])
+      } else {
+        blend(&[(e_rgb, 2), (b_rgb, 1), (d_rgb, 1)])
+      }
+    } else if !window.is_similar((0, 0), a) {
+      blend(&[(e_rgb, 3), (a_rgb, 1)])
+    } else {
+      e_rgb
+    }
+  };
+
+  [corner(-1, -1), corner(1, -1), corner(-1, 1), corner(1, 1)]
+}
+
+/// xBR level 1 at 2x. Each output corner looks at its quadrant of the 5x5 neighbourhood; mirrored for the
+/// bottom right corner the names are
+/// ```text
+///       B
+///     D E F F4
+///     G H I I4
+///         H5 I5
+/// ```
+/// plus `C` above `F`.
+fn xbr2x(window: &Window) -> [Rgb; 4] {
+  let e = (0, 0);
+  let corner = |sx: i32, sy: i32| {
+    let p = |u: i32, v: i32| (u * sx, v * sy);
+    let (b, c, d, f, g, h, i) = (p(0, -1), p(1, -1), p(-1, 0), p(1, 0), p(-1, 1), p(0, 1), p(1, 1));
+    let (f4, i4, h5, i5) = (p(2, 0), p(2, 1), p(0, 2), p(1, 2));
+    let dist = |c_0, c_1| window.distance(c_0, c_1);
+
+    let edge = dist(e, c) + dist(e, g) + dist(i, f4) + dist(i, h5) + 4 * dist(h, f);
+    let cross = dist(h, d) + dist(h, i5) + dist(f, i4) + dist(f, b) + 4 * dist(e, i);
+
+    if edge < cross {
+      let (u, v) = if dist(e, f) <= dist(e, h) { f } else { h };
+      blend(&[(window.rgb(0, 0), 1), (window.rgb(u, v), 1)])
+    } else {
+      window.rgb(0, 0)
+    }
+  };
+
+  [corner(-1, -1), corner(1, -1), corner(-1, 1), corner(1, 1)]
+}
+
+fn to_yuv(color: Rgb) -> Yuv {
+  let [r, g, b] = color.map(i32::from);
+  let y = (299 * r + 587 * g + 114 * b) / 1000;
+  let u = (-169 * r - 331 * g + 500 * b) / 1000 + 128;
+  let v = (500 * r - 419 * g - 81 * b) / 1000 + 128;
+  [y, u, v]
+}
+
+fn is_similar([y_0, u_0, v_0]: Yuv, [y_1, u_1, v_1]: Yuv) -> bool {
+  (y_0 - y_1).abs() <= SIMILAR_THRESHOLD_Y
+    && (u_0 - u_1).abs() <= SIMILAR_THRESHOLD_U
+    && (v_0 - v_1).abs() <= SIMILAR_THRESHOLD_V
+}
+
+fn distance([y_0, u_0, v_0]: Yuv, [y_1, u_1, v_1]: Yuv) -> i32 {
+  48 * (y_0 - y_1).abs() + 7 * (u_0 - u_1).abs() + 6 * (v_0 - v_1).abs()
+}
+
+fn blend(weighted: &[(Rgb, u32)]) -> Rgb {
+  let total = weighted.iter().map(|(_, w)| w).sum::<u32>();
+  let mut res = [0u8; 3];
+  for (ch, val) in res.iter_mut().enumerate() {
+    let sum = weighted.iter().map(|(c, w)| u32::from(c[ch]) * w).sum::<u32>();
+    *val = ((sum + total / 2) / total) as u8;
+  }
+  res
+}
+
+#[cfg(test)]
+mod test {
+  use crate::gfx::frame::Frame;
+  use crate::gfx::scaler::Scaler;
+
+  const BLACK: [u8; 3] = [0, 0, 0];
+  const WHITE: [u8; 3] = [255, 255, 255];
+
+  fn frame_from(width: u32, height: u32, pixels: &[[u8; 3]]) -> Frame {
+    Frame {
+      width,
+      height,
+      pixels: pixels.iter().flat_map(|p| *p).collect(),
+    }
+  }
+
+  fn diagonal() -> Frame {
+    frame_from(3, 3, &[
+      WHITE, BLACK, BLACK,
+      BLACK, WHITE, BLACK,
+      BLACK, BLACK, WHITE,
+    ])
+  }
+
+  #[test]
+  fn scaler_output_size() {
+    let src = diagonal();
+    for scaler in [Scaler::None, Scaler::Scale2x, Scaler::Scale3x, Scaler::Blend2x, Scaler::Xbr2x] {
+      let dst = scaler.apply(&src);
+      assert_eq!(dst.width, 3 * scaler.factor());
+      assert_eq!(dst.height, 3 * scaler.factor());
+      assert_eq!(dst.pixels.len(), (dst.width * dst.height * 3) as usize);
+    }
+  }
+
+  #[test]
+  fn flat_color_stays_flat() {
+    let src = frame_from(2, 2, &[WHITE; 4]);
+    for scaler in [Scaler::Scale2x, Scaler::Scale3x, Scaler::Blend2x, Scaler::Xbr2x] {
+      assert!(scaler.apply(&src).pixels.iter().all(|p| *p == 255));
+    }
+  }
+
+  #[test]
+  fn scale2x_smooths_diagonal() {
+    let dst = Scaler::Scale2x.apply(&diagonal());
+
+    // Center of the line has no matching neighbours and stays solid
+    assert_eq!(dst.get_pixel(2, 2), WHITE);
+    assert_eq!(dst.get_pixel(3, 3), WHITE);
+    // Black pixel next to the line gets its inner corner filled in
+    assert_eq!(dst.get_pixel(2, 0), BLACK);
+    assert_eq!(dst.get_pixel(2, 1), WHITE);
+    assert_eq!(dst.get_pixel(3, 1), BLACK);
+  }
+
+  #[test]
+  fn scale3x_keeps_center() {
+    let dst = Scaler::Scale3x.apply(&diagonal());
+
+    assert_eq!(dst.get_pixel(4, 4), WHITE);
+    assert_eq!(dst.get_pixel(0, 0), WHITE);
+    assert_eq!(dst.get_pixel(8, 8), WHITE);
+    assert_eq!(dst.get_pixel(8, 0), BLACK);
+  }
+
+  #[test]
+  fn blend2x_blends_isolated_pixel() {
+    let mut pixels = [BLACK; 9];
+    pixels[4] = WHITE;
+    let dst = Scaler::Blend2x.apply(&frame_from(3, 3, &pixels));
+
+    let corner = dst.get_pixel(2, 2);
+    assert!(corner[0] > 0 && corner[0] < 255);
+  }
+
+  #[test]
+  fn xbr2x_fills_staircase() {
+    let src = frame_from(4, 4, &[
+      BLACK, BLACK, BLACK, BLACK,
+      BLACK, BLACK, BLACK, WHITE,
+      BLACK, BLACK, WHITE, WHITE,
+      BLACK, WHITE, WHITE, WHITE,
+    ]);
+    let dst = Scaler::Xbr2x.apply(&src);
+
+    // Outer corner of a step is blended halfway towards the edge color
+    assert_eq!(dst.get_pixel(5, 3), [128, 128, 128]);
+    assert_eq!(dst.get_pixel(5, 2), BLACK);
+    assert_eq!(dst.get_pixel(0, 0), BLACK);
+    assert_eq!(dst.get_pixel(7, 7), WHITE);
+  }
+}
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::{ImageFormat, ImageResult, RgbImage};
+
+use crate::gfx::frame::Frame;
+
+/// Writes the frame as PNG. `Frame` rows are bottom-up so they're flipped back to image order.
+pub fn save_png(frame: &Frame, path: &Path) -> ImageResult<()> {
+  let row_len = (frame.width * 3) as usize;
+  let pixels = frame.pixels.chunks(row_len).rev().flatten().copied().collect::<Vec<u8>>();
+  let image = RgbImage::from_raw(frame.width, frame.height, pixels).expect("Frame size mismatch");
+  image.save_with_format(path, ImageFormat::Png)
+}
+
+/// `<dir>/<rom name>_<UTC timestamp>.png`
+pub fn timestamped_path(dir: &Path, rom_file: &str) -> PathBuf {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+  dir.join(format!("{}_{}.png", rom_name(rom_file), format_timestamp(now.as_millis())))
+}
+
+/// `<dir>/<rom name>_frame<N>.png`, stable name for golden images
+pub fn frame_path(dir: &Path, rom_file: &str, frame: u64) -> PathBuf {
+  dir.join(format!("{}_frame{}.png", rom_name(rom_file), frame))
+}
+
+fn rom_name(rom_file: &str) -> String {
+  Path::new(rom_file)
+    .file_stem()
+    .map_or_else(|| "screenshot".to_string(), |stem| stem.to_string_lossy().into_owned())
+}
+
+fn format_timestamp(millis: u128) -> String {
+  let secs = (millis / 1000) as u64;
+  let (year, month, day) = civil_from_days(secs / 86_400);
+  let time = secs % 86_400;
+  format!("{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+          year, month, day, time / 3600, (time / 60) % 60, time % 60, millis % 1000)
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date, Howard Hinnant's algorithm
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+  let z = days + 719_468;
+  let era = z / 146_097;
+  let day_of_era = z - era * 146_097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + u64::from(month <= 2);
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+  use std::path::Path;
+
+  use crate::gfx::frame::Frame;
+  use crate::gfx::screenshot::{format_timestamp, frame_path, save_png};
+
+  #[test]
+  fn timestamp_format() {
+    assert_eq!(format_timestamp(0), "19700101-000000-000");
+    assert_eq!(format_timestamp(1_700_000_000_123), "20231114-221320-123");
+  }
+
+  #[test]
+  fn frame_path_uses_rom_name() {
+    let path = frame_path(Path::new("out"), "roms/smb.nes", 120);
+    assert_eq!(path, Path::new("out").join("smb_frame120.png"));
+  }
+
+  #[test]
+  fn png_is_written_top_down() {
+    let mut frame = Frame::new(2, 2);
+    // Bottom-up storage, so row 1 is the top of the image
+    frame.set_pixel(0, 1, [255, 0, 0]);
+    let path = std::env::temp_dir().join("nes-emulator-screenshot-test.png");
+    save_png(&frame, &path).unwrap();
+
+    let image = image::open(&path).unwrap().to_rgb8();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0]);
+    assert_eq!(image.get_pixel(0, 1).0, [0, 0, 0]);
+  }
+}
@@ -6,10 +6,17 @@ extern crate image;
 
 
 use std::env;
+use std::path::PathBuf;
 
 use getopts::Options;
 
+use crate::apu::audio_sink::AudioOutput;
+use crate::apu::channel_mix::{Channel, MixSettings};
+use crate::gfx::ntsc::NtscPreset;
+use crate::gfx::scaler::Scaler;
+use crate::nes::config::Config;
 use crate::nes::Nes;
+use crate::nes::region::Region;
 
 mod apu;
 mod bus;
@@ -17,8 +24,10 @@ mod cartridge;
 mod cpu;
 mod mapper;
 mod nes;
+mod nsf;
 mod ppu;
 mod gfx;
+mod recorder;
 
 fn main() {
   let args: Vec<String> = env::args().collect();
@@ -28,13 +37,30 @@ fn main() {
   opts.optflag("h", "help", "print help");
   opts.optflag("d", "debug", "show memory debug");
   opts.optflag("v", "version", "print version number");
+  opts.optopt("f", "filter", "NTSC video filter", "composite|svideo|rgb");
+  opts.optopt("s", "scaler", "pixel-art upscaler", "none|scale2x|scale3x|blend2x|xbr2x");
+  opts.optopt("", "region", "override console region", "ntsc|pal|dendy");
+  opts.optopt("", "screenshot-dir", "directory for screenshots", "DIR");
+  opts.optopt("", "screenshot-frame", "save screenshot of frame N without window and exit", "N");
+  opts.optopt("", "record", "record video to PATH.y4m and audio to PATH.wav", "PATH");
+  opts.optopt("", "frames", "run N frames without window and exit", "N");
+  opts.optflag("", "dot-accurate", "render every PPU dot instead of whole scanlines");
+  opts.optopt("", "sample-rate", "audio output rate in Hz", "44100|48000");
+  opts.optopt("", "audio-wav", "write audio to a WAV file instead of the sound device", "PATH");
+  opts.optflag("", "no-audio", "disable audio output");
+  opts.optopt("", "track", "track of an NSF music file to play", "N");
+  opts.optopt("", "vgm", "log sound register writes to a VGM file", "PATH");
+  opts.optopt("", "mute", "mute sound channels", "CHANNEL,...");
+  opts.optopt("", "solo", "play only one sound channel", "CHANNEL");
+  opts.optopt("", "volume", "sound channel volumes from 0", "CHANNEL=VOLUME,...");
+  opts.optopt("", "pan", "sound channel panning from -1 (left) to 1 (right)", "CHANNEL=PAN,...");
   let matches = match opts.parse(&args[1..]) {
     Ok(m) => m,
     Err(e) => panic!("{}", e.to_string()),
   };
 
   if matches.opt_present("h") {
-    println!("USAGE:\nnes-emulator [FLAGS]\n\nFLAGS:\n-h, --help\t\t\tPrints help information\n-v, --version\t\t\tPrints version information\n-r, --rom\t\t\tRom filename to load\n-d, --debug\t\t\tShow memory debug on terminal");
+    println!("USAGE:\nnes-emulator [FLAGS]\n\nFLAGS:\n-h, --help\t\t\tPrints help information\n-v, --version\t\t\tPrints version information\n-r, --rom\t\t\tRom filename to load\n-d, --debug\t\t\tShow memory debug on terminal\n-f, --filter <preset>\t\tNTSC video filter: composite, svideo or rgb\n-s, --scaler <name>\t\tUpscaler: none, scale2x, scale3x, blend2x or xbr2x\n    --region <region>\t\tConsole region: ntsc, pal or dendy, from the ROM header or file name by default\n    --screenshot-dir <dir>\tDirectory for screenshots, defaults to current directory\n    --screenshot-frame <n>\tRun without window, save screenshot of frame n and exit\n    --record <path>\t\tRecord video to <path>.y4m and audio to <path>.wav\n    --frames <n>\t\tRun n frames without window and exit\n    --dot-accurate\t\tRender every PPU dot instead of catching up whole scanlines\n    --sample-rate <hz>\t\tAudio output rate: 44100 or 48000, defaults to 44100\n    --audio-wav <path>\t\tWrite audio to a WAV file instead of the sound device\n    --no-audio\t\t\tDisable audio output, the default without window\n    --track <n>\t\t\tTrack of an NSF music file to play, from 1\n    --vgm <path>\t\t\tLog sound register writes to a VGM file\n    --mute <channels>\t\tMute sound channels, like pulse_0,noise\n    --solo <channel>\t\tPlay only one sound channel\n    --volume <levels>\t\tSound channel volumes, like triangle=1.5,dmc=0.5\n    --pan <positions>\t\tSound channel panning from -1 to 1, like pulse_0=-0.5,pulse_1=0.5\n\nCHANNELS:\npulse_0, pulse_1, triangle, noise, dmc, expansion");
     return;
   }
 
@@ -49,9 +75,81 @@ fn main() {
     panic!("No ROM file parameter given")
   };
 
-  let use_debug_mode = matches.opt_present("d");
-  let mut nes = Nes::new(&rom_file, use_debug_mode);
+  let video_filter = matches.opt_str("f").map(|name| {
+    NtscPreset::from_name(&name).unwrap_or_else(|| panic!("Unknown video filter {}", name))
+  });
+
+  let scaler = matches.opt_str("s").map_or(Scaler::None, |name| {
+    Scaler::from_name(&name).unwrap_or_else(|| panic!("Unknown scaler {}", name))
+  });
+
+  let region = matches.opt_str("region").map(|name| {
+    Region::from_name(&name).unwrap_or_else(|| panic!("Unknown region {}", name))
+  });
+
+  let screenshot_dir = matches.opt_str("screenshot-dir").map_or_else(PathBuf::new, PathBuf::from);
+
+  let screenshot_frame = matches.opt_str("screenshot-frame").map(|frame| {
+    frame.parse::<u64>().unwrap_or_else(|_| panic!("Invalid screenshot frame {}", frame))
+  });
+
+  let record_path = matches.opt_str("record").map(PathBuf::from);
+
+  let frame_limit = matches.opt_str("frames").map(|frames| {
+    frames.parse::<u64>().unwrap_or_else(|_| panic!("Invalid frame count {}", frames))
+  });
+
+  let sample_rate = matches.opt_str("sample-rate").map(|rate| match rate.as_str() {
+    "44100" => 44100,
+    "48000" => 48000,
+    _ => panic!("Unsupported sample rate {}", rate),
+  });
+
+  let track = matches.opt_str("track").map(|track| {
+    track.parse::<u8>().ok().filter(|&track| track > 0).unwrap_or_else(|| panic!("Invalid track {}", track))
+  });
+
+  let parse_channel = |name: &str| Channel::from_name(name).unwrap_or_else(|| panic!("Unknown channel {}", name));
+  let parse_values = |spec: String| MixSettings::parse_channel_values(&spec).unwrap_or_else(|e| panic!("{}", e));
+  let mut mix_settings = MixSettings::default();
+  for name in matches.opt_str("mute").iter().flat_map(|names| names.split(',')) {
+    mix_settings.set_muted(parse_channel(name.trim()), true);
+  }
+  mix_settings.set_solo(matches.opt_str("solo").map(|name| parse_channel(&name)));
+  for (channel, volume) in matches.opt_str("volume").map(parse_values).unwrap_or_default() {
+    mix_settings.set_volume(channel, volume);
+  }
+  for (channel, pan) in matches.opt_str("pan").map(parse_values).unwrap_or_default() {
+    mix_settings.set_pan(channel, pan);
+  }
+
+  let mut config = Config {
+    is_dbg: matches.opt_present("d"),
+    video_filter,
+    scaler,
+    region,
+    screenshot_dir,
+    screenshot_frame,
+    record_path,
+    frame_limit,
+    is_dot_accurate: matches.opt_present("dot-accurate"),
+    sample_rate,
+    track,
+    vgm_path: matches.opt_str("vgm").map(PathBuf::from),
+    mix_settings,
+    ..Config::default()
+  };
+  config.audio_output = match matches.opt_str("audio-wav") {
+    Some(path) => AudioOutput::Wav(PathBuf::from(path)),
+    None if matches.opt_present("no-audio") || config.is_headless() => AudioOutput::None,
+    None => AudioOutput::Device,
+  };
+  let mut nes = Nes::new(&rom_file, &config);
 
   nes.reset();
-  nes.render_loop();
+  if config.is_headless() {
+    nes.run_headless();
+  } else {
+    nes.render_loop();
+  }
 }
@@ -24,6 +24,37 @@ enum ChrMode {
   NonConsecutive,
 }
 
+/// SxROM boards that reuse the upper CHR bank bits for PRG ROM and PRG RAM address lines
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Board {
+  /// SNROM, SKROM and the other boards up to 256 KiB PRG ROM and 8 KiB PRG RAM
+  Standard,
+  /// 16 KiB PRG RAM, CHR bit 3 selects the 8 KiB bank
+  Sorom,
+  /// 512 KiB PRG ROM, CHR bit 4 selects the 256 KiB half
+  Surom,
+  /// 512 KiB PRG ROM and 32 KiB PRG RAM, CHR bit 4 selects the PRG half and bits 2-3 the RAM bank
+  Sxrom,
+  /// SEROM, SHROM and SH1ROM, 32 KiB PRG ROM without PRG banking
+  Serom,
+}
+
+impl Board {
+  // NES 2.0 submappers first, the deprecated ones included, then the board implied by the memory sizes
+  fn detect(submapper: u8, prg_rom_len: usize, prg_ram_len: usize) -> Board {
+    match submapper {
+      1 => Board::Surom,
+      2 => Board::Sorom,
+      4 => Board::Sxrom,
+      5 => Board::Serom,
+      _ if prg_rom_len > 0x40000 && prg_ram_len > 0x4000 => Board::Sxrom,
+      _ if prg_rom_len > 0x40000 => Board::Surom,
+      _ if prg_ram_len > 0x2000 => Board::Sorom,
+      _ => Board::Standard,
+    }
+  }
+}
+
 bitfield!{
     #[derive(Copy, Clone)]
     struct CtrlReg(u8);
@@ -36,6 +67,8 @@ bitfield!{
 impl CtrlReg {
   fn mirroring(&self) -> Mirroring {
     match self.nt_mode_id() {
+      0 => Mirroring::SingleScreenLower,
+      1 => Mirroring::SingleScreenUpper,
       2 => Mirroring::Vertical,
       3 => Mirroring::Horizontal,
       _ => panic!("Invalid mirroring mode"),
@@ -79,17 +112,13 @@ impl ShiftReg {
   }
 
   fn push(&mut self, n: u8) -> Option<u8> {
-    if n >> 7 == 1 {
+    self.val |= (n & 1) << self.idx;
+    if self.idx == 4 {
+      let result = self.val;
       self.reset();
-    } else {
-      self.val |= (n & 1) << self.idx;
-      if self.idx == 4 {
-        let result = self.val;
-        self.reset();
-        return Some(result);
-      }
-      self.idx += 1;
+      return Some(result);
     }
+    self.idx += 1;
     None
   }
 }
@@ -97,96 +126,155 @@ impl ShiftReg {
 #[derive(Clone)]
 pub(crate) struct Mapper1 {
   rom: Rc<RefCell<RomData>>,
+  board: Board,
   shift_reg: ShiftReg,
   control_reg: CtrlReg,
   prg_0: usize,
   chr_0: usize,
   chr_1: usize,
+  is_prg_ram_enabled: bool,
+  // CPU cycles counted to spot the back to back writes of read-modify-write instructions
+  cycle: u64,
+  last_write_cycle: Option<u64>,
 }
 
 impl Mapper1 {
   pub fn new(rom: Rc<RefCell<RomData>>) -> Self {
+    let header = rom.borrow().rom_header;
+    let board = Board::detect(header.submapper, header.prg_rom_len, header.prg_ram_len);
+
     Mapper1 {
       rom,
+      board,
       shift_reg: ShiftReg::new(),
-      control_reg: CtrlReg(0x0E),
+      control_reg: CtrlReg(0x0C),
       chr_0: 0,
       chr_1: 0,
       prg_0: 0,
+      is_prg_ram_enabled: true,
+      cycle: 0,
+      last_write_cycle: None,
     }
   }
 
-  fn get_rom(&self) -> Ref<RomData> {
+  fn get_rom(&self) -> Ref<'_, RomData> {
     self.rom.borrow()
   }
 
-  fn get_mut_rom(&self) -> RefMut<RomData> {
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
     self.rom.borrow_mut()
   }
 
+  // The serial port ignores a write on the cycle after another one, so only the dummy write of a read-modify-write
+  // instruction counts. The CPU makes both writes in the same step, hence the same cycle is ignored too.
   fn write_shift(&mut self, address: u16, value: u8) {
-    if let Some(shift_value) = self.shift_reg.push(value) {
+    let is_consecutive = self.last_write_cycle.is_some_and(|cycle| self.cycle <= cycle + 1);
+    self.last_write_cycle = Some(self.cycle);
+    if is_consecutive {
+      return;
+    }
+
+    if value & 0x80 > 0 {
+      self.shift_reg.reset();
+      self.control_reg = CtrlReg(self.control_reg.0 | 0x0C);
+    } else if let Some(shift_value) = self.shift_reg.push(value) {
       match address {
         0x8000..=0x9FFF => self.control_reg = CtrlReg(shift_value),
         0xA000..=0xBFFF => self.chr_0 = shift_value as usize & 0x1F,
         0xC000..=0xDFFF => self.chr_1 = shift_value as usize & 0x1F,
-        0xE000..=0xFFFF => self.prg_0 = shift_value as usize & 0x0F,
+        0xE000..=0xFFFF => {
+          self.prg_0 = shift_value as usize & 0x0F;
+          self.is_prg_ram_enabled = shift_value & 0x10 == 0;
+        }
         _ => panic!("Invalid write_shift address 0x{:04X}", address),
       }
     }
   }
 
-  fn read_paged_prg_ram(&self, offset: u16) -> u8 {
-    self.get_rom()
-      .prg_ram
-      .read(Page::First(PageSize::Eight), offset)
+  // The CHR bank 0 register drives the extra address lines, in 4 KiB CHR mode as well
+  fn prg_ram_page(&self) -> Option<Page> {
+    let is_disabled_by_chr = self.board == Board::Standard && self.get_rom().rom_header.chr_rom_len == 0 && self.chr_0 & 0x10 > 0;
+    if !self.is_prg_ram_enabled || is_disabled_by_chr {
+      return None;
+    }
+
+    let bank = match self.board {
+      Board::Sorom => (self.chr_0 >> 3) & 0x01,
+      Board::Sxrom => (self.chr_0 >> 2) & 0x03,
+      _ => 0,
+    };
+    let bank_count = (self.get_rom().prg_ram.data.len() / PageSize::Eight.value()).max(1);
+    Some(Page::FromNth(bank % bank_count, PageSize::Eight))
+  }
+
+  fn read_paged_prg_ram(&self, address: u16) -> u8 {
+    match self.prg_ram_page() {
+      Some(page) => self.get_rom().prg_ram.read(page, address - 0x6000),
+      // Open bus
+      None => (address >> 8) as u8,
+    }
   }
 
   fn write_paged_prg_ram(&mut self, offset: u16, value: u8) {
-    self.get_mut_rom()
-      .prg_ram
-      .write(Page::First(PageSize::Eight), offset, value);
+    if let Some(page) = self.prg_ram_page() {
+      self.get_mut_rom().prg_ram.write(page, offset, value);
+    }
   }
 
   fn get_page(&self, address_range: AddressRange) -> Page {
-    match self.control_reg.chr_mode() {
+    let bank = match self.control_reg.chr_mode() {
       ChrMode::Consecutive => match address_range {
-        AddressRange::Lo => Page::FromNth(self.chr_0, PageSize::Four),
-        AddressRange::Hi => Page::FromNth(self.chr_0 + 1, PageSize::Four),
+        AddressRange::Lo => self.chr_0 & !1,
+        AddressRange::Hi => self.chr_0 | 1,
       },
       ChrMode::NonConsecutive => match address_range {
-        AddressRange::Lo => Page::FromNth(self.chr_0, PageSize::Four),
-        AddressRange::Hi => Page::FromNth(self.chr_1, PageSize::Four),
+        AddressRange::Lo => self.chr_0,
+        AddressRange::Hi => self.chr_1,
       },
-    }
+    };
+    // CHR RAM boards use the upper bits for other purposes
+    let rom = self.get_rom();
+    let chr_len = if rom.rom_header.chr_rom_len == 0 { rom.chr_ram.data.len() } else { rom.chr_rom.data.len() };
+    Page::FromNth(bank % (chr_len / PageSize::Four.value()), PageSize::Four)
   }
 
   fn write_paged_chr_ram(&mut self, address_range: AddressRange, offset: u16, value: u8) {
-    self.get_mut_rom().chr_ram.write(self.get_page(address_range), offset, value)
+    let page = self.get_page(address_range);
+    self.get_mut_rom().chr_ram.write(page, offset, value)
   }
 
   fn read_paged_prg_rom(&self, address_range: AddressRange, offset: u16) -> u8 {
-    let page = match self.control_reg.prg_mode() {
+    let bank = match self.control_reg.prg_mode() {
+      _ if self.board == Board::Serom => match address_range {
+        AddressRange::Lo => 0,
+        AddressRange::Hi => 1,
+      },
       PrgMode::FixFirst => match address_range {
-        AddressRange::Lo => Page::First(PageSize::Sixteen),
-        AddressRange::Hi => Page::FromNth(self.prg_0, PageSize::Sixteen),
+        AddressRange::Lo => 0,
+        AddressRange::Hi => self.prg_0,
       },
       PrgMode::FixLast => match address_range {
-        AddressRange::Lo => Page::FromNth(self.prg_0, PageSize::Sixteen),
-        AddressRange::Hi => Page::Last(PageSize::Sixteen),
+        AddressRange::Lo => self.prg_0,
+        AddressRange::Hi => 0x0F,
       },
       PrgMode::Consecutive => match address_range {
-        AddressRange::Lo => Page::FromNth(self.prg_0 & !1, PageSize::Sixteen),
-        AddressRange::Hi => Page::FromNth(self.prg_0 | 1, PageSize::Sixteen),
+        AddressRange::Lo => self.prg_0 & !1,
+        AddressRange::Hi => self.prg_0 | 1,
       },
     };
-    self.get_rom().prg_rom.read(page, offset)
+    let outer_bank = match self.board {
+      Board::Surom | Board::Sxrom => self.chr_0 & 0x10,
+      _ => 0,
+    };
+    let rom = self.get_rom();
+    let bank_count = rom.prg_rom.data.len() / PageSize::Sixteen.value();
+    rom.prg_rom.read(Page::FromNth((outer_bank | bank) % bank_count, PageSize::Sixteen), offset)
   }
 
   fn read_paged_chr_rom(&self, address_range: AddressRange, offset: u16) -> u8 {
     let page = self.get_page(address_range);
 
-    if self.get_mut_rom().rom_header.chr_rom_len == 0 {
+    if self.get_rom().rom_header.chr_rom_len == 0 {
       self.get_rom().chr_ram.read(page, offset)
     } else {
       self.get_rom().chr_rom.read(page, offset)
@@ -197,7 +285,7 @@ impl Mapper1 {
 impl Mapper for Mapper1 {
   fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
     match address {
-      0x6000..=0x7FFF => self.read_paged_prg_ram(address - 0x6000),
+      0x6000..=0x7FFF => self.read_paged_prg_ram(address),
       0x8000..=0xBFFF => self.read_paged_prg_rom(AddressRange::Lo, address - 0x8000),
       0xC000..=0xFFFF => self.read_paged_prg_rom(AddressRange::Hi, address - 0xC000),
       _ => panic!("Invalid mapped_read_cpu_u8 address 0x{:04X}", address),
@@ -233,4 +321,138 @@ impl Mapper for Mapper1 {
   fn mirroring(&self) -> Mirroring {
     self.control_reg.mirroring()
   }
+
+  fn clock_cpu(&mut self) {
+    self.cycle += 1;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::cartridge::rom_reading::{Mirroring, Rom};
+  use crate::cartridge::rom_with_pager::RomData;
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper1::Mapper1;
+
+  // CHR RAM board, each 16 KiB PRG bank filled with its number
+  fn mapper(prg_banks: usize, prg_ram_len: usize, submapper: u8) -> Mapper1 {
+    let mut rom = Rom::mock_rom();
+    rom.rom_header.prg_rom_len = prg_banks * 0x4000;
+    rom.rom_header.prg_ram_len = prg_ram_len;
+    rom.rom_header.chr_rom_len = 0;
+    rom.rom_header.submapper = submapper;
+    rom.prg_rom = (0..prg_banks).flat_map(|bank| vec![bank as u8; 0x4000]).collect();
+    rom.prg_ram = vec![0; prg_ram_len];
+    rom.chr_rom = Vec::new();
+    Mapper1::new(Rc::new(RefCell::new(RomData::new(rom))))
+  }
+
+  // Five serial writes, one per cycle apart
+  fn write_register(mapper: &mut Mapper1, address: u16, value: u8) {
+    for bit in 0..5 {
+      mapper.clock_cpu();
+      mapper.clock_cpu();
+      mapper.mapped_write_cpu_u8(address, value >> bit);
+    }
+  }
+
+  #[test]
+  fn mirroring_and_prg_modes() {
+    let mut mapper = mapper(8, 0x2000, 0);
+    // Power up in fix last mode
+    assert_eq!(mapper.mapped_read_cpu_u8(0xC000), 7);
+
+    write_register(&mut mapper, 0x8000, 0x00);
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+    write_register(&mut mapper, 0x8000, 0x01);
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+    // 32 KiB mode ignores the low bit
+    write_register(&mut mapper, 0xE000, 0x05);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_cpu_u8(0xC000)), (4, 5));
+
+    // Fix first mode
+    write_register(&mut mapper, 0x8000, 0x08);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_cpu_u8(0xC000)), (0, 5));
+
+    // A reset write goes back to fix last mode
+    mapper.clock_cpu();
+    mapper.clock_cpu();
+    mapper.mapped_write_cpu_u8(0x8000, 0x80);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_cpu_u8(0xC000)), (5, 7));
+  }
+
+  #[test]
+  fn prg_ram_enable() {
+    let mut mapper = mapper(2, 0x2000, 0);
+    mapper.mapped_write_cpu_u8(0x6000, 0x42);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x42);
+
+    write_register(&mut mapper, 0xE000, 0x10);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x60);
+    mapper.mapped_write_cpu_u8(0x6000, 0x00);
+    write_register(&mut mapper, 0xE000, 0x00);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x42);
+
+    // SNROM disables it with bit 4 of the CHR register too
+    write_register(&mut mapper, 0xA000, 0x10);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x60);
+  }
+
+  #[test]
+  fn consecutive_writes_are_ignored() {
+    let mut mapper = mapper(8, 0x2000, 0);
+    // The dummy write of INC resets the shift register, the write of the result on the next cycle is ignored
+    mapper.clock_cpu();
+    mapper.mapped_write_cpu_u8(0x8000, 0xFF);
+    mapper.clock_cpu();
+    mapper.mapped_write_cpu_u8(0x8000, 0x00);
+    write_register(&mut mapper, 0xE000, 0x03);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x8000), 3);
+  }
+
+  #[test]
+  fn surom_and_sxrom_banks() {
+    let mut surom = mapper(32, 0x2000, 0);
+    write_register(&mut surom, 0xE000, 0x02);
+    assert_eq!((surom.mapped_read_cpu_u8(0x8000), surom.mapped_read_cpu_u8(0xC000)), (2, 15));
+    write_register(&mut surom, 0xA000, 0x10);
+    assert_eq!((surom.mapped_read_cpu_u8(0x8000), surom.mapped_read_cpu_u8(0xC000)), (18, 31));
+
+    // Selected by submapper, PRG RAM banks from bits 2-3
+    let mut sxrom = mapper(32, 0x8000, 4);
+    write_register(&mut sxrom, 0xA000, 0x18);
+    assert_eq!(sxrom.mapped_read_cpu_u8(0xC000), 31);
+    sxrom.mapped_write_cpu_u8(0x6000, 0x22);
+    write_register(&mut sxrom, 0xA000, 0x14);
+    sxrom.mapped_write_cpu_u8(0x6000, 0x11);
+    assert_eq!(sxrom.mapped_read_cpu_u8(0x6000), 0x11);
+    write_register(&mut sxrom, 0xA000, 0x08);
+    assert_eq!(sxrom.mapped_read_cpu_u8(0x6000), 0x22);
+  }
+
+  #[test]
+  fn sorom_ram_banks() {
+    let mut sorom = mapper(16, 0x4000, 0);
+    sorom.mapped_write_cpu_u8(0x6000, 0x11);
+    write_register(&mut sorom, 0xA000, 0x08);
+    assert_eq!(sorom.mapped_read_cpu_u8(0x6000), 0x00);
+    sorom.mapped_write_cpu_u8(0x6000, 0x22);
+    write_register(&mut sorom, 0xA000, 0x00);
+    assert_eq!(sorom.mapped_read_cpu_u8(0x6000), 0x11);
+  }
+
+  #[test]
+  fn nes_2_small_prg_ram() {
+    // NES 2.0 header with 2 KiB of PRG RAM
+    let mut bytes = b"NES\x1A\x02\x00\x10\x08\x00\x00\x05\x00\x00\x00\x00\x00".to_vec();
+    bytes.extend(vec![0u8; 0x8000]);
+    let rom = Rom::read_from_file(bytes.into_iter());
+    let mut mapper = Mapper1::new(Rc::new(RefCell::new(RomData::new(rom))));
+    mapper.mapped_write_cpu_u8(0x6000, 0x42);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x42);
+  }
 }
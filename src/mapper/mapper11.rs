@@ -0,0 +1,79 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, read_prg_32k, write_chr};
+use crate::mapper::pager::PageSize::Eight;
+
+/// Color Dreams, bits 0-1 of the register pick the 32 KiB PRG bank and bits 4-7 the 8 KiB CHR bank
+#[derive(Clone)]
+pub(crate) struct Mapper11 {
+  prg_bank: usize,
+  chr_bank: usize,
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper11 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper11 {
+    let mirroring = rom.borrow().rom_header.mirroring;
+
+    Mapper11 {
+      prg_bank: 0,
+      chr_bank: 0,
+      mirroring,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for Mapper11 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    read_prg_32k(&self.get_rom(), self.prg_bank, address)
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    if address >= 0x8000 {
+      self.prg_bank = usize::from(data & 0x03);
+      self.chr_bank = usize::from(data >> 4);
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    read_chr(&self.get_rom(), self.chr_bank, Eight, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    write_chr(&mut self.get_mut_rom(), self.chr_bank, Eight, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper11::Mapper11;
+  use crate::mapper::pager::PageSize::{Eight, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn prg_and_chr_banks() {
+    let mut mapper = Mapper11::new(numbered_rom(11, 4, ThirtyTwo, 16, Eight));
+    mapper.mapped_write_cpu_u8(0x8000, 0xD3);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_ppu_u8(0x1FFF)), (3, 13));
+    mapper.mapped_write_cpu_u8(0xC000, 0x21);
+    assert_eq!((mapper.mapped_read_cpu_u8(0xFFFF), mapper.mapped_read_ppu_u8(0x0000)), (1, 2));
+  }
+}
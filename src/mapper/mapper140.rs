@@ -0,0 +1,83 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, read_prg_32k, write_chr};
+use crate::mapper::pager::PageSize::Eight;
+
+/// Jaleco JF-11 and JF-14, the register at 0x6000-0x7FFF picks the 32 KiB PRG bank from bits 4-5 and the 8 KiB CHR
+/// bank from bits 0-3
+#[derive(Clone)]
+pub(crate) struct Mapper140 {
+  prg_bank: usize,
+  chr_bank: usize,
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper140 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper140 {
+    let mirroring = rom.borrow().rom_header.mirroring;
+
+    Mapper140 {
+      prg_bank: 0,
+      chr_bank: 0,
+      mirroring,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for Mapper140 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    read_prg_32k(&self.get_rom(), self.prg_bank, address)
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    if let 0x6000..=0x7FFF = address {
+      self.prg_bank = usize::from(data >> 4 & 0x03);
+      self.chr_bank = usize::from(data & 0x0F);
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    read_chr(&self.get_rom(), self.chr_bank, Eight, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    write_chr(&mut self.get_mut_rom(), self.chr_bank, Eight, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper140::Mapper140;
+  use crate::mapper::pager::PageSize::{Eight, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn prg_and_chr_banks() {
+    let mut mapper = Mapper140::new(numbered_rom(140, 4, ThirtyTwo, 16, Eight));
+    mapper.mapped_write_cpu_u8(0x6000, 0x3B);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_ppu_u8(0x0000)), (3, 11));
+    mapper.mapped_write_cpu_u8(0x7FFF, 0x14);
+    assert_eq!((mapper.mapped_read_cpu_u8(0xFFFF), mapper.mapped_read_ppu_u8(0x1FFF)), (1, 4));
+    // Writes to ROM do nothing
+    mapper.mapped_write_cpu_u8(0x8000, 0x00);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_ppu_u8(0x0000)), (1, 4));
+  }
+}
@@ -0,0 +1,158 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, read_prg_32k, write_chr};
+use crate::mapper::pager::Page;
+use crate::mapper::pager::PageSize::{self, Eight, Four};
+
+/// The two unrelated boards that share mapper 34
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Board {
+  /// 32 KiB PRG bank selected by writes to `$8000-$FFFF`, CHR RAM
+  Bnrom,
+  /// PRG RAM with the 32 KiB PRG bank and two 4 KiB CHR banks selected by `$7FFD-$7FFF`
+  Nina001,
+}
+
+impl Board {
+  // NES 2.0 submappers first, then NINA-001 when there is more CHR ROM than BNROM could ever use
+  fn detect(submapper: u8, chr_rom_len: usize) -> Board {
+    match submapper {
+      1 => Board::Nina001,
+      2 => Board::Bnrom,
+      _ if chr_rom_len > 0x2000 => Board::Nina001,
+      _ => Board::Bnrom,
+    }
+  }
+}
+
+/// BNROM and AVE NINA-001
+#[derive(Clone)]
+pub(crate) struct Mapper34 {
+  board: Board,
+  prg_bank: usize,
+  chr_banks: [usize; 2],
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper34 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper34 {
+    let header = rom.borrow().rom_header;
+
+    Mapper34 {
+      board: Board::detect(header.submapper, header.chr_rom_len),
+      prg_bank: 0,
+      chr_banks: [0, 1],
+      mirroring: header.mirroring,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+
+  fn chr_bank(&self, address: u16) -> (usize, PageSize) {
+    match self.board {
+      Board::Bnrom => (0, Eight),
+      Board::Nina001 => (self.chr_banks[usize::from(address >> 12)], Four),
+    }
+  }
+}
+
+impl Mapper for Mapper34 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    match address {
+      0x6000..=0x7FFF if self.board == Board::Nina001 => self.get_rom().prg_ram.read(Page::First(Eight), address - 0x6000),
+      _ => read_prg_32k(&self.get_rom(), self.prg_bank, address),
+    }
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    match (self.board, address) {
+      (Board::Bnrom, 0x8000..=0xFFFF) => self.prg_bank = usize::from(data),
+      (Board::Nina001, 0x6000..=0x7FFF) => {
+        // The registers sit on top of PRG RAM, which is written too
+        self.get_mut_rom().prg_ram.write(Page::First(Eight), address - 0x6000, data);
+        match address {
+          0x7FFD => self.prg_bank = usize::from(data & 0x01),
+          0x7FFE => self.chr_banks[0] = usize::from(data & 0x0F),
+          0x7FFF => self.chr_banks[1] = usize::from(data & 0x0F),
+          _ => {}
+        }
+      }
+      _ => {}
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    let (bank, size) = self.chr_bank(address);
+    read_chr(&self.get_rom(), bank, size, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    let (bank, size) = self.chr_bank(address);
+    write_chr(&mut self.get_mut_rom(), bank, size, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper34::{Board, Mapper34};
+  use crate::mapper::pager::PageSize::{Eight, Four, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn bnrom_prg_bank() {
+    let mut mapper = Mapper34::new(numbered_rom(34, 8, ThirtyTwo, 0, Eight));
+    assert_eq!(mapper.board, Board::Bnrom);
+    mapper.mapped_write_cpu_u8(0x8000, 6);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_cpu_u8(0xFFFF)), (6, 6));
+    // The register is not at $7FFD on this board
+    mapper.mapped_write_cpu_u8(0x7FFD, 1);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x8000), 6);
+  }
+
+  #[test]
+  fn nina_001_banks_and_prg_ram() {
+    let mut mapper = Mapper34::new(numbered_rom(34, 2, ThirtyTwo, 16, Four));
+    assert_eq!(mapper.board, Board::Nina001);
+    assert_eq!((mapper.mapped_read_ppu_u8(0x0000), mapper.mapped_read_ppu_u8(0x1000)), (0, 1));
+
+    mapper.mapped_write_cpu_u8(0x7FFD, 1);
+    mapper.mapped_write_cpu_u8(0x7FFE, 9);
+    mapper.mapped_write_cpu_u8(0x7FFF, 14);
+    assert_eq!(mapper.mapped_read_cpu_u8(0xC000), 1);
+    assert_eq!((mapper.mapped_read_ppu_u8(0x0FFF), mapper.mapped_read_ppu_u8(0x1000)), (9, 14));
+    assert_eq!(mapper.mapped_read_cpu_u8(0x7FFE), 9);
+
+    // Writes to ROM do nothing
+    mapper.mapped_write_cpu_u8(0x8000, 0);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x8000), 1);
+
+    // Selected by submapper, CHR RAM in place of the missing CHR ROM
+    let rom = numbered_rom(34, 2, ThirtyTwo, 0, Eight);
+    rom.borrow_mut().rom_header.submapper = 1;
+    let mut mapper = Mapper34::new(rom);
+    assert_eq!(mapper.board, Board::Nina001);
+    mapper.mapped_write_ppu_u8(0x1010, 0x42);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x1010), 0x42);
+    mapper.mapped_write_cpu_u8(0x7FFF, 0);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x1010), 0x00);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x0010), 0x00);
+    mapper.mapped_write_cpu_u8(0x7FFE, 1);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x0010), 0x42);
+  }
+}
@@ -1,81 +1,250 @@
 use std::cell::{Ref, RefCell, RefMut};
 use std::rc::Rc;
 
-use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_reading::{Mirroring, NameTableSource};
 use crate::cartridge::rom_with_pager::RomData;
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, PpuBusAccess, PpuBusWatch};
 use crate::mapper::pager::Page;
 use crate::mapper::pager::PageSize::{Eight, One};
 
+// A12 has to stay low for about three M2 cycles before a rise clocks the IRQ counter, which filters out the short
+// lows between the pattern fetches
+const A12_LOW_DOTS: u64 = 10;
+
+/// How the revisions of the chip trigger the IRQ when the counter reaches zero
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum IrqBehaviour {
+  /// MMC3B, MMC3C and MMC6, every clock that leaves the counter at zero triggers it, a reload value of zero
+  /// included
+  Normal,
+  /// MMC3A, only a decrement to zero or a reload requested through $C001 triggers it
+  Alternate,
+}
+
+/// Boards built on the MMC3 banking core, told apart by the mapper number and the NES 2.0 submapper
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Board {
+  /// TxROM and the other MMC3 boards, with CHR ROM or CHR RAM
+  Mmc3,
+  /// HKROM, 1 KiB of PRG RAM inside the MMC6 with separate enables for its two halves
+  Mmc6,
+  /// TxSROM, mapper 118, bit 7 of the CHR banks picks the nametable of each quadrant
+  TxSrom,
+  /// TQROM, mapper 119, bit 6 of the CHR banks picks 8 KiB of CHR RAM over the CHR ROM
+  Tqrom,
+  /// Namco 108 and DxROM, mapper 206, the banking core alone without IRQ, mirroring control or PRG RAM
+  Namco108,
+}
+
+impl Board {
+  fn detect(mapper: u16, submapper: u8) -> Board {
+    match (mapper, submapper) {
+      (118, _) => Board::TxSrom,
+      (119, _) => Board::Tqrom,
+      (206, _) => Board::Namco108,
+      (_, 1) => Board::Mmc6,
+      _ => Board::Mmc3,
+    }
+  }
+}
+
 #[derive(Clone)]
 pub(crate) struct Mapper4 {
+  board: Board,
   prg_select: bool,
   chr_select: bool,
   registers: [usize; 8],
   index: usize,
   mirroring: Mirroring,
+  // $A001, chip enable and write protect on the MMC3, read and write enables of each half on the MMC6
+  ram_protect: u8,
+  // $8000 bit 5 of the MMC6, needed for any access to its RAM and for writes to $A001
+  is_mmc6_ram_enabled: bool,
   irq_counter: u8,
   irq_period: u8,
+  irq_reload: bool,
   irq_enabled: bool,
   flag_irq: bool,
+  irq_behaviour: IrqBehaviour,
+  // PPU dot A12 went low on, `None` while it is high
+  a12_low_since: Option<u64>,
   rom: Rc<RefCell<RomData>>,
 }
 
 impl Mapper4 {
   pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper4 {
+    let header = rom.borrow().rom_header;
+    let board = Board::detect(header.mapper, header.submapper);
+    // Four-screen boards ignore the mirroring register, Namco 108 boards have it hardwired
+    let mirroring = match header.mirroring {
+      Mirroring::FourScreen => Mirroring::FourScreen,
+      mirroring if board == Board::Namco108 => mirroring,
+      _ => Mirroring::Horizontal,
+    };
+    // NES 2.0 submapper 4 marks the boards with an MMC3A
+    let irq_behaviour = if header.submapper == 4 && board == Board::Mmc3 {
+      IrqBehaviour::Alternate
+    } else {
+      IrqBehaviour::Normal
+    };
+
     Mapper4 {
+      board,
       prg_select: false,
       chr_select: false,
       registers: [0; 8],
       index: 0,
-      mirroring: Mirroring::Horizontal,
+      mirroring,
+      // Left enabled until the game says otherwise, plenty of them never write $A001
+      ram_protect: if board == Board::Mmc6 { 0x00 } else { 0x80 },
+      is_mmc6_ram_enabled: false,
       irq_counter: 0,
       irq_period: 0,
+      irq_reload: false,
       irq_enabled: false,
       flag_irq: false,
+      irq_behaviour,
+      a12_low_since: Some(0),
       rom,
     }
   }
 
-  fn get_rom(&self) -> Ref<RomData> {
+  fn get_rom(&self) -> Ref<'_, RomData> {
     self.rom.borrow()
   }
 
-  fn get_mut_rom(&self) -> RefMut<RomData> {
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
     self.rom.borrow_mut()
   }
+
+  fn clock_irq_counter(&mut self) {
+    let (counter, is_reload) = (self.irq_counter, self.irq_reload);
+    if self.irq_counter == 0 || self.irq_reload {
+      self.irq_counter = self.irq_period;
+      self.irq_reload = false;
+    } else {
+      self.irq_counter -= 1;
+    }
+
+    let is_triggered = match self.irq_behaviour {
+      IrqBehaviour::Normal => self.irq_counter == 0,
+      IrqBehaviour::Alternate => self.irq_counter == 0 && (counter > 0 || is_reload),
+    };
+    if is_triggered && self.irq_enabled {
+      self.flag_irq = true;
+    }
+  }
+
+  fn read_prg_rom(&self, bank: usize, offset: u16) -> u8 {
+    let rom = self.get_rom();
+    let bank_count = rom.prg_rom.data.len() / Eight.value();
+    rom.prg_rom.read(Page::FromNth(bank % bank_count, Eight), offset)
+  }
+
+  // Read and write enables of the PRG RAM at the address
+  fn prg_ram_access(&self, address: u16) -> (bool, bool) {
+    match self.board {
+      Board::Namco108 => (false, false),
+      Board::Mmc6 if address < 0x7000 || !self.is_mmc6_ram_enabled => (false, false),
+      Board::Mmc6 => {
+        let enables = if address & 0x0200 > 0 { self.ram_protect >> 6 } else { self.ram_protect >> 4 };
+        // A half that can't be read can't be written either
+        (enables & 0x02 > 0, enables & 0x03 == 0x03)
+      }
+      _ => (self.ram_protect & 0x80 > 0, self.ram_protect & 0xC0 == 0x80),
+    }
+  }
+
+  fn read_prg_ram(&self, address: u16) -> u8 {
+    let (is_readable, _) = self.prg_ram_access(address);
+    match self.board {
+      Board::Mmc6 if is_readable => self.get_rom().prg_ram.read(Page::First(One), address & 0x03FF),
+      // The disabled half reads back zeros as long as the other one is readable
+      Board::Mmc6 if self.prg_ram_access(address ^ 0x0200).0 => 0,
+      _ if is_readable => self.get_rom().prg_ram.read(Page::First(Eight), address - 0x6000),
+      // Open bus
+      _ => (address >> 8) as u8,
+    }
+  }
+
+  fn write_prg_ram(&mut self, address: u16, data: u8) {
+    match self.prg_ram_access(address) {
+      (_, true) if self.board == Board::Mmc6 => self.get_mut_rom().prg_ram.write(Page::First(One), address & 0x03FF, data),
+      (_, true) => self.get_mut_rom().prg_ram.write(Page::First(Eight), address - 0x6000, data),
+      _ => (),
+    }
+  }
+
+  // Value of the 1 KiB CHR bank register of the address, 2 KiB banks ignore their low bit
+  fn chr_bank(&self, address: u16) -> usize {
+    let slot = usize::from(address >> 10) ^ if self.chr_select { 4 } else { 0 };
+    match slot {
+      0 => self.registers[0] & !1,
+      1 => self.registers[0] | 1,
+      2 => self.registers[1] & !1,
+      3 => self.registers[1] | 1,
+      _ => self.registers[slot - 2],
+    }
+  }
+
+  // Whether the address maps to CHR RAM, and the 1 KiB page it maps to
+  fn chr_page(&self, address: u16) -> (bool, Page) {
+    let bank = self.chr_bank(address);
+    let rom = self.get_rom();
+    let is_ram = match self.board {
+      Board::Tqrom => bank & 0x40 > 0,
+      _ => rom.rom_header.chr_rom_len == 0,
+    };
+    let len = if is_ram { rom.chr_ram.data.len() } else { rom.chr_rom.data.len() };
+    (is_ram, Page::FromNth(bank % (len / One.value()), One))
+  }
 }
 
 impl Mapper for Mapper4 {
   fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    let offset = address & 0x1FFF;
     match (address, self.prg_select) {
-      (0x6000..=0x7FFF, _) => self.get_rom().prg_ram.read(Page::First(Eight), address - 0x6000),
-      (0x8000..=0x9FFF, false) => self.get_rom().prg_rom.read(Page::FromNth(self.registers[6], Eight), address - 0x8000),
-      (0x8000..=0x9FFF, true) => self.get_rom().prg_rom.read(Page::FromEnd(1, Eight), address - 0x8000),
-      (0xA000..=0xBFFF, _) => self.get_rom().prg_rom.read(Page::FromNth(self.registers[7], Eight), address - 0xA000),
-      (0xC000..=0xDFFF, false) => self.get_rom().prg_rom.read(Page::FromEnd(1, Eight), address - 0xC000),
-      (0xC000..=0xDFFF, true) => self.get_rom().prg_rom.read(Page::FromNth(self.registers[6], Eight), address - 0xC000),
-      (0xE000..=0xFFFF, _) => self.get_rom().prg_rom.read(Page::FromEnd(0, Eight), address - 0xE000),
+      (0x6000..=0x7FFF, _) => self.read_prg_ram(address),
+      (0x8000..=0x9FFF, false) | (0xC000..=0xDFFF, true) => self.read_prg_rom(self.registers[6], offset),
+      (0xA000..=0xBFFF, _) => self.read_prg_rom(self.registers[7], offset),
+      (0x8000..=0x9FFF, true) | (0xC000..=0xDFFF, false) => self.get_rom().prg_rom.read(Page::FromEnd(1, Eight), offset),
+      (0xE000..=0xFFFF, _) => self.get_rom().prg_rom.read(Page::FromEnd(0, Eight), offset),
       _ => panic!("Invalid mapped_read_cpu_u8 address 0x{:04X}", address),
     }
   }
 
   fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    if self.board == Board::Namco108 {
+      match (address, address % 2) {
+        (0x8000..=0x9FFF, 0) => self.index = data as usize & 0x07,
+        (0x8000..=0x9FFF, 1) => self.registers[self.index] = data as usize,
+        _ => (),
+      }
+      return;
+    }
+
     match (address, address % 2) {
-      (0x6000..=0x7FFF, _) => self.get_mut_rom().prg_ram.write(Page::First(Eight), address - 0x6000, data),
+      (0x6000..=0x7FFF, _) => self.write_prg_ram(address, data),
       (0x8000..=0x9FFF, 0) => {
         self.index = data as usize & 0x07;
         self.prg_select = data & 0x40 > 0;
         self.chr_select = data & 0x80 > 0;
+        if self.board == Board::Mmc6 {
+          self.is_mmc6_ram_enabled = data & 0x20 > 0;
+        }
       }
       (0x8000..=0x9FFF, 1) => {
         self.registers[self.index] = data as usize;
       }
-      (0xA000..=0xBFFF, 0) => {
+      (0xA000..=0xBFFF, 0) if self.mirroring != Mirroring::FourScreen => {
         self.mirroring = if data % 2 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
       }
+      (0xA000..=0xBFFF, 1) if self.board != Board::Mmc6 || self.is_mmc6_ram_enabled => self.ram_protect = data,
       (0xC000..=0xDFFF, 0) => self.irq_period = data,
-      (0xC000..=0xDFFF, 1) => self.irq_counter = 0,
+      (0xC000..=0xDFFF, 1) => {
+        self.irq_counter = 0;
+        self.irq_reload = true;
+      }
       (0xE000..=0xFFFF, 0) => {
         self.irq_enabled = false;
         self.flag_irq = false;
@@ -86,51 +255,225 @@ impl Mapper for Mapper4 {
   }
 
   fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
-    let bank = match (address, self.chr_select) {
-      (0x0000..=0x03FF, false) => self.registers[0] & !1,
-      (0x0000..=0x03FF, true) => self.registers[2],
-      (0x0400..=0x07FF, false) => self.registers[0] | 1,
-      (0x0400..=0x07FF, true) => self.registers[3],
-      (0x0800..=0x0BFF, false) => self.registers[1] & !1,
-      (0x0800..=0x0BFF, true) => self.registers[4],
-      (0x0C00..=0x0FFF, false) => self.registers[1] | 1,
-      (0x0C00..=0x0FFF, true) => self.registers[5],
-
-      (0x1000..=0x13FF, false) => self.registers[2],
-      (0x1000..=0x13FF, true) => self.registers[0] & !1,
-      (0x1400..=0x17FF, false) => self.registers[3],
-      (0x1400..=0x17FF, true) => self.registers[0] | 1,
-      (0x1800..=0x1BFF, false) => self.registers[4],
-      (0x1800..=0x1BFF, true) => self.registers[1] & !1,
-      (0x1C00..=0x1FFF, false) => self.registers[5],
-      (0x1C00..=0x1FFF, true) => self.registers[1] | 1,
-      _ => panic!("Invalid mapped_read_ppu_u8 address 0x{:04X}", address),
-    };
-    self.get_rom().chr_rom.read(Page::FromNth(bank, One), address & 0x03FF)
+    match self.chr_page(address) {
+      (true, page) => self.get_rom().chr_ram.read(page, address & 0x03FF),
+      (false, page) => self.get_rom().chr_rom.read(page, address & 0x03FF),
+    }
   }
 
-  fn mapped_write_ppu_u8(&mut self, _address: u16, _data: u8) {}
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    if let (true, page) = self.chr_page(address) {
+      self.get_mut_rom().chr_ram.write(page, address & 0x03FF, data);
+    }
+  }
 
   fn mirroring(&self) -> Mirroring {
     self.mirroring
   }
 
+  fn name_tables(&self) -> [NameTableSource; 4] {
+    if self.board != Board::TxSrom {
+      return self.mirroring.name_tables();
+    }
+    // Each quadrant follows the CHR bank of the matching 1 KiB of $0000-$0FFF
+    [0x0000, 0x0400, 0x0800, 0x0C00].map(|address| NameTableSource::Vram(self.chr_bank(address) >> 7 & 1))
+  }
+
   fn irq_flag(&self) -> bool {
     self.flag_irq
   }
 
-  fn signal_scanline(&mut self) {
-    if self.irq_counter == 0 {
-      self.irq_counter = self.irq_period;
-    } else {
-      self.irq_counter -= 1;
+  fn ppu_bus_access(&mut self, address: u16, _access: PpuBusAccess, dot: u64) -> Option<u8> {
+    if address & 0x1000 == 0 {
+      self.a12_low_since.get_or_insert(dot);
+    } else if let Some(low_since) = self.a12_low_since.take() {
+      if dot - low_since >= A12_LOW_DOTS {
+        self.clock_irq_counter();
+      }
     }
-    if self.irq_counter == 0 && self.irq_enabled {
-      self.flag_irq = true;
+    None
+  }
+
+  fn ppu_bus_watch(&self) -> PpuBusWatch {
+    if self.board == Board::Namco108 { PpuBusWatch::None } else { PpuBusWatch::SpriteFetches }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::cartridge::rom_reading::{Mirroring, NameTableSource, Rom};
+  use crate::cartridge::rom_with_pager::RomData;
+  use crate::mapper::{Mapper, PpuBusAccess, PpuBusWatch};
+  use crate::mapper::mapper4::Mapper4;
+
+  // 64 KiB of CHR ROM with each 1 KiB bank filled with its number, none for CHR RAM boards
+  fn board(mapper: u16, submapper: u8, has_chr_rom: bool) -> Mapper4 {
+    let mut rom = Rom::mock_rom();
+    rom.rom_header.mapper = mapper;
+    rom.rom_header.submapper = submapper;
+    rom.chr_rom = if has_chr_rom { (0..64).flat_map(|bank| vec![bank as u8; 0x0400]).collect() } else { Vec::new() };
+    rom.rom_header.chr_rom_len = rom.chr_rom.len();
+    rom.chr_ram = vec![0; 0x2000];
+    Mapper4::new(Rc::new(RefCell::new(RomData::new(rom))))
+  }
+
+  fn write_bank(mapper: &mut Mapper4, register: u8, bank: u8) {
+    mapper.mapped_write_cpu_u8(0x8000, register);
+    mapper.mapped_write_cpu_u8(0x8001, bank);
+  }
+
+  fn mmc3(submapper: u8, irq_period: u8) -> Mapper4 {
+    let mut rom = Rom::mock_rom();
+    rom.rom_header.submapper = submapper;
+    let mut mapper = Mapper4::new(Rc::new(RefCell::new(RomData::new(rom))));
+    mapper.mapped_write_cpu_u8(0xC000, irq_period);
+    mapper.mapped_write_cpu_u8(0xC001, 0);
+    mapper.mapped_write_cpu_u8(0xE001, 0);
+    mapper
+  }
+
+  // Handlers acknowledge by disabling then enabling the IRQ again
+  fn acknowledge_irq(mapper: &mut Mapper4) {
+    mapper.mapped_write_cpu_u8(0xE000, 0);
+    mapper.mapped_write_cpu_u8(0xE001, 0);
+  }
+
+  // Background from $0000 and sprites from $1000, A12 rises once per line on the first sprite fetch
+  fn run_lines(mapper: &mut Mapper4, lines: u64) -> Vec<u64> {
+    let mut irq_lines = Vec::new();
+    for line in 0..lines {
+      for dot in (2..=256).step_by(2).chain(322..=340) {
+        mapper.ppu_bus_access(0x0000, PpuBusAccess::Background, line * 341 + dot);
+      }
+      for dot in (261..=320).step_by(2) {
+        mapper.ppu_bus_access(0x1000, PpuBusAccess::Sprite, line * 341 + dot);
+      }
+      if mapper.irq_flag() {
+        irq_lines.push(line);
+        acknowledge_irq(mapper);
+      }
     }
+    irq_lines
+  }
+
+  #[test]
+  fn counter_clocked_by_a12_rises() {
+    let mut mapper = mmc3(0, 3);
+    assert_eq!(run_lines(&mut mapper, 10), [3, 7]);
+
+    // Rises after short lows are filtered out
+    let mut mapper = mmc3(0, 0);
+    mapper.ppu_bus_access(0x1000, PpuBusAccess::Sprite, 100);
+    acknowledge_irq(&mut mapper);
+    mapper.ppu_bus_access(0x0000, PpuBusAccess::Background, 102);
+    mapper.ppu_bus_access(0x1000, PpuBusAccess::Sprite, 106);
+    assert!(!mapper.irq_flag());
+    mapper.ppu_bus_access(0x0000, PpuBusAccess::Background, 108);
+    mapper.ppu_bus_access(0x1000, PpuBusAccess::Sprite, 130);
+    assert!(mapper.irq_flag());
+  }
+
+  #[test]
+  fn irq_behaviour_of_revisions() {
+    // A reload value of zero triggers on every line with the MMC3B
+    let mut mmc3b = mmc3(0, 0);
+    assert_eq!(run_lines(&mut mmc3b, 3), [0, 1, 2]);
+
+    // The MMC3A only triggers on the reload requested by $C001
+    let mut mmc3a = mmc3(4, 0);
+    assert_eq!(run_lines(&mut mmc3a, 3), [0]);
+    mmc3a.mapped_write_cpu_u8(0xC001, 0);
+    assert_eq!(run_lines(&mut mmc3a, 3), [0]);
+  }
+
+  #[test]
+  fn mirroring_and_chr_ram() {
+    let mut mapper = board(4, 0, false);
+    mapper.mapped_write_cpu_u8(0xA000, 0);
+    assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+    mapper.mapped_write_cpu_u8(0xA000, 1);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+    write_bank(&mut mapper, 2, 5);
+    mapper.mapped_write_ppu_u8(0x1000, 0x42);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x1000), 0x42);
+    // Same RAM bank seen through the other half with the CHR banks inverted
+    mapper.mapped_write_cpu_u8(0x8000, 0x80);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x0000), 0x42);
+  }
+
+  #[test]
+  fn prg_ram_protect() {
+    let mut mapper = board(4, 0, true);
+    mapper.mapped_write_cpu_u8(0x6000, 0x11);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x11);
+
+    // Write protected
+    mapper.mapped_write_cpu_u8(0xA001, 0xC0);
+    mapper.mapped_write_cpu_u8(0x6000, 0x22);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x11);
+
+    // Disabled
+    mapper.mapped_write_cpu_u8(0xA001, 0x00);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x60);
+  }
+
+  #[test]
+  fn mmc6_ram_halves() {
+    let mut mapper = board(4, 1, true);
+    // Nothing goes through before $8000 bit 5 is set
+    mapper.mapped_write_cpu_u8(0xA001, 0xF0);
+    mapper.mapped_write_cpu_u8(0x7000, 0x11);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x7000), 0x70);
+
+    mapper.mapped_write_cpu_u8(0x8000, 0x20);
+    mapper.mapped_write_cpu_u8(0xA001, 0xF0);
+    mapper.mapped_write_cpu_u8(0x7000, 0x11);
+    mapper.mapped_write_cpu_u8(0x7200, 0x22);
+    // 1 KiB mirrored over $7000-$7FFF
+    assert_eq!(mapper.mapped_read_cpu_u8(0x7C00), 0x11);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x60);
+
+    // Upper half readable only, the lower one reads zeros
+    mapper.mapped_write_cpu_u8(0xA001, 0x80);
+    mapper.mapped_write_cpu_u8(0x7200, 0x33);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x7200), 0x22);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x7000), 0x00);
+  }
+
+  #[test]
+  fn txsrom_and_tqrom_chr_banks() {
+    let mut txsrom = board(118, 0, true);
+    write_bank(&mut txsrom, 0, 0x82);
+    write_bank(&mut txsrom, 1, 0x04);
+    assert_eq!(txsrom.name_tables(), [1, 1, 0, 0].map(NameTableSource::Vram));
+    assert_eq!(txsrom.mapped_read_ppu_u8(0x0400), 3);
+    // The mirroring register does nothing
+    txsrom.mapped_write_cpu_u8(0xA000, 0);
+    assert_eq!(txsrom.name_tables(), [1, 1, 0, 0].map(NameTableSource::Vram));
+
+    let mut tqrom = board(119, 0, true);
+    write_bank(&mut tqrom, 2, 0x07);
+    write_bank(&mut tqrom, 3, 0x41);
+    tqrom.mapped_write_ppu_u8(0x1000, 0x99);
+    tqrom.mapped_write_ppu_u8(0x1400, 0x99);
+    assert_eq!((tqrom.mapped_read_ppu_u8(0x1000), tqrom.mapped_read_ppu_u8(0x1400)), (7, 0x99));
   }
 
-  fn clear_irq_flag(&mut self) {
-    self.flag_irq = false;
+  #[test]
+  fn namco_108() {
+    let mut mapper = board(206, 0, true);
+    // Mode bits, mirroring, PRG RAM and IRQ registers are not there
+    mapper.mapped_write_cpu_u8(0x8000, 0xC1);
+    mapper.mapped_write_cpu_u8(0x8001, 0x09);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x0800), 8);
+    mapper.mapped_write_cpu_u8(0xA000, 1);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    mapper.mapped_write_cpu_u8(0x6000, 0x11);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x60);
+    assert_eq!(mapper.ppu_bus_watch(), PpuBusWatch::None);
   }
 }
@@ -0,0 +1,628 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::NameTableSource;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, PpuBusAccess, PpuBusWatch};
+use crate::mapper::mmc5_audio::Mmc5Audio;
+use crate::mapper::pager::{Page, Pager};
+use crate::mapper::pager::PageSize::{Eight, One};
+
+// iNES headers rarely tell how much work RAM the board has, it gets the two 32 KiB chips of the biggest ones
+const PRG_RAM_LEN: usize = 0x10000;
+// CPU cycles without a PPU read after which the MMC5 considers the frame over
+const IN_FRAME_TIMEOUT: u8 = 3;
+
+#[derive(Clone)]
+pub(crate) struct Mapper5 {
+  prg_mode: u8,
+  chr_mode: u8,
+  // $5102 and $5103, PRG RAM is writable while they hold 2 and 1
+  ram_protect: [u8; 2],
+  ex_ram_mode: u8,
+  ex_ram: [u8; 0x0400],
+  // $5105, two bits per quadrant: CIRAM page 0 or 1, ExRAM or fill mode
+  name_table_mapping: u8,
+  fill_tile: u8,
+  fill_attribute: u8,
+  // $5113-$5117, RAM at $6000 then the four 8 KiB windows of $8000-$FFFF
+  prg_banks: [u8; 5],
+  // $5120-$512B, the sprite set then the background set used with 8x16 sprites, with the $5130 bits written
+  // alongside them
+  chr_banks: [usize; 12],
+  chr_upper: u8,
+  is_background_set_last: bool,
+  // $2000 bit 5, snooped on the CPU bus
+  is_large_sprites: bool,
+  split_control: u8,
+  split_scroll: u8,
+  split_bank: u8,
+  irq_compare: u8,
+  irq_enabled: bool,
+  irq_pending: bool,
+  in_frame: bool,
+  scan_line: u8,
+  idle_cycles: u8,
+  // Scanlines show up as three reads of the same nametable address in a row
+  last_name_table_address: u16,
+  name_table_reads: u8,
+  // Column of the tile being fetched, the first two of a line are fetched at the end of the previous one
+  tile_column: u8,
+  next_tile_column: u8,
+  is_split_tile: bool,
+  ex_attribute: u8,
+  multiplicand: u8,
+  multiplier: u8,
+  audio: Mmc5Audio,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper5 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper5 {
+    if rom.borrow().prg_ram.data.len() < PRG_RAM_LEN {
+      rom.borrow_mut().prg_ram = Pager::new(vec![0; PRG_RAM_LEN]);
+    }
+
+    Mapper5 {
+      prg_mode: 3,
+      chr_mode: 0,
+      ram_protect: [0; 2],
+      ex_ram_mode: 0,
+      ex_ram: [0; 0x0400],
+      name_table_mapping: 0,
+      fill_tile: 0,
+      fill_attribute: 0,
+      prg_banks: [0, 0, 0, 0, 0xFF],
+      chr_banks: [0; 12],
+      chr_upper: 0,
+      is_background_set_last: false,
+      is_large_sprites: false,
+      split_control: 0,
+      split_scroll: 0,
+      split_bank: 0,
+      irq_compare: 0,
+      irq_enabled: false,
+      irq_pending: false,
+      in_frame: false,
+      scan_line: 0,
+      idle_cycles: 0,
+      last_name_table_address: 0,
+      name_table_reads: 0,
+      tile_column: 0,
+      next_tile_column: 0,
+      is_split_tile: false,
+      ex_attribute: 0,
+      multiplicand: 0xFF,
+      multiplier: 0xFF,
+      audio: Mmc5Audio::new(),
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+
+  // Whether the address in $6000-$FFFF maps to ROM, and its 8 KiB bank
+  fn prg_bank(&self, address: u16) -> (bool, usize) {
+    let slot = usize::from((address - 0x6000) >> 13);
+    if slot == 0 {
+      return (false, usize::from(self.prg_banks[0] & 0x07));
+    }
+
+    // Register and size in 8 KiB banks of the window the address falls in
+    let (register, size) = match (self.prg_mode, slot) {
+      (0, _) => (4, 4),
+      (1, 1 | 2) | (2, 1 | 2) => (2, 2),
+      (1, _) => (4, 2),
+      (2, 3) => (3, 1),
+      (2, _) => (4, 1),
+      _ => (slot, 1),
+    };
+    let value = self.prg_banks[register];
+    let bank = (usize::from(value & 0x7F) & !(size - 1)) | ((slot - 1) & (size - 1));
+    if register == 4 || value & 0x80 > 0 {
+      (true, bank)
+    } else {
+      (false, bank & 0x07)
+    }
+  }
+
+  fn is_prg_ram_writable(&self) -> bool {
+    self.ram_protect == [0x02, 0x01]
+  }
+
+  // 1 KiB CHR page of the address, from the sprite set or from the background set
+  fn chr_page(&self, address: u16, is_background_set: bool) -> usize {
+    let slot = usize::from(address >> 10) & 0x07;
+    let (register, size) = match (self.chr_mode, is_background_set) {
+      (0, false) => (7, 8),
+      (0, true) => (11, 8),
+      (1, false) => (slot & 4 | 3, 4),
+      (1, true) => (11, 4),
+      (2, false) => (slot & 6 | 1, 2),
+      (2, true) => (8 + (slot & 2 | 1), 2),
+      (_, false) => (slot, 1),
+      (_, true) => (8 + (slot & 3), 1),
+    };
+    self.chr_banks[register] * size + (slot & (size - 1))
+  }
+
+  fn read_chr(&self, page: usize, address: u16) -> u8 {
+    let rom = self.get_rom();
+    let chr = if rom.chr_rom.data.is_empty() { &rom.chr_ram } else { &rom.chr_rom };
+    chr.read(Page::FromNth(page % (chr.data.len() / One.value()), One), address & 0x03FF)
+  }
+
+  // Pattern fetch from a 4 KiB bank, `fine_y` replaces the row of the tile when given
+  fn read_chr_4k(&self, bank: usize, address: u16, fine_y: Option<u8>) -> u8 {
+    let address = match fine_y {
+      Some(fine_y) => address & 0x0FF8 | u16::from(fine_y),
+      None => address & 0x0FFF,
+    };
+    self.read_chr(bank * 4 + usize::from(address >> 10), address)
+  }
+
+  // Three reads of the same nametable address in a row, the two dummy fetches at the end of a line and the first
+  // fetch of the next, mark the start of a scanline
+  fn detect_scan_line(&mut self, address: u16) {
+    let is_repeat = address == self.last_name_table_address && address & 0x2000 > 0;
+    self.last_name_table_address = address;
+    self.name_table_reads = if is_repeat { self.name_table_reads + 1 } else { 0 };
+    if self.name_table_reads != 2 {
+      return;
+    }
+
+    if self.in_frame {
+      self.scan_line = self.scan_line.wrapping_add(1);
+      if self.scan_line == self.irq_compare {
+        self.irq_pending = true;
+      }
+    } else {
+      self.in_frame = true;
+      self.scan_line = 0;
+    }
+    self.next_tile_column = 2;
+  }
+
+  fn is_in_split(&self, column: u8) -> bool {
+    let threshold = self.split_control & 0x1F;
+    let is_right_side = self.split_control & 0x40 > 0;
+    self.split_control & 0x80 > 0 && self.ex_ram_mode <= 1 && (column >= threshold) == is_right_side
+  }
+
+  // Row of the split nametable shown on the line of the tile, the first two tiles belong to the next line
+  fn split_y(&self) -> u8 {
+    let line = if !self.in_frame {
+      0
+    } else if self.tile_column < 2 {
+      u16::from(self.scan_line) + 1
+    } else {
+      u16::from(self.scan_line)
+    };
+    ((u16::from(self.split_scroll) + line) % 240) as u8
+  }
+
+  // Nametable, attribute and pattern fetches of the background, replaced by the split or by ExGrafix
+  fn fetch_background(&mut self, address: u16) -> Option<u8> {
+    if address < 0x2000 {
+      return Some(if self.is_split_tile {
+        self.read_chr_4k(usize::from(self.split_bank), address, Some(self.split_y() & 0x07))
+      } else if self.ex_ram_mode == 1 {
+        self.read_chr_4k(usize::from(self.ex_attribute & 0x3F) | usize::from(self.chr_upper) << 6, address, None)
+      } else {
+        self.read_chr(self.chr_page(address, self.is_large_sprites), address)
+      });
+    }
+
+    if address & 0x03FF < 0x03C0 {
+      self.tile_column = self.next_tile_column;
+      self.next_tile_column = self.next_tile_column.wrapping_add(1);
+      self.is_split_tile = self.is_in_split(self.tile_column);
+    }
+    let (split_row, column) = (usize::from(self.split_y() >> 3), usize::from(self.tile_column & 0x1F));
+
+    if address & 0x03FF < 0x03C0 {
+      if self.is_split_tile {
+        return Some(self.ex_ram[split_row * 32 + column]);
+      }
+      self.ex_attribute = self.ex_ram[usize::from(address & 0x03FF)];
+      None
+    } else if self.is_split_tile {
+      let shift = (split_row & 0x02) << 1 | column & 0x02;
+      let attribute = self.ex_ram[0x03C0 + split_row / 4 * 8 + column / 4] >> shift & 0x03;
+      Some(attribute * 0x55)
+    } else if self.ex_ram_mode == 1 {
+      Some((self.ex_attribute >> 6) * 0x55)
+    } else {
+      None
+    }
+  }
+}
+
+impl Mapper for Mapper5 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    let offset = address & 0x1FFF;
+    let data = match self.prg_bank(address) {
+      (true, bank) => {
+        let rom = self.get_rom();
+        let bank_count = rom.prg_rom.data.len() / Eight.value();
+        rom.prg_rom.read(Page::FromNth(bank % bank_count, Eight), offset)
+      }
+      (false, bank) => self.get_rom().prg_ram.read(Page::FromNth(bank, Eight), offset),
+    };
+    self.audio.snoop_prg_read(address, data);
+    data
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    if let (false, bank) = self.prg_bank(address) {
+      if self.is_prg_ram_writable() {
+        self.get_mut_rom().prg_ram.write(Page::FromNth(bank, Eight), address & 0x1FFF, data);
+      }
+    }
+  }
+
+  fn read_expansion_u8(&mut self, address: u16) -> u8 {
+    match address {
+      0x5010 | 0x5015 => self.audio.read_u8(address),
+      0x5204 => {
+        let data = u8::from(self.irq_pending) << 7 | u8::from(self.in_frame) << 6;
+        self.irq_pending = false;
+        data
+      }
+      0x5205 => (u16::from(self.multiplicand) * u16::from(self.multiplier)) as u8,
+      0x5206 => ((u16::from(self.multiplicand) * u16::from(self.multiplier)) >> 8) as u8,
+      0x5C00..=0x5FFF if self.ex_ram_mode >= 2 => self.ex_ram[usize::from(address & 0x03FF)],
+      _ => (address >> 8) as u8,
+    }
+  }
+
+  fn write_expansion_u8(&mut self, address: u16, data: u8) {
+    match address {
+      0x5000..=0x5015 => self.audio.write_u8(address, data),
+      0x5100 => self.prg_mode = data & 0x03,
+      0x5101 => self.chr_mode = data & 0x03,
+      0x5102 | 0x5103 => self.ram_protect[usize::from(address - 0x5102)] = data & 0x03,
+      0x5104 => self.ex_ram_mode = data & 0x03,
+      0x5105 => self.name_table_mapping = data,
+      0x5106 => self.fill_tile = data,
+      0x5107 => self.fill_attribute = data & 0x03,
+      0x5113..=0x5117 => self.prg_banks[usize::from(address - 0x5113)] = data,
+      0x5120..=0x512B => {
+        self.chr_banks[usize::from(address - 0x5120)] = usize::from(self.chr_upper) << 8 | usize::from(data);
+        self.is_background_set_last = address >= 0x5128;
+      }
+      0x5130 => self.chr_upper = data & 0x03,
+      0x5200 => self.split_control = data,
+      0x5201 => self.split_scroll = data,
+      0x5202 => self.split_bank = data,
+      0x5203 => self.irq_compare = data,
+      0x5204 => self.irq_enabled = data & 0x80 > 0,
+      0x5205 => self.multiplicand = data,
+      0x5206 => self.multiplier = data,
+      // Nametable modes only take writes while rendering, zeros get written otherwise
+      0x5C00..=0x5FFF if self.ex_ram_mode <= 1 => {
+        self.ex_ram[usize::from(address & 0x03FF)] = if self.in_frame { data } else { 0 };
+      }
+      0x5C00..=0x5FFF if self.ex_ram_mode == 2 => self.ex_ram[usize::from(address & 0x03FF)] = data,
+      _ => (),
+    }
+  }
+
+  // $2007 accesses use the sprite set, or the set written last with 8x16 sprites
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    self.read_chr(self.chr_page(address, self.is_large_sprites && self.is_background_set_last), address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    if self.get_rom().chr_rom.data.is_empty() {
+      let page = self.chr_page(address, self.is_large_sprites && self.is_background_set_last);
+      let mut rom = self.get_mut_rom();
+      let page_count = rom.chr_ram.data.len() / One.value();
+      rom.chr_ram.write(Page::FromNth(page % page_count, One), address & 0x03FF, data);
+    }
+  }
+
+  fn name_tables(&self) -> [NameTableSource; 4] {
+    [0, 2, 4, 6].map(|shift| match self.name_table_mapping >> shift & 0x03 {
+      page @ (0 | 1) => NameTableSource::Vram(usize::from(page)),
+      _ => NameTableSource::Cartridge,
+    })
+  }
+
+  fn read_name_table(&self, address: u16) -> u8 {
+    let offset = usize::from(address & 0x03FF);
+    match self.name_table_mapping >> ((address >> 9) & 0x06) & 0x03 {
+      2 if self.ex_ram_mode <= 1 => self.ex_ram[offset],
+      3 if offset >= 0x03C0 => self.fill_attribute * 0x55,
+      3 => self.fill_tile,
+      _ => 0,
+    }
+  }
+
+  fn write_name_table(&mut self, address: u16, data: u8) {
+    if self.name_table_mapping >> ((address >> 9) & 0x06) & 0x03 == 2 && self.ex_ram_mode <= 1 {
+      self.ex_ram[usize::from(address & 0x03FF)] = data;
+    }
+  }
+
+  fn irq_flag(&self) -> bool {
+    (self.irq_pending && self.irq_enabled) || self.audio.irq_flag()
+  }
+
+  fn ppu_bus_access(&mut self, address: u16, access: PpuBusAccess, _dot: u64) -> Option<u8> {
+    if access == PpuBusAccess::Cpu {
+      return None;
+    }
+    self.idle_cycles = 0;
+    self.detect_scan_line(address);
+
+    match access {
+      PpuBusAccess::Sprite => {
+        self.next_tile_column = 0;
+        Some(self.read_chr(self.chr_page(address, false), address))
+      }
+      _ => self.fetch_background(address),
+    }
+  }
+
+  fn ppu_bus_watch(&self) -> PpuBusWatch {
+    PpuBusWatch::AllFetches
+  }
+
+  fn snoop_ppu_register(&mut self, address: u16, data: u8) {
+    if address & 0x07 == 0 {
+      self.is_large_sprites = data & 0x20 > 0;
+    }
+  }
+
+  fn clock_cpu(&mut self) {
+    self.idle_cycles = self.idle_cycles.saturating_add(1);
+    if self.idle_cycles >= IN_FRAME_TIMEOUT {
+      self.in_frame = false;
+    }
+
+    self.audio.clock_cpu();
+  }
+
+  fn audio_output(&self) -> f64 {
+    self.audio.output()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::cartridge::rom_reading::{NameTableSource, Rom};
+  use crate::cartridge::rom_with_pager::RomData;
+  use crate::mapper::{Mapper, PpuBusAccess};
+  use crate::mapper::mapper5::Mapper5;
+
+  // 128 KiB of PRG ROM and 256 KiB of CHR ROM, each 8 KiB PRG bank and 1 KiB CHR bank filled with its number
+  fn mmc5() -> Mapper5 {
+    let mut rom = Rom::mock_rom();
+    rom.prg_rom = (0..16).flat_map(|bank| vec![bank as u8; 0x2000]).collect();
+    rom.rom_header.prg_rom_len = rom.prg_rom.len();
+    rom.chr_rom = (0..256).flat_map(|bank| vec![bank as u8; 0x0400]).collect();
+    rom.rom_header.chr_rom_len = rom.chr_rom.len();
+    Mapper5::new(Rc::new(RefCell::new(RomData::new(rom))))
+  }
+
+  fn read_windows(mapper: &Mapper5) -> [u8; 4] {
+    [0x8000, 0xA000, 0xC000, 0xE000].map(|address| mapper.mapped_read_cpu_u8(address))
+  }
+
+  // Fetches of a rendered line: 32 tiles, the sprites, two tiles of the next line and the dummy nametable reads
+  fn fetch_line(mapper: &mut Mapper5) {
+    let fetch_tile = |mapper: &mut Mapper5, column: u16| {
+      mapper.ppu_bus_access(0x2000 | column, PpuBusAccess::Background, 0);
+      mapper.ppu_bus_access(0x23C0 | column >> 2, PpuBusAccess::Background, 0);
+      mapper.ppu_bus_access(0x0000, PpuBusAccess::Background, 0);
+      mapper.ppu_bus_access(0x0008, PpuBusAccess::Background, 0);
+    };
+    for column in 2..34 {
+      fetch_tile(mapper, column & 0x1F);
+    }
+    for _ in 0..16 {
+      mapper.ppu_bus_access(0x1FF0, PpuBusAccess::Sprite, 0);
+    }
+    fetch_tile(mapper, 0);
+    fetch_tile(mapper, 1);
+    mapper.ppu_bus_access(0x2002, PpuBusAccess::Background, 0);
+    mapper.ppu_bus_access(0x2002, PpuBusAccess::Background, 0);
+  }
+
+  #[test]
+  fn prg_banking_modes() {
+    let mut mapper = mmc5();
+    // Mode 3 with the last bank at $E000 on power up
+    assert_eq!(mapper.mapped_read_cpu_u8(0xE000), 15);
+
+    mapper.write_expansion_u8(0x5100, 0);
+    mapper.write_expansion_u8(0x5117, 0x86);
+    assert_eq!(read_windows(&mapper), [4, 5, 6, 7]);
+
+    mapper.write_expansion_u8(0x5100, 1);
+    mapper.write_expansion_u8(0x5115, 0x83);
+    mapper.write_expansion_u8(0x5117, 0x89);
+    assert_eq!(read_windows(&mapper), [2, 3, 8, 9]);
+
+    mapper.write_expansion_u8(0x5100, 2);
+    mapper.write_expansion_u8(0x5116, 0x8A);
+    assert_eq!(read_windows(&mapper), [2, 3, 10, 9]);
+
+    // RAM bank 1 at $8000, writable only with both protect registers set
+    mapper.write_expansion_u8(0x5100, 3);
+    mapper.write_expansion_u8(0x5114, 0x01);
+    mapper.mapped_write_cpu_u8(0x8000, 0x42);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x8000), 0);
+    mapper.write_expansion_u8(0x5102, 0x02);
+    mapper.write_expansion_u8(0x5103, 0x01);
+    mapper.mapped_write_cpu_u8(0x8000, 0x42);
+    mapper.write_expansion_u8(0x5113, 0x01);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x6000), 0x42);
+  }
+
+  #[test]
+  fn chr_bank_sets() {
+    let mut mapper = mmc5();
+    mapper.write_expansion_u8(0x5101, 3);
+    for register in 0..12 {
+      mapper.write_expansion_u8(0x5120 + register, 0x10 + register as u8);
+    }
+
+    // 8x16 sprites, the background gets its own set
+    mapper.snoop_ppu_register(0x2000, 0x20);
+    assert_eq!(mapper.ppu_bus_access(0x1400, PpuBusAccess::Sprite, 0), Some(0x15));
+    assert_eq!(mapper.ppu_bus_access(0x1400, PpuBusAccess::Background, 0), Some(0x19));
+    // $2007 goes through the set written last
+    assert_eq!(mapper.mapped_read_ppu_u8(0x1400), 0x19);
+    mapper.write_expansion_u8(0x5125, 0x15);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x1400), 0x15);
+
+    // 8x8 sprites, everything uses the sprite set
+    mapper.snoop_ppu_register(0x2000, 0x00);
+    assert_eq!(mapper.ppu_bus_access(0x1400, PpuBusAccess::Background, 0), Some(0x15));
+
+    // 2 KiB banks
+    mapper.write_expansion_u8(0x5101, 2);
+    mapper.write_expansion_u8(0x5121, 3);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x0400), 7);
+  }
+
+  #[test]
+  fn name_tables_and_ex_ram() {
+    let mut mapper = mmc5();
+    mapper.write_expansion_u8(0x5105, 0b11_10_01_00);
+    assert_eq!(mapper.name_tables(), [
+      NameTableSource::Vram(0),
+      NameTableSource::Vram(1),
+      NameTableSource::Cartridge,
+      NameTableSource::Cartridge,
+    ]);
+
+    // CPU RAM, not a nametable
+    mapper.write_expansion_u8(0x5104, 2);
+    mapper.write_expansion_u8(0x5C10, 0x55);
+    assert_eq!(mapper.read_expansion_u8(0x5C10), 0x55);
+    assert_eq!(mapper.read_name_table(0x2810), 0);
+
+    // Nametable, the CPU writes zeros outside of rendering and reads open bus
+    mapper.write_expansion_u8(0x5104, 0);
+    assert_eq!(mapper.read_name_table(0x2810), 0x55);
+    mapper.write_expansion_u8(0x5C10, 0x66);
+    assert_eq!(mapper.read_name_table(0x2810), 0);
+    assert_eq!(mapper.read_expansion_u8(0x5C10), 0x5C);
+    mapper.write_name_table(0x2810, 0x66);
+    assert_eq!(mapper.read_name_table(0x2810), 0x66);
+
+    // Read-only
+    mapper.write_expansion_u8(0x5104, 3);
+    mapper.write_expansion_u8(0x5C10, 0x77);
+    assert_eq!(mapper.read_expansion_u8(0x5C10), 0x66);
+
+    mapper.write_expansion_u8(0x5106, 0x33);
+    mapper.write_expansion_u8(0x5107, 0x02);
+    assert_eq!(mapper.read_name_table(0x2C05), 0x33);
+    assert_eq!(mapper.read_name_table(0x2FC1), 0xAA);
+  }
+
+  #[test]
+  fn ex_grafix_and_split() {
+    let mut mapper = mmc5();
+    mapper.write_expansion_u8(0x5104, 2);
+    mapper.write_expansion_u8(0x5C05, 0xC3);
+
+    // Palette 3 and 4 KiB bank 3 for the tile at $2005
+    mapper.write_expansion_u8(0x5104, 1);
+    assert_eq!(mapper.ppu_bus_access(0x2005, PpuBusAccess::Background, 0), None);
+    assert_eq!(mapper.ppu_bus_access(0x23C1, PpuBusAccess::Background, 0), Some(0xFF));
+    assert_eq!(mapper.ppu_bus_access(0x0010, PpuBusAccess::Background, 0), Some(12));
+
+    // Split of the four leftmost tiles, from CHR bank 2 and the nametable in ExRAM
+    let mut mapper = mmc5();
+    mapper.write_expansion_u8(0x5104, 2);
+    mapper.write_expansion_u8(0x5C00, 0x07);
+    mapper.write_expansion_u8(0x5FC0, 0x03);
+    mapper.write_expansion_u8(0x5104, 0);
+    mapper.write_expansion_u8(0x5200, 0x84);
+    mapper.write_expansion_u8(0x5202, 0x02);
+    assert_eq!(mapper.ppu_bus_access(0x2345, PpuBusAccess::Background, 0), Some(0x07));
+    assert_eq!(mapper.ppu_bus_access(0x23D1, PpuBusAccess::Background, 0), Some(0xFF));
+    assert_eq!(mapper.ppu_bus_access(0x0075, PpuBusAccess::Background, 0), Some(8));
+    for address in 0x2346..0x2349 {
+      mapper.ppu_bus_access(address, PpuBusAccess::Background, 0);
+    }
+    // Column 4 is past the split
+    assert_eq!(mapper.ppu_bus_access(0x2349, PpuBusAccess::Background, 0), None);
+    assert_eq!(mapper.ppu_bus_access(0x0075, PpuBusAccess::Background, 0), Some(0));
+  }
+
+  #[test]
+  fn scan_line_irq() {
+    let mut mapper = mmc5();
+    mapper.write_expansion_u8(0x5203, 10);
+    mapper.write_expansion_u8(0x5204, 0x80);
+
+    // Pre-render line, the frame starts with the first fetch of line 0
+    fetch_line(&mut mapper);
+    let mut irq_lines = Vec::new();
+    for line in 0..240 {
+      fetch_line(&mut mapper);
+      if mapper.irq_flag() {
+        irq_lines.push(line);
+      }
+    }
+    // Stays asserted from the compare line on until $5204 is read
+    assert_eq!(irq_lines, (10..240).collect::<Vec<_>>());
+
+    mapper.clock_cpu();
+    assert_eq!(mapper.read_expansion_u8(0x5204), 0xC0);
+    assert!(!mapper.irq_flag());
+
+    // No reads for three cycles end the frame
+    for _ in 0..3 {
+      mapper.clock_cpu();
+    }
+    assert_eq!(mapper.read_expansion_u8(0x5204), 0x00);
+  }
+
+  #[test]
+  fn multiplier_and_audio() {
+    let mut mapper = mmc5();
+    mapper.write_expansion_u8(0x5205, 12);
+    mapper.write_expansion_u8(0x5206, 34);
+    assert_eq!((mapper.read_expansion_u8(0x5205), mapper.read_expansion_u8(0x5206)), (0x98, 0x01));
+
+    // Full volume with a period the APU pulses would mute
+    mapper.write_expansion_u8(0x5015, 0x01);
+    mapper.write_expansion_u8(0x5000, 0x3F);
+    mapper.write_expansion_u8(0x5002, 0x04);
+    mapper.write_expansion_u8(0x5003, 0x08);
+    assert_eq!(mapper.read_expansion_u8(0x5015), 0x01);
+    let levels: Vec<f64> = (0..100).map(|_| {
+      mapper.clock_cpu();
+      mapper.audio_output()
+    }).collect();
+    assert!(levels.contains(&1.0) && levels.contains(&0.0));
+
+    mapper.write_expansion_u8(0x5015, 0x00);
+    mapper.write_expansion_u8(0x5011, 0xFF);
+    assert!((mapper.audio_output() - 3.8).abs() < 1e-9);
+
+    // Read mode takes the PCM from reads of $8000-$BFFF, a zero raises the IRQ
+    mapper.write_expansion_u8(0x5010, 0x81);
+    mapper.write_expansion_u8(0x5114, 0x80);
+    mapper.mapped_read_cpu_u8(0x8000);
+    assert!(mapper.irq_flag());
+    assert_eq!(mapper.read_expansion_u8(0x5010), 0x80);
+    assert!(!mapper.irq_flag());
+  }
+}
@@ -0,0 +1,86 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, read_prg_32k, write_chr};
+use crate::mapper::pager::PageSize::Eight;
+
+/// GxROM and MHROM, bits 4-5 of the register pick the 32 KiB PRG bank and bits 0-1 the 8 KiB CHR bank
+#[derive(Clone)]
+pub(crate) struct Mapper66 {
+  prg_bank: usize,
+  chr_bank: usize,
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper66 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper66 {
+    let mirroring = rom.borrow().rom_header.mirroring;
+
+    Mapper66 {
+      prg_bank: 0,
+      chr_bank: 0,
+      mirroring,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for Mapper66 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    read_prg_32k(&self.get_rom(), self.prg_bank, address)
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    if address >= 0x8000 {
+      self.prg_bank = usize::from(data >> 4 & 0x03);
+      self.chr_bank = usize::from(data & 0x03);
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    read_chr(&self.get_rom(), self.chr_bank, Eight, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    write_chr(&mut self.get_mut_rom(), self.chr_bank, Eight, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper66::Mapper66;
+  use crate::mapper::pager::PageSize::{Eight, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn prg_and_chr_banks() {
+    let mut mapper = Mapper66::new(numbered_rom(66, 4, ThirtyTwo, 4, Eight));
+    mapper.mapped_write_cpu_u8(0x8000, 0x21);
+    assert_eq!((mapper.mapped_read_cpu_u8(0xC000), mapper.mapped_read_ppu_u8(0x1000)), (2, 1));
+    // Unused bits are ignored
+    mapper.mapped_write_cpu_u8(0xFFFF, 0xCE);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_ppu_u8(0x0000)), (0, 2));
+
+    // Dumped with CHR RAM, the bank bits wrap around its single bank
+    let mut mapper = Mapper66::new(numbered_rom(66, 4, ThirtyTwo, 0, Eight));
+    mapper.mapped_write_cpu_u8(0x8000, 0x03);
+    mapper.mapped_write_ppu_u8(0x0123, 0x42);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x0123), 0x42);
+  }
+}
@@ -0,0 +1,81 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, read_prg_32k, write_chr};
+use crate::mapper::pager::PageSize::Eight;
+
+/// AxROM, one register picks the 32 KiB PRG bank and which nametable fills the screen, with 8 KiB of CHR RAM
+#[derive(Clone)]
+pub(crate) struct Mapper7 {
+  prg_bank: usize,
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper7 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper7 {
+    Mapper7 {
+      prg_bank: 0,
+      mirroring: Mirroring::SingleScreenLower,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for Mapper7 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    read_prg_32k(&self.get_rom(), self.prg_bank, address)
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    if address >= 0x8000 {
+      self.prg_bank = usize::from(data & 0x07);
+      self.mirroring = if data & 0x10 > 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    read_chr(&self.get_rom(), 0, Eight, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    write_chr(&mut self.get_mut_rom(), 0, Eight, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::cartridge::rom_reading::Mirroring;
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper7::Mapper7;
+  use crate::mapper::pager::PageSize::{Eight, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn prg_bank_and_single_screen() {
+    let mut mapper = Mapper7::new(numbered_rom(7, 8, ThirtyTwo, 0, Eight));
+    assert_eq!(mapper.mapped_read_cpu_u8(0xFFFF), 0);
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+
+    mapper.mapped_write_cpu_u8(0x8000, 0x15);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_cpu_u8(0xFFFF)), (5, 5));
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+
+    mapper.mapped_write_ppu_u8(0x1234, 0x42);
+    assert_eq!(mapper.mapped_read_ppu_u8(0x1234), 0x42);
+  }
+}
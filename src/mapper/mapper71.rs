@@ -0,0 +1,113 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, write_chr};
+use crate::mapper::pager::Page;
+use crate::mapper::pager::PageSize::{Eight, Sixteen};
+
+/// Camerica and Codemasters boards, a switchable 16 KiB PRG bank at `$8000` with the last bank fixed at `$C000`, and CHR
+/// RAM
+#[derive(Clone)]
+pub(crate) struct Mapper71 {
+  // Only the Fire Hawk board (BF9097) has one, told apart by NES 2.0 submapper 1. The mirroring of the other
+  // boards is soldered and ignores writes there.
+  has_mirroring_register: bool,
+  prg_bank: usize,
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper71 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper71 {
+    let header = rom.borrow().rom_header;
+
+    Mapper71 {
+      has_mirroring_register: header.submapper == 1,
+      prg_bank: 0,
+      mirroring: header.mirroring,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for Mapper71 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    match address {
+      // Open bus
+      0x6000..=0x7FFF => (address >> 8) as u8,
+      0x8000..=0xBFFF => {
+        let rom = self.get_rom();
+        let bank_count = rom.prg_rom.data.len() / Sixteen.value();
+        rom.prg_rom.read(Page::FromNth(self.prg_bank % bank_count, Sixteen), address - 0x8000)
+      }
+      0xC000..=0xFFFF => self.get_rom().prg_rom.read(Page::Last(Sixteen), address - 0xC000),
+      _ => panic!("Invalid mapped_read_cpu_u8 address 0x{:04X}", address),
+    }
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    match address {
+      0x9000..=0x9FFF if self.has_mirroring_register => {
+        self.mirroring = if data & 0x10 > 0 { Mirroring::SingleScreenUpper } else { Mirroring::SingleScreenLower };
+      }
+      0xC000..=0xFFFF => self.prg_bank = usize::from(data),
+      _ => {}
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    read_chr(&self.get_rom(), 0, Eight, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    write_chr(&mut self.get_mut_rom(), 0, Eight, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::cartridge::rom_reading::Mirroring;
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper71::Mapper71;
+  use crate::mapper::pager::PageSize::{Eight, Sixteen};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn prg_bank_and_fire_hawk_mirroring() {
+    let mut mapper = Mapper71::new(numbered_rom(71, 8, Sixteen, 0, Eight));
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_cpu_u8(0xC000)), (0, 7));
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+    mapper.mapped_write_cpu_u8(0xC000, 3);
+    assert_eq!((mapper.mapped_read_cpu_u8(0xBFFF), mapper.mapped_read_cpu_u8(0xFFFF)), (3, 7));
+    // $8000-$BFFF is not the bank register
+    mapper.mapped_write_cpu_u8(0x8000, 5);
+    assert_eq!(mapper.mapped_read_cpu_u8(0x8000), 3);
+
+    // Soldered mirroring except on Fire Hawk
+    mapper.mapped_write_cpu_u8(0x9000, 0x10);
+    assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+    let rom = numbered_rom(71, 8, Sixteen, 0, Eight);
+    rom.borrow_mut().rom_header.submapper = 1;
+    let mut mapper = Mapper71::new(rom);
+    mapper.mapped_write_cpu_u8(0x9000, 0x10);
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    mapper.mapped_write_cpu_u8(0x9FFF, 0x00);
+    assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+  }
+}
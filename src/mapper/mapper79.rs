@@ -0,0 +1,88 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::{Mapper, read_chr, read_prg_32k, write_chr};
+use crate::mapper::pager::PageSize::Eight;
+
+/// AVE NINA-03 and NINA-06, the register in expansion space picks the 32 KiB PRG bank from bit 3 and the 8 KiB CHR
+/// bank from bits 0-2
+#[derive(Clone)]
+pub(crate) struct Mapper79 {
+  prg_bank: usize,
+  chr_bank: usize,
+  mirroring: Mirroring,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl Mapper79 {
+  pub fn new(rom: Rc<RefCell<RomData>>) -> Mapper79 {
+    let mirroring = rom.borrow().rom_header.mirroring;
+
+    Mapper79 {
+      prg_bank: 0,
+      chr_bank: 0,
+      mirroring,
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for Mapper79 {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    read_prg_32k(&self.get_rom(), self.prg_bank, address)
+  }
+
+  fn mapped_write_cpu_u8(&mut self, _address: u16, _data: u8) {}
+
+  // Decoded from A8, A13 and A14 only, so the register repeats every 512 bytes over 0x4100-0x5FFF
+  fn write_expansion_u8(&mut self, address: u16, data: u8) {
+    if address & 0xE100 == 0x4100 {
+      self.prg_bank = usize::from(data >> 3 & 0x01);
+      self.chr_bank = usize::from(data & 0x07);
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    read_chr(&self.get_rom(), self.chr_bank, Eight, address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    write_chr(&mut self.get_mut_rom(), self.chr_bank, Eight, address, data);
+  }
+
+  fn mirroring(&self) -> Mirroring {
+    self.mirroring
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::mapper::Mapper;
+  use crate::mapper::mapper79::Mapper79;
+  use crate::mapper::pager::PageSize::{Eight, ThirtyTwo};
+  use crate::mapper::test::numbered_rom;
+
+  #[test]
+  fn expansion_register_banks() {
+    let mut mapper = Mapper79::new(numbered_rom(79, 2, ThirtyTwo, 8, Eight));
+    mapper.write_expansion_u8(0x4100, 0x0D);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_ppu_u8(0x0000)), (1, 5));
+    // Mirrored at 0x5F00
+    mapper.write_expansion_u8(0x5F00, 0x02);
+    assert_eq!((mapper.mapped_read_cpu_u8(0xFFFF), mapper.mapped_read_ppu_u8(0x1FFF)), (0, 2));
+    // A8 clear, not the register
+    mapper.write_expansion_u8(0x4200, 0x0F);
+    mapper.mapped_write_cpu_u8(0x8000, 0x0F);
+    assert_eq!((mapper.mapped_read_cpu_u8(0x8000), mapper.mapped_read_ppu_u8(0x0000)), (0, 2));
+  }
+}
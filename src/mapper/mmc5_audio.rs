@@ -0,0 +1,105 @@
+use std::cell::Cell;
+
+use crate::apu::pulse::Pulse;
+
+// The pulse envelopes and length counters are clocked at 240 Hz by a timer of their own
+const AUDIO_FRAME_CYCLES: u16 = 7457;
+// Level of a full scale PCM sample in full volume pulses, about the range of the DMC
+const PCM_RANGE: f64 = 3.8;
+
+/// Sound unit of the MMC5 at 0x5000-0x5015, two pulses without sweep and an 8-bit PCM, shared by the MMC5 board and
+/// the NSF player
+#[derive(Clone)]
+pub(crate) struct Mmc5Audio {
+  pulses: [Pulse; 2],
+  is_odd_cycle: bool,
+  audio_cycles: u16,
+  // PCM read mode, where reads of $8000-$BFFF feed the DAC and a zero raises the IRQ
+  is_pcm_read_mode: bool,
+  pcm_irq_enabled: bool,
+  pcm_irq: Cell<bool>,
+  pcm_output: Cell<u8>,
+}
+
+impl Mmc5Audio {
+  pub fn new() -> Mmc5Audio {
+    Mmc5Audio {
+      pulses: [Pulse::without_sweep(), Pulse::without_sweep()],
+      is_odd_cycle: false,
+      audio_cycles: 0,
+      is_pcm_read_mode: false,
+      pcm_irq_enabled: false,
+      pcm_irq: Cell::new(false),
+      pcm_output: Cell::new(0),
+    }
+  }
+
+  pub fn read_u8(&mut self, address: u16) -> u8 {
+    match address {
+      0x5010 => {
+        let data = u8::from(self.pcm_irq.get()) << 7;
+        self.pcm_irq.set(false);
+        data
+      }
+      0x5015 => u8::from(self.pulses[0].is_playing()) | u8::from(self.pulses[1].is_playing()) << 1,
+      _ => (address >> 8) as u8,
+    }
+  }
+
+  pub fn write_u8(&mut self, address: u16, data: u8) {
+    match address {
+      0x5000..=0x5003 => self.pulses[0].pulse_write_reg_u8(address, data),
+      0x5004..=0x5007 => self.pulses[1].pulse_write_reg_u8(address, data),
+      0x5010 => {
+        self.is_pcm_read_mode = data & 0x01 > 0;
+        self.pcm_irq_enabled = data & 0x80 > 0;
+      }
+      0x5011 if !self.is_pcm_read_mode && data != 0 => self.pcm_output.set(data),
+      0x5015 => {
+        self.pulses[0].set_enabled(data & 0x01 > 0);
+        self.pulses[1].set_enabled(data & 0x02 > 0);
+      }
+      _ => {}
+    }
+  }
+
+  /// Watches the CPU reads of program memory, in read mode those of 0x8000-0xBFFF are the PCM samples
+  pub fn snoop_prg_read(&self, address: u16, data: u8) {
+    if !self.is_pcm_read_mode || !(0x8000..=0xBFFF).contains(&address) {
+      return;
+    }
+    if data == 0 {
+      self.pcm_irq.set(true);
+    } else {
+      self.pcm_output.set(data);
+    }
+  }
+
+  pub fn irq_flag(&self) -> bool {
+    self.pcm_irq.get() && self.pcm_irq_enabled
+  }
+
+  pub fn clock_cpu(&mut self) {
+    self.is_odd_cycle = !self.is_odd_cycle;
+    self.audio_cycles += 1;
+    let is_audio_frame = self.audio_cycles == AUDIO_FRAME_CYCLES;
+    if is_audio_frame {
+      self.audio_cycles = 0;
+    }
+    for pulse in self.pulses.iter_mut() {
+      pulse.start_cycle();
+      if self.is_odd_cycle {
+        pulse.step_sequencer();
+      }
+      if is_audio_frame {
+        pulse.step_quarter_frame();
+        pulse.step_half_frame();
+      }
+    }
+  }
+
+  pub fn output(&self) -> f64 {
+    let pulses = self.pulses[0].sample() + self.pulses[1].sample();
+    f64::from(pulses) / 15.0 + f64::from(self.pcm_output.get()) / 255.0 * PCM_RANGE
+  }
+}
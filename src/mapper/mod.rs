@@ -1,25 +1,87 @@
-use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_reading::{Mirroring, NameTableSource};
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::pager::{Page, PageSize, Pager};
 
 pub mod mapper0;
 pub mod mapper1;
 pub mod mapper2;
 pub mod mapper3;
 pub mod mapper4;
+pub mod mapper5;
+pub mod mapper7;
+pub mod mapper11;
+pub mod mapper34;
+pub mod mapper66;
+pub mod mapper71;
+pub mod mapper79;
+pub mod mapper140;
+pub mod mmc5_audio;
+pub mod nsf_mapper;
 pub mod pager;
 
+/// What puts an address on the PPU bus
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PpuBusAccess {
+  /// Nametable, attribute and pattern fetches of the background, the dummy nametable fetches included
+  Background,
+  /// Sprite pattern fetches
+  Sprite,
+  /// The VRAM address outside of rendering, set by $2006 and $2007 accesses
+  Cpu,
+}
+
+/// Parts of the visible lines a mapper needs the PPU to fetch on time, the rest can be rendered in one go
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PpuBusWatch {
+  None,
+  /// The sprite fetches, where the MMC3 IRQ counter sees A12 rise
+  SpriteFetches,
+  /// Every fetch, the MMC5 detects scanlines on the first fetches of the line
+  AllFetches,
+}
+
 pub trait Mapper: MapperClone {
   fn mapped_read_cpu_u8(&self, address: u16) -> u8;
   fn mapped_write_cpu_u8(&mut self, address: u16, data: u8);
+  /// Cartridge space at 0x4020-0x5FFF, unmapped on most boards so reads see open bus
+  fn read_expansion_u8(&mut self, address: u16) -> u8 {
+    (address >> 8) as u8
+  }
+  fn write_expansion_u8(&mut self, _address: u16, _data: u8) {}
   fn mapped_read_ppu_u8(&self, address: u16) -> u8;
   fn mapped_write_ppu_u8(&mut self, address: u16, data: u8);
   fn mirroring(&self) -> Mirroring {
     Mirroring::Horizontal
   }
+  /// Per quadrant nametable mapping, mappers with more than plain mirroring override this
+  fn name_tables(&self) -> [NameTableSource; 4] {
+    self.mirroring().name_tables()
+  }
+  fn read_name_table(&self, _address: u16) -> u8 {
+    0
+  }
+  fn write_name_table(&mut self, _address: u16, _data: u8) {}
   fn irq_flag(&self) -> bool {
     false
   }
-  fn signal_scanline(&mut self) {}
-  fn clear_irq_flag(&mut self) {}
+  /// Address put on the PPU bus by rendering fetches and by $2006/$2007 accesses outside of rendering, on PPU dot
+  /// `dot` since power up. Palette accesses stay inside the PPU. Mappers that answer a rendering fetch with their
+  /// own data return it, the PPU reads its memory map otherwise.
+  fn ppu_bus_access(&mut self, _address: u16, _access: PpuBusAccess, _dot: u64) -> Option<u8> {
+    None
+  }
+  fn ppu_bus_watch(&self) -> PpuBusWatch {
+    PpuBusWatch::None
+  }
+  /// CPU writes to $2000-$3FFF, for mappers that snoop the PPU registers
+  fn snoop_ppu_register(&mut self, _address: u16, _data: u8) {}
+  /// Clocked once per CPU cycle, for mappers with cycle timers or sound chips
+  fn clock_cpu(&mut self) {}
+  /// Output of the expansion sound chip mixed with the APU through the cartridge port. The unit is the level of one
+  /// APU pulse channel at full volume, chips scale their channels to their own loudness relative to it.
+  fn audio_output(&self) -> f64 {
+    0.0
+  }
 }
 
 pub trait MapperClone {
@@ -37,3 +99,64 @@ impl Clone for Box<dyn Mapper> {
     self.clone_mapper()
   }
 }
+
+/// 0x6000-0xFFFF on boards that switch the PRG ROM 32 KiB at a time and have no PRG RAM, banks past the end of the
+/// ROM wrap around
+pub(crate) fn read_prg_32k(rom: &RomData, bank: usize, address: u16) -> u8 {
+  match address {
+    // Open bus
+    0x6000..=0x7FFF => (address >> 8) as u8,
+    0x8000..=0xFFFF => {
+      let bank_count = (rom.prg_rom.data.len() / PageSize::ThirtyTwo.value()).max(1);
+      rom.prg_rom.read(Page::FromNth(bank % bank_count, PageSize::ThirtyTwo), address - 0x8000)
+    }
+    _ => panic!("Invalid mapped_read_cpu_u8 address 0x{:04X}", address),
+  }
+}
+
+// CHR RAM stands in for the CHR ROM of boards dumped without one
+fn chr_memory(rom: &RomData) -> &Pager {
+  if rom.rom_header.chr_rom_len == 0 { &rom.chr_ram } else { &rom.chr_rom }
+}
+
+fn chr_page(chr: &Pager, bank: usize, size: PageSize) -> Page {
+  let bank_count = (chr.data.len() / size.value()).max(1);
+  Page::FromNth(bank % bank_count, size)
+}
+
+/// Reads `address` in CHR bank `bank` of `size`, banks past the end of the CHR memory wrap around
+pub(crate) fn read_chr(rom: &RomData, bank: usize, size: PageSize, address: u16) -> u8 {
+  let chr = chr_memory(rom);
+  chr.read(chr_page(chr, bank, size), address & (size.value() - 1) as u16)
+}
+
+/// Writes `address` in CHR bank `bank` of `size` when the CHR memory is RAM
+pub(crate) fn write_chr(rom: &mut RomData, bank: usize, size: PageSize, address: u16, data: u8) {
+  if rom.rom_header.chr_rom_len == 0 {
+    let page = chr_page(&rom.chr_ram, bank, size);
+    rom.chr_ram.write(page, address & (size.value() - 1) as u16, data);
+  }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::cartridge::rom_reading::Rom;
+  use crate::cartridge::rom_with_pager::RomData;
+  use crate::mapper::pager::PageSize;
+
+  /// ROM of the mapper with each PRG and CHR bank filled with its number, 8 KiB of CHR RAM when there are no CHR
+  /// banks
+  pub fn numbered_rom(mapper: u16, prg_banks: usize, prg_size: PageSize, chr_banks: usize, chr_size: PageSize) -> Rc<RefCell<RomData>> {
+    let mut rom = Rom::mock_rom();
+    rom.rom_header.mapper = mapper;
+    rom.prg_rom = (0..prg_banks).flat_map(|bank| vec![bank as u8; prg_size.value()]).collect();
+    rom.rom_header.prg_rom_len = rom.prg_rom.len();
+    rom.chr_rom = (0..chr_banks).flat_map(|bank| vec![bank as u8; chr_size.value()]).collect();
+    rom.rom_header.chr_rom_len = rom.chr_rom.len();
+    rom.chr_ram = vec![0; 0x2000];
+    Rc::new(RefCell::new(RomData::new(rom)))
+  }
+}
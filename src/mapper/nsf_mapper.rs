@@ -0,0 +1,156 @@
+use std::cell::{Ref, RefCell, RefMut};
+use std::rc::Rc;
+
+use crate::cartridge::rom_with_pager::RomData;
+use crate::mapper::Mapper;
+use crate::mapper::mmc5_audio::Mmc5Audio;
+use crate::mapper::pager::Page;
+use crate::mapper::pager::PageSize::{Eight, Four};
+use crate::nsf::{BANK_SIZE, MMC5_AUDIO};
+
+/// Synthetic board of NSF players, eight 4 KiB PRG banks switched by writes to 0x5FF8-0x5FFF and 8 KiB of work RAM,
+/// with the MMC5 sound unit when the music uses it
+#[derive(Clone)]
+pub(crate) struct NsfMapper {
+  banks: [u8; 8],
+  bank_count: usize,
+  mmc5_audio: Option<Mmc5Audio>,
+  rom: Rc<RefCell<RomData>>,
+}
+
+impl NsfMapper {
+  pub fn new(rom: Rc<RefCell<RomData>>, banks: [u8; 8], expansion_chips: u8) -> NsfMapper {
+    let bank_count = rom.borrow().prg_rom.data.len() / BANK_SIZE;
+
+    NsfMapper {
+      banks,
+      bank_count,
+      mmc5_audio: (expansion_chips & MMC5_AUDIO > 0).then(Mmc5Audio::new),
+      rom,
+    }
+  }
+
+  fn get_rom(&self) -> Ref<'_, RomData> {
+    self.rom.borrow()
+  }
+
+  fn get_mut_rom(&self) -> RefMut<'_, RomData> {
+    self.rom.borrow_mut()
+  }
+}
+
+impl Mapper for NsfMapper {
+  fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+    match address {
+      0x6000..=0x7FFF => self.get_rom().prg_ram.read(Page::First(Eight), address - 0x6000),
+      0x8000..=0xFFFF => {
+        let bank = usize::from(self.banks[usize::from((address - 0x8000) >> 12)]) % self.bank_count;
+        let data = self.get_rom().prg_rom.read(Page::FromNth(bank, Four), address & 0x0FFF);
+        if let Some(audio) = &self.mmc5_audio {
+          audio.snoop_prg_read(address, data);
+        }
+        data
+      }
+      _ => panic!("Invalid mapped_read_cpu_u8 0x{:04X}", address)
+    }
+  }
+
+  fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+    match address {
+      0x6000..=0x7FFF => self.get_mut_rom().prg_ram.write(Page::First(Eight), address - 0x6000, data),
+      // Writes to ROM are ignored
+      0x8000..=0xFFFF => (),
+      _ => panic!("Invalid mapped_write_cpu_u8 0x{:04X}", address)
+    }
+  }
+
+  fn read_expansion_u8(&mut self, address: u16) -> u8 {
+    match (address, &mut self.mmc5_audio) {
+      (0x5010 | 0x5015, Some(audio)) => audio.read_u8(address),
+      _ => (address >> 8) as u8,
+    }
+  }
+
+  fn write_expansion_u8(&mut self, address: u16, data: u8) {
+    match (address, &mut self.mmc5_audio) {
+      (0x5000..=0x5015, Some(audio)) => audio.write_u8(address, data),
+      (0x5FF8..=0x5FFF, _) => self.banks[usize::from(address - 0x5FF8)] = data,
+      _ => {}
+    }
+  }
+
+  fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+    self.get_rom().chr_ram.read(Page::First(Eight), address)
+  }
+
+  fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+    self.get_mut_rom().chr_ram.write(Page::First(Eight), address, data);
+  }
+
+  fn clock_cpu(&mut self) {
+    if let Some(audio) = &mut self.mmc5_audio {
+      audio.clock_cpu();
+    }
+  }
+
+  fn audio_output(&self) -> f64 {
+    self.mmc5_audio.as_ref().map_or(0.0, Mmc5Audio::output)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::cartridge::Cartridge;
+  use crate::nsf::{MMC5_AUDIO, Nsf};
+  use crate::nsf::test::mock_nsf_bytes;
+
+  #[test]
+  fn bank_registers() {
+    let mut program = vec![0u8; 0x3000];
+    for (bank, chunk) in program.chunks_mut(0x1000).enumerate() {
+      chunk.fill(bank as u8 + 1);
+    }
+    let nsf = Nsf::from_bytes(&mock_nsf_bytes(&program, [2, 1, 0, 0, 0, 0, 0, 0]));
+    let mut cart = Cartridge::from_nsf(&nsf);
+
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0x8000), 3);
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0x9FFF), 2);
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0xF000), 1);
+
+    cart.mapper.write_expansion_u8(0x5FFF, 2);
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0xFFFF), 3);
+    // Banks past the end of the data wrap around
+    cart.mapper.write_expansion_u8(0x5FF8, 4);
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0x8000), 2);
+
+    cart.mapper.mapped_write_cpu_u8(0x6000, 0x42);
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0x6000), 0x42);
+  }
+
+  #[test]
+  fn mmc5_audio() {
+    let mut bytes = mock_nsf_bytes(&[0x60; 0x20], [0; 8]);
+    let mut cart = Cartridge::from_nsf(&Nsf::from_bytes(&bytes));
+    cart.mapper.write_expansion_u8(0x5011, 0xFF);
+    assert_eq!(cart.clock_cpu(), 0.0);
+    assert_eq!(cart.mapper.read_expansion_u8(0x5015), 0x50);
+
+    bytes[0x7B] = MMC5_AUDIO;
+    let mut cart = Cartridge::from_nsf(&Nsf::from_bytes(&bytes));
+    cart.mapper.write_expansion_u8(0x5011, 0xFF);
+    assert!((cart.clock_cpu() - 3.8).abs() < 1e-9);
+
+    // Pulse 2 on top of the PCM level
+    cart.mapper.write_expansion_u8(0x5015, 0x02);
+    cart.mapper.write_expansion_u8(0x5004, 0x3F);
+    cart.mapper.write_expansion_u8(0x5006, 0x04);
+    cart.mapper.write_expansion_u8(0x5007, 0x08);
+    assert_eq!(cart.mapper.read_expansion_u8(0x5015), 0x02);
+    let levels: Vec<f64> = (0..100).map(|_| cart.clock_cpu()).collect();
+    assert!(levels.iter().any(|&level| level > 4.0) && levels.iter().any(|&level| level < 4.0));
+
+    // The bank registers still work next to the sound unit
+    cart.mapper.write_expansion_u8(0x5FF8, 0);
+    assert_eq!(cart.mapper.mapped_read_cpu_u8(0x8000), 0x60);
+  }
+}
@@ -4,6 +4,7 @@ pub enum PageSize {
   Four = 0x1000,
   Eight = 0x2000,
   Sixteen = 0x4000,
+  ThirtyTwo = 0x8000,
 }
 
 impl PageSize {
@@ -13,6 +14,7 @@ impl PageSize {
       PageSize::Four => 0x1000,
       PageSize::Eight => 0x2000,
       PageSize::Sixteen => 0x4000,
+      PageSize::ThirtyTwo => 0x8000,
     }
   }
 }
@@ -46,12 +48,13 @@ impl Pager {
     self.data[idx] = value;
   }
 
+  // Every read goes through here, page sizes are powers of two so this avoids divisions
   fn page_count(&self, size: PageSize) -> usize {
-    if self.data.len() % size.value() != 0 {
+    if self.data.len() & (size.value() - 1) != 0 {
       panic!("Page size must divide evenly into data length {} % {} == 0", self.data.len(), size.value())
     }
 
-    self.data.len() / (size as usize) - 1
+    (self.data.len() >> size.value().trailing_zeros()) - 1
   }
 
   fn index(&self, page: Page, offset: u16) -> usize {
@@ -0,0 +1,41 @@
+use std::path::PathBuf;
+
+use crate::apu::audio_sink::AudioOutput;
+use crate::apu::channel_mix::MixSettings;
+use crate::gfx::ntsc::NtscPreset;
+use crate::gfx::scaler::Scaler;
+use crate::nes::region::Region;
+
+/// Emulator settings collected from the command line
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+  pub is_dbg: bool,
+  pub video_filter: Option<NtscPreset>,
+  pub scaler: Scaler,
+  /// Overrides the region detected from the ROM
+  pub region: Option<Region>,
+  pub screenshot_dir: PathBuf,
+  /// Run without a window and save a screenshot of this frame, then exit
+  pub screenshot_frame: Option<u64>,
+  /// Base path of the Y4M and WAV recording
+  pub record_path: Option<PathBuf>,
+  /// Run without a window for this many frames, then exit
+  pub frame_limit: Option<u64>,
+  /// Disables the scanline catch-up renderer of the PPU
+  pub is_dot_accurate: bool,
+  /// Audio output rate in Hz, 44100 by default
+  pub sample_rate: Option<u32>,
+  pub audio_output: AudioOutput,
+  /// One-based track of a music file to start with
+  pub track: Option<u8>,
+  /// Log the APU register writes to this VGM file
+  pub vgm_path: Option<PathBuf>,
+  /// Mute, solo, volume and panning of the sound channels
+  pub mix_settings: MixSettings,
+}
+
+impl Config {
+  pub fn is_headless(&self) -> bool {
+    self.screenshot_frame.is_some() || self.frame_limit.is_some()
+  }
+}
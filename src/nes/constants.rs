@@ -1,11 +1,10 @@
+use crate::apu::channel_mix::Channel;
+
 pub const SCREEN_RES_X: u32 = 256;
 pub const SCREEN_RES_Y: u32 = 240;
 
 pub const SCALING_FACTOR: u32 = 4;
 
-// 16ms per frame ~ 60FPS
-pub const REFRESH_RATE: f32 = 1.0 / 60.0;
-
 #[derive(Copy, Clone, Debug)]
 pub struct Color([u8; 3]);
 
@@ -92,4 +91,10 @@ pub enum KeyboardCommand {
   Resize,
   Pause,
   Continue,
+  CycleScaler,
+  Screenshot,
+  NextTrack,
+  PreviousTrack,
+  ToggleMute(Channel),
+  ToggleSolo(Channel),
 }
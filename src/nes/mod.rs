@@ -1,7 +1,9 @@
 use std::{fs, process, thread};
+use std::borrow::Cow;
 use std::cell::{RefCell, RefMut};
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
@@ -14,24 +16,50 @@ use glium::Surface;
 use winit::event::ElementState::Pressed;
 use winit::event_loop::EventLoop;
 use winit::platform::run_return::EventLoopExtRunReturn;
-use crate::apu::Apu;
+use crate::apu::{Apu, DEFAULT_SAMPLE_RATE};
+use crate::apu::channel_mix::Channel;
 use crate::bus::Bus;
 use crate::cartridge::Cartridge;
 use crate::cpu::Cpu;
+use crate::gfx::frame::Frame;
+use crate::gfx::ntsc::{NTSC_OUTPUT_WIDTH, NtscFilter};
+use crate::gfx::scaler::Scaler;
+use crate::gfx::screenshot;
 use crate::gfx::WindowContext;
-use crate::nes::constants::{KeyboardCommand, REFRESH_RATE, SCREEN_RES_X, SCREEN_RES_Y};
+use crate::nes::constants::{KeyboardCommand, SCREEN_RES_X, SCREEN_RES_Y};
+use crate::nes::config::Config;
 use crate::nes::controller::Controller;
 use crate::nes::debug_view::DebugView;
+use crate::nes::region::Region;
+use crate::nsf::{MMC5_AUDIO, Nsf};
+use crate::nsf::driver::NsfDriver;
 use crate::ppu::{Ppu, PpuState, registers::Registers};
+use crate::recorder::Recorder;
 use winit::event_loop::ControlFlow;
 
+pub mod config;
 pub mod controller;
 pub mod constants;
 mod debug_view;
+pub mod region;
 
 pub type OffScreenBuffer = [[u8; 3]; (SCREEN_RES_X * SCREEN_RES_Y) as usize];
+pub type PaletteIndexBuffer = [u16; (SCREEN_RES_X * SCREEN_RES_Y) as usize];
 
-const FRAME_DURATION: Duration = Duration::from_millis((REFRESH_RATE * 1000.0) as u64);
+/// Output of the NTSC filter, or the PPU pixels as they are without one
+fn filtered_frame<'a>(ntsc_filter: Option<&'a mut NtscFilter>, ppu: &Ppu, off_screen: &OffScreenBuffer) -> Cow<'a, Frame> {
+  match ntsc_filter {
+    Some(filter) => Cow::Borrowed(filter.apply(ppu.palette_indices(), ppu.is_even_frame)),
+    None => Cow::Owned(Frame::from_off_screen(off_screen)),
+  }
+}
+
+fn save_screenshot(frame: &Frame, path: &Path) {
+  match screenshot::save_png(frame, path) {
+    Ok(()) => println!("Screenshot saved to {}", path.display()),
+    Err(e) => eprintln!("Failed to save screenshot {}: {}", path.display(), e),
+  }
+}
 
 fn init_controller() -> Gilrs {
   match GilrsBuilder::new().set_update_state(false).build() {
@@ -51,47 +79,125 @@ fn init_controller() -> Gilrs {
 pub struct Nes {
   apu: Rc<RefCell<Apu>>,
   cpu: Cpu,
-  ppu: Ppu,
-  system_cycles: u32,
-  window_context: WindowContext,
+  // Runs the CPU instead of the reset vector when playing a music file
+  nsf_driver: Option<NsfDriver>,
+  ppu: Rc<RefCell<Ppu>>,
+  cpu_cycles: u32,
+  // CPU cycles left to wait for the DMC sample fetches
+  cpu_stall_cycles: u32,
+  // Position within the PPU dots of a CPU cycle, see `Region::cpu_divider`
+  cpu_phase: u32,
+  cpu_divider: (u32, u32),
+  frame_duration: Duration,
+  window_context: Option<WindowContext>,
   controller: Rc<RefCell<Controller>>,
   off_screen_pixels: Rc<RefCell<OffScreenBuffer>>,
+  ntsc_filter: Option<NtscFilter>,
+  scaler: Scaler,
+  // Recordings keep the scaler they started with, the video size can't change
+  recording_scaler: Scaler,
+  rom_file: String,
+  screenshot_dir: PathBuf,
+  screenshot_frame: Option<u64>,
+  frame_limit: Option<u64>,
+  frame_count: u64,
+  recorder: Option<Recorder>,
   memory_hash: u64,
   dbg_view: Option<DebugView>,
   is_dbg: bool,
   is_paused: bool,
+  is_fast_forward: bool,
   gilrs: Gilrs,
   input_filter: Repeat,
-  event_loop: Rc<RefCell<EventLoop<()>>>,
+  event_loop: Option<Rc<RefCell<EventLoop<()>>>>,
   resize: bool,
 }
 
 impl Nes {
-  pub fn new(rom_file: &str, is_dbg: bool) -> Self {
+  pub fn new(rom_file: &str, config: &Config) -> Self {
     let rom_bytes = fs::read(rom_file).expect("Rom file read error");
 
-    let cartridge = Cartridge::new(rom_bytes);
+    let nsf = if Nsf::is_nsf(&rom_bytes) { Some(Nsf::from_bytes(&rom_bytes)) } else { None };
+    let cartridge = match nsf.as_ref() {
+      Some(nsf) => Cartridge::from_nsf(nsf),
+      None => Cartridge::new(rom_bytes),
+    };
+    let region = config.region
+      .or(cartridge.rom_header.region)
+      .or_else(|| Region::from_file_name(rom_file))
+      .unwrap_or_default();
     let cart = Rc::new(RefCell::new(cartridge));
 
-    let event_loop = Rc::new(RefCell::new(winit::event_loop::EventLoopBuilder::new().build()));
-    let window_context = WindowContext::new(event_loop.clone());
+    let (event_loop, window_context) = if config.is_headless() {
+      (None, None)
+    } else {
+      let event_loop = Rc::new(RefCell::new(winit::event_loop::EventLoopBuilder::new().build()));
+      let window_context = WindowContext::new(event_loop.clone());
+      (Some(event_loop), Some(window_context))
+    };
 
     let controller = Rc::new(RefCell::new(Controller::new()));
 
-    let apu = Rc::new(RefCell::new(Apu::new()));
-
-    let registers = Rc::new(RefCell::new(Registers::new(cart.clone())));
-    let bus = Bus::new(cart, registers.clone(), controller.clone(), apu.clone());
+    let sample_rate = config.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    let audio_sink = config.audio_output.open_sink(sample_rate);
+    let mut apu = Apu::new(region, sample_rate, audio_sink);
+    apu.set_mix_settings(config.mix_settings.clone());
+    if let Some(path) = config.vgm_path.as_ref() {
+      apu.start_vgm_log(path).unwrap_or_else(|e| panic!("Failed to create VGM file {}: {}", path.display(), e));
+    }
+    let apu = Rc::new(RefCell::new(apu));
 
-    let cpu = Cpu::new(bus);
+    let registers = Rc::new(RefCell::new(Registers::new(cart.clone(), region)));
 
     let off_screen: OffScreenBuffer = [[0u8; 3]; (SCREEN_RES_X * SCREEN_RES_Y) as usize];
     let off_screen_pixels = Rc::new(RefCell::new(off_screen));
-    let ppu = Ppu::new(registers, off_screen_pixels.clone());
+    let mut ppu = Ppu::new(registers.clone(), off_screen_pixels.clone(), region);
+    ppu.is_catch_up = !config.is_dot_accurate;
+    let ppu = Rc::new(RefCell::new(ppu));
 
-    let system_cycles = 0;
+    let bus = Bus::new(cart, registers, ppu.clone(), controller.clone(), apu.clone());
 
+    let cpu = Cpu::new(bus);
+
+    let nsf_driver = nsf.map(|nsf| {
+      println!("{} - {} ({})", nsf.title, nsf.artist, nsf.copyright);
+      let missing_chips = nsf.expansion_chips & !MMC5_AUDIO;
+      if missing_chips != 0 {
+        eprintln!("Expansion audio 0x{:02X} is not emulated", missing_chips);
+      }
+      let driver = NsfDriver::new(&nsf, region, config.track.map(|track| track.saturating_sub(1)));
+      println!("Track {}/{}", driver.song() + 1, driver.total_songs());
+      driver
+    });
+
+    let cpu_cycles = 0;
+    let cpu_stall_cycles = 0;
+    let cpu_phase = 0;
+    let cpu_divider = region.cpu_divider();
+    let (frame_rate_num, frame_rate_den) = region.frame_rate();
+    let frame_duration = Duration::from_secs_f64(f64::from(frame_rate_den) / f64::from(frame_rate_num));
+
+    let ntsc_filter = config.video_filter.map(NtscFilter::new);
+    let scaler = config.scaler;
+
+    let rom_file = rom_file.to_string();
+    let screenshot_dir = config.screenshot_dir.clone();
+    let screenshot_frame = config.screenshot_frame;
+    let frame_limit = config.frame_limit;
+    let frame_count = 0;
+
+    let recording_scaler = scaler;
+    let recorder = config.record_path.as_ref().map(|path| {
+      let width = if ntsc_filter.is_some() { NTSC_OUTPUT_WIDTH } else { SCREEN_RES_X };
+      let (width, height) = (width * scaler.factor(), SCREEN_RES_Y * scaler.factor());
+      let recorder = Recorder::create(path, width, height, region.frame_rate(), apu.borrow().sample_rate());
+      recorder.unwrap_or_else(|e| panic!("Failed to start recording {}: {}", path.display(), e))
+    });
+    apu.borrow_mut().set_recording(recorder.is_some());
+
+    let is_dbg = config.is_dbg;
     let is_paused = false;
+    let is_fast_forward = false;
     let memory_hash = 0;
     let dbg_view = if is_dbg { Some(DebugView::new(64, 16)) } else { None };
 
@@ -104,15 +210,30 @@ impl Nes {
     Nes {
       apu,
       cpu,
+      nsf_driver,
       ppu,
-      system_cycles,
+      cpu_cycles,
+      cpu_stall_cycles,
+      cpu_phase,
+      cpu_divider,
+      frame_duration,
       window_context,
       controller,
       off_screen_pixels,
+      ntsc_filter,
+      scaler,
+      recording_scaler,
+      rom_file,
+      screenshot_dir,
+      screenshot_frame,
+      frame_limit,
+      frame_count,
+      recorder,
       memory_hash,
       dbg_view,
       is_dbg,
       is_paused,
+      is_fast_forward,
       gilrs,
       input_filter,
       event_loop,
@@ -125,21 +246,17 @@ impl Nes {
     self.apu.borrow_mut()
   }
 
-
-  #[inline]
-  fn get_event_loop(&mut self) -> RefMut<EventLoop<()>> {
-    self.event_loop.borrow_mut()
-  }
-
   #[inline]
-  fn get_off_screen_pixels(&mut self) -> RefMut<OffScreenBuffer> {
-    self.off_screen_pixels.borrow_mut()
+  fn get_mut_ppu(&mut self) -> RefMut<Ppu> {
+    self.ppu.borrow_mut()
   }
 
   pub fn render_loop(&mut self) {
+    let event_loop = self.event_loop.clone().expect("Render loop needs a window");
     let mut last_time = Instant::now();
 
     let mut keyboard_state = None;
+    let mut is_fast_forward = false;
     // 0x80 | 0x40 | 0x20 | 0x10 | 0x08 | 0x04 | 0x02 | 0x01 == 0xFF
     let mut key_map: [bool; 8] = [false, false, false, false, false, false, false, false];
 
@@ -156,7 +273,7 @@ impl Nes {
       if poll_input {
         poll_input = false;
         let is_paused = self.is_paused;
-        let _ = self.get_event_loop().run_return(|event, _, control_flow| {
+        let _ = event_loop.borrow_mut().run_return(|event, _, control_flow| {
           *control_flow = ControlFlow::Wait;
           if let winit::event::Event::MainEventsCleared = &event {
             *control_flow = ControlFlow::Exit;
@@ -183,9 +300,58 @@ impl Nes {
                     VirtualKeyCode::Down => update_key_map(&mut key_map, 5, input.state == Pressed),
                     VirtualKeyCode::Left => update_key_map(&mut key_map, 6, input.state == Pressed),
                     VirtualKeyCode::Right => update_key_map(&mut key_map, 7, input.state == Pressed),
+                    VirtualKeyCode::Tab => is_fast_forward = input.state == Pressed,
                     VirtualKeyCode::R => {
                       keyboard_state = Some(KeyboardCommand::Reset)
                     }
+                    VirtualKeyCode::F if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::CycleScaler)
+                    }
+                    VirtualKeyCode::F12 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::Screenshot)
+                    }
+                    VirtualKeyCode::PageDown if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::NextTrack)
+                    }
+                    VirtualKeyCode::PageUp if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::PreviousTrack)
+                    }
+                    VirtualKeyCode::Key1 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleMute(Channel::Pulse0))
+                    }
+                    VirtualKeyCode::Key2 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleMute(Channel::Pulse1))
+                    }
+                    VirtualKeyCode::Key3 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleMute(Channel::Triangle))
+                    }
+                    VirtualKeyCode::Key4 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleMute(Channel::Noise))
+                    }
+                    VirtualKeyCode::Key5 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleMute(Channel::Dmc))
+                    }
+                    VirtualKeyCode::Key6 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleMute(Channel::Expansion))
+                    }
+                    VirtualKeyCode::F1 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleSolo(Channel::Pulse0))
+                    }
+                    VirtualKeyCode::F2 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleSolo(Channel::Pulse1))
+                    }
+                    VirtualKeyCode::F3 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleSolo(Channel::Triangle))
+                    }
+                    VirtualKeyCode::F4 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleSolo(Channel::Noise))
+                    }
+                    VirtualKeyCode::F5 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleSolo(Channel::Dmc))
+                    }
+                    VirtualKeyCode::F6 if input.state == Pressed => {
+                      keyboard_state = Some(KeyboardCommand::ToggleSolo(Channel::Expansion))
+                    }
                     _ => {}
                   }
               }
@@ -221,32 +387,122 @@ impl Nes {
           Some(KeyboardCommand::Exit) => break 'app,
           Some(KeyboardCommand::Reset) => {
             self.cpu.reset();
-            self.ppu.reset();
+            self.get_mut_ppu().reset();
             self.get_apu().reset();
+            self.restart_track();
           }
           Some(KeyboardCommand::Resize) => self.resize = true,
+          Some(KeyboardCommand::CycleScaler) => {
+            self.scaler = self.scaler.next();
+            keyboard_state = None;
+          }
+          Some(KeyboardCommand::Screenshot) => {
+            let path = screenshot::timestamped_path(&self.screenshot_dir, &self.rom_file);
+            let ppu = self.ppu.borrow();
+            let frame = filtered_frame(self.ntsc_filter.as_mut(), &ppu, &self.off_screen_pixels.borrow());
+            save_screenshot(&self.scaler.scale(&frame), &path);
+            keyboard_state = None;
+          }
+          Some(KeyboardCommand::NextTrack) | Some(KeyboardCommand::PreviousTrack) => {
+            if let Some(driver) = self.nsf_driver.as_mut() {
+              if keyboard_state == Some(KeyboardCommand::NextTrack) {
+                driver.next_song();
+              } else {
+                driver.previous_song();
+              }
+              println!("Track {}/{}", driver.song() + 1, driver.total_songs());
+            }
+            keyboard_state = None;
+          }
+          Some(KeyboardCommand::ToggleMute(channel)) | Some(KeyboardCommand::ToggleSolo(channel)) => {
+            let mut mix_settings = self.apu.borrow().mix_settings().clone();
+            if keyboard_state == Some(KeyboardCommand::ToggleMute(channel)) {
+              mix_settings.toggle_mute(channel);
+            } else {
+              mix_settings.toggle_solo(channel);
+            }
+            let heard: Vec<String> = Channel::ALL.iter()
+              .filter(|&&channel| mix_settings.is_audible(channel))
+              .map(|channel| channel.to_string())
+              .collect();
+            println!("Channels: {}", if heard.is_empty() { "none".to_string() } else { heard.join(", ") });
+            self.get_apu().set_mix_settings(mix_settings);
+            keyboard_state = None;
+          }
           _ => {}
         }
         self.controller.borrow_mut().update_buttons(key_map);
+        self.is_fast_forward = is_fast_forward;
       }
 
       if !self.is_paused {
         self.clock();
       }
-      if self.ppu.is_frame_ready || self.is_paused {
+      let is_frame_ready = self.ppu.borrow().is_frame_ready;
+      if is_frame_ready || self.is_paused {
         if keyboard_state == Some(KeyboardCommand::Resize) {
           self.resize = true;
         }
         self.render_screen();
-        self.ppu.is_frame_ready = false;
+        self.get_mut_ppu().is_frame_ready = false;
 
-        if let Some(delay) = FRAME_DURATION.checked_sub(last_time.elapsed()) {
-          thread::sleep(delay);
+        // The audio device plays at a steady rate, waiting for it paces the emulation without drifting apart
+        let is_audio_paced = !self.is_paused && !self.is_fast_forward && self.apu.borrow().wait_for_playback();
+        if !self.is_fast_forward && !is_audio_paced {
+          if let Some(delay) = self.frame_duration.checked_sub(last_time.elapsed()) {
+            thread::sleep(delay);
+          }
         }
         poll_input = true;
         last_time = Instant::now();
       }
     } // app loop
+    self.finish_recording();
+  }
+
+  /// Runs without a window as fast as possible until the frame limit and the screenshot frame have been reached
+  pub fn run_headless(&mut self) {
+    let last_frame = self.frame_limit.max(self.screenshot_frame).expect("Headless run needs a frame limit");
+
+    while self.frame_count < last_frame {
+      self.clock();
+    }
+    self.finish_recording();
+  }
+
+  /// Hands the frame to the window, the recording and the screenshot, all through the NTSC filter and the scaler
+  fn on_frame_ready(&mut self) {
+    self.frame_count += 1;
+    let screenshot_path = (self.screenshot_frame == Some(self.frame_count))
+      .then(|| screenshot::frame_path(&self.screenshot_dir, &self.rom_file, self.frame_count));
+    if self.window_context.is_none() && self.recorder.is_none() && screenshot_path.is_none() {
+      return;
+    }
+
+    let ppu = self.ppu.borrow();
+    let frame = filtered_frame(self.ntsc_filter.as_mut(), &ppu, &self.off_screen_pixels.borrow());
+    let shown = self.scaler.scale(&frame);
+
+    if let Some(window_context) = self.window_context.as_mut() {
+      window_context.update_image_buffer(&shown);
+    }
+
+    if let Some(recorder) = self.recorder.as_mut() {
+      let recorded = if self.recording_scaler == self.scaler { Cow::Borrowed(&*shown) } else { self.recording_scaler.scale(&frame) };
+      let samples = self.apu.borrow_mut().take_recorded_samples();
+      recorder.write_frame(&recorded, &samples).expect("Recording write error");
+    }
+
+    if let Some(path) = screenshot_path {
+      save_screenshot(&shown, &path);
+    }
+  }
+
+  fn finish_recording(&mut self) {
+    if let Some(mut recorder) = self.recorder.take() {
+      recorder.finish().expect("Recording write error");
+    }
+    self.get_apu().finish_audio().expect("Audio file write error");
   }
 
   fn draw_ram(
@@ -268,52 +524,56 @@ impl Nes {
   }
 
   fn clock(&mut self) {
-    let curr_system_cycles = self.system_cycles;
-
-    let state = self.ppu.clock();
+    let state = self.get_mut_ppu().clock();
 
     if state == PpuState::Render {
-      self.update_image_buffer();
+      self.on_frame_ready();
     }
 
-    if (curr_system_cycles % 3) == 0 {
+    let (dots, cycles) = self.cpu_divider;
+    if self.cpu_phase < cycles {
+      let curr_cpu_cycles = self.cpu_cycles;
       if !self.cpu.bus.dma_transfer {
-        self.get_apu().step(curr_system_cycles);
-        self.cpu.clock(curr_system_cycles);
-        if self.is_dbg {
-          self.draw_ram(0x0000);
+        let expansion_audio = self.cpu.bus.get_mut_cartridge().clock_cpu();
+        self.get_apu().step(curr_cpu_cycles, expansion_audio);
+        self.cpu_stall_cycles += self.cpu.bus.dmc_dma_access();
+        if self.cpu_stall_cycles > 0 {
+          self.cpu_stall_cycles -= 1;
+        } else {
+          match self.nsf_driver.as_mut() {
+            Some(driver) => driver.clock(&mut self.cpu, curr_cpu_cycles),
+            None => self.cpu.clock(curr_cpu_cycles),
+          }
+          if self.is_dbg {
+            self.draw_ram(0x0000);
+          }
         }
       } else if self.cpu.bus.dma_transfer {
-        self.get_apu().flush_samples();
-        self.system_cycles = self.system_cycles.wrapping_add(self.cpu.bus.oam_dma_access(self.system_cycles));
+        self.cpu_cycles = self.cpu_cycles.wrapping_add(self.cpu.bus.oam_dma_access(self.cpu_cycles));
       }
-    }
+      self.cpu_cycles = self.cpu_cycles.wrapping_add(1);
 
-    if self.ppu.nmi {
-      self.ppu.nmi = false;
-      self.cpu.nmi();
+      let nmi_line = self.ppu.borrow().nmi_line();
+      self.cpu.set_nmi_line(nmi_line);
+      let irq_line = self.cpu.bus.get_cartridge().irq_flag() || self.get_apu().get_irq_flag();
+      self.cpu.set_irq_line(irq_line);
     }
-
-    if self.cpu.bus.get_cartridge().irq_flag() {
-      self.cpu.bus.get_mut_cartridge().clear_irq_flag();
-      self.cpu.irq();
+    self.cpu_phase += cycles;
+    if self.cpu_phase >= dots {
+      self.cpu_phase -= dots;
     }
-
-    self.system_cycles = self.system_cycles.wrapping_add(1);
-  }
-
-  fn update_image_buffer(&mut self) {
-    let pixels = self.get_off_screen_pixels().iter().flat_map(|p| *p).collect::<Vec<u8>>();
-    self.window_context.update_image_buffer(pixels);
   }
 
   fn render_screen(&mut self) {
+    let Some(window_context) = self.window_context.as_mut() else {
+      return;
+    };
     if self.resize {
-      self.window_context.update_screen_size();
+      window_context.update_screen_size();
       self.resize = false;
     }
 
-    let mut target = self.window_context.display.draw();
+    let mut target = window_context.display.draw();
     target.clear_color(0.0, 0.0, 0.0, 1.0);
 
     let uniforms = uniform! {
@@ -323,18 +583,29 @@ impl Nes {
                             [0.0, 0.0, 1.0, 0.0],
                             [0.0, 0.0, 0.0, 1.0f32],
                         ],
-                        tex: &self.window_context.texture,
+                        tex: &window_context.texture,
                     };
 
-    target.draw(&self.window_context.vertex_buffer, self.window_context.indices, &self.window_context.program, &uniforms,
+    target.draw(&window_context.vertex_buffer, window_context.indices, &window_context.program, &uniforms,
                 &Default::default()).unwrap();
     target.finish().unwrap();
   }
 
+  // Music files have no reset vector program, the song starts over instead
+  fn restart_track(&mut self) {
+    if let Some(driver) = self.nsf_driver.as_mut() {
+      driver.select_song(driver.song());
+    }
+  }
+
   pub fn reset(&mut self) {
     self.cpu.reset();
-    self.ppu.reset();
+    self.restart_track();
+    self.get_mut_ppu().reset();
     self.off_screen_pixels.replace([[0u8; 3]; 256 * 240]);
-    self.system_cycles = 0;
+    self.cpu_cycles = 0;
+    self.cpu_stall_cycles = 0;
+    self.cpu_phase = 0;
+    self.frame_count = 0;
   }
 }
@@ -0,0 +1,146 @@
+use std::path::Path;
+
+/// TV system of the console, decides the clock dividers and the frame layout
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Region {
+  #[default]
+  Ntsc,
+  Pal,
+  /// Famiclone with a PAL frame but NTSC-like CPU divider and APU timing
+  Dendy,
+}
+
+impl Region {
+  pub fn from_name(name: &str) -> Option<Region> {
+    match name.to_lowercase().as_str() {
+      "ntsc" => Some(Region::Ntsc),
+      "pal" => Some(Region::Pal),
+      "dendy" => Some(Region::Dendy),
+      _ => None,
+    }
+  }
+
+  /// Guesses the region from the GoodNES / No-Intro country tags in the ROM file name. There is no database of ROM
+  /// hashes, so this is the only fallback for headers that don't tell the region, NTSC after it.
+  pub fn from_file_name(rom_file: &str) -> Option<Region> {
+    let name = Path::new(rom_file).file_stem()?.to_string_lossy().to_lowercase();
+    if name.contains("(dendy)") {
+      return Some(Region::Dendy);
+    }
+    let pal_tags = ["(e)", "(europe)", "(pal)", "(a)", "(australia)", "(g)", "(germany)", "(f)", "(france)",
+      "(i)", "(italy)", "(s)", "(spain)", "(sw)", "(sweden)", "(uk)"];
+    if pal_tags.iter().any(|tag| name.contains(tag)) {
+      return Some(Region::Pal);
+    }
+    None
+  }
+
+  /// Scanlines per frame including the pre-render line
+  pub fn scan_lines(self) -> usize {
+    match self {
+      Region::Ntsc => 262,
+      Region::Pal | Region::Dendy => 312,
+    }
+  }
+
+  /// Scanline where the vertical blank flag is set
+  pub fn vblank_scan_line(self) -> usize {
+    match self {
+      Region::Ntsc | Region::Pal => 241,
+      // Dendy keeps NTSC vblank length and pads the post-render part instead
+      Region::Dendy => 291,
+    }
+  }
+
+  /// PPU dots after which OAM rows that were not accessed lose their contents. OAM is DRAM, the NTSC PPU stops
+  /// refreshing it during vblank and rows fade after about 3000 CPU cycles. The PAL PPU keeps refreshing it during
+  /// its long vblank, and Dendy clones idle too long after rendering for decay to be safe to emulate.
+  pub fn oam_decay_dots(self) -> Option<u64> {
+    match self {
+      Region::Ntsc => Some(9000),
+      Region::Pal | Region::Dendy => None,
+    }
+  }
+
+  pub fn skips_odd_frame_dot(self) -> bool {
+    self == Region::Ntsc
+  }
+
+  /// PPU dots per CPU cycle as numerator and denominator
+  pub fn cpu_divider(self) -> (u32, u32) {
+    match self {
+      Region::Ntsc | Region::Dendy => (3, 1),
+      Region::Pal => (16, 5),
+    }
+  }
+
+  pub fn cpu_clock_rate(self) -> u32 {
+    match self {
+      // Master clock 236.25 MHz / 11 divided by 12
+      Region::Ntsc => 1_789_773,
+      // Master clock 26.601712 MHz divided by 16
+      Region::Pal => 1_662_607,
+      // Master clock 26.601712 MHz divided by 15
+      Region::Dendy => 1_773_448,
+    }
+  }
+
+  /// Exact frame rate as numerator and denominator
+  pub fn frame_rate(self) -> (u32, u32) {
+    match self {
+      // ~60.0988 FPS, 341 * 261.5 dots per frame on average
+      Region::Ntsc => (39_375_000, 655_171),
+      // ~50.0070 FPS, 341 * 312 dots per frame
+      Region::Pal | Region::Dendy => (3_325_214, 66_495),
+    }
+  }
+
+  /// Frame counter step positions in CPU cycles after the sequence restarts: three first quarter frames, the last
+  /// step of the 4-step sequence and the last step of the 5-step sequence
+  pub fn frame_counter_steps(self) -> [u32; 5] {
+    match self {
+      Region::Ntsc | Region::Dendy => [7_457, 14_913, 22_371, 29_829, 37_281],
+      Region::Pal => [8_313, 16_627, 24_939, 33_253, 41_565],
+    }
+  }
+
+  /// Noise channel timer periods in CPU cycles
+  pub fn noise_periods(self) -> [u16; 16] {
+    match self {
+      Region::Ntsc | Region::Dendy => [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068],
+      Region::Pal => [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778],
+    }
+  }
+
+  /// DMC output rates as timer periods in CPU cycles
+  pub fn dmc_rates(self) -> [u16; 16] {
+    match self {
+      Region::Ntsc | Region::Dendy => [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54],
+      Region::Pal => [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50],
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::nes::region::Region;
+
+  #[test]
+  fn region_from_file_name() {
+    assert_eq!(Region::from_file_name("roms/Super Mario Bros. (E).nes"), Some(Region::Pal));
+    assert_eq!(Region::from_file_name("Tetris (Europe).nes"), Some(Region::Pal));
+    assert_eq!(Region::from_file_name("Contra (Dendy).nes"), Some(Region::Dendy));
+    assert_eq!(Region::from_file_name("Zelda (U) [!].nes"), None);
+  }
+
+  #[test]
+  fn cpu_divider_matches_clock_rates() {
+    for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+      let (num, den) = region.frame_rate();
+      let (dots, cycles) = region.cpu_divider();
+      let dots_per_frame = (341 * region.scan_lines()) as f64;
+      let cpu_rate = dots_per_frame * num as f64 / den as f64 * cycles as f64 / dots as f64;
+      assert!((cpu_rate - region.cpu_clock_rate() as f64).abs() < 100.0, "{:?} {}", region, cpu_rate);
+    }
+  }
+}
@@ -0,0 +1,207 @@
+use crate::cpu::Cpu;
+use crate::nes::region::Region;
+use crate::nsf::Nsf;
+
+// INIT and PLAY return here, nothing is mapped at this address so no program code can run into it
+const RETURN_ADDRESS: u16 = 0x5FF6;
+
+/// Plays an NSF in place of the reset vector program: calls INIT for the selected song, then PLAY at the rate of
+/// the file, and leaves the CPU idle in between
+pub struct NsfDriver {
+  init_address: u16,
+  play_address: u16,
+  // Bank registers written on every INIT, only for bankswitched files
+  bank_init: Option<[u8; 8]>,
+  is_pal: bool,
+  total_songs: u8,
+  song: u8,
+  // Set when a song has to be (re)started on the next instruction boundary
+  pending_song: Option<u8>,
+  play_period: u32,
+  play_timer: u32,
+  is_play_due: bool,
+  is_in_routine: bool,
+}
+
+impl NsfDriver {
+  /// `song` is zero-based, `None` starts the default song of the file
+  pub fn new(nsf: &Nsf, region: Region, song: Option<u8>) -> NsfDriver {
+    let total_songs = nsf.total_songs.max(1);
+    let song = match song {
+      Some(song) if song >= total_songs => panic!("Track {} not found, the file has {}", u16::from(song) + 1, total_songs),
+      Some(song) => song,
+      // Some files give a starting song past the last one
+      None => nsf.starting_song % total_songs,
+    };
+
+    NsfDriver {
+      init_address: nsf.init_address,
+      play_address: nsf.play_address,
+      bank_init: if nsf.is_bankswitched() { Some(nsf.bank_init) } else { None },
+      is_pal: region == Region::Pal,
+      total_songs,
+      song,
+      pending_song: Some(song),
+      play_period: nsf.play_period(region),
+      play_timer: 0,
+      is_play_due: false,
+      is_in_routine: false,
+    }
+  }
+
+  pub fn song(&self) -> u8 {
+    self.song
+  }
+
+  pub fn total_songs(&self) -> u8 {
+    self.total_songs
+  }
+
+  pub fn next_song(&mut self) {
+    self.select_song((self.song + 1) % self.total_songs);
+  }
+
+  pub fn previous_song(&mut self) {
+    self.select_song(if self.song == 0 { self.total_songs - 1 } else { self.song - 1 });
+  }
+
+  /// Starts the song from the beginning, the routine running is abandoned
+  pub fn select_song(&mut self, song: u8) {
+    self.song = song;
+    self.pending_song = Some(song);
+  }
+
+  /// Replaces `Cpu::clock`, called once per CPU cycle
+  pub fn clock(&mut self, cpu: &mut Cpu, system_cycle: u32) {
+    if self.play_timer == 0 {
+      self.play_timer = self.play_period;
+      self.is_play_due = true;
+    }
+    self.play_timer -= 1;
+
+    if cpu.cycle == 0 {
+      if cpu.pc == RETURN_ADDRESS {
+        self.is_in_routine = false;
+      }
+
+      if let Some(song) = self.pending_song.take() {
+        self.init_song(cpu, song, system_cycle);
+      } else if !self.is_in_routine && self.is_play_due {
+        self.is_play_due = false;
+        self.is_in_routine = true;
+        cpu.call(self.play_address, RETURN_ADDRESS);
+      }
+
+      if !self.is_in_routine {
+        return;
+      }
+    }
+
+    cpu.clock(system_cycle);
+  }
+
+  // Power up state expected by the INIT routine, then the call with the song in A and the region in X
+  fn init_song(&mut self, cpu: &mut Cpu, song: u8, system_cycle: u32) {
+    let bus = &mut cpu.bus;
+    bus.ram.fill(0);
+    for address in 0x6000..=0x7FFF {
+      bus.write_u8(address, 0x00, system_cycle);
+    }
+    for address in 0x4000..=0x4013 {
+      bus.write_u8(address, 0x00, system_cycle);
+    }
+    bus.write_u8(0x4015, 0x00, system_cycle);
+    bus.write_u8(0x4015, 0x0F, system_cycle);
+    bus.write_u8(0x4017, 0x40, system_cycle);
+    if let Some(bank_init) = self.bank_init {
+      for (address, bank) in (0x5FF8..=0x5FFF).zip(bank_init) {
+        bus.write_u8(address, bank, system_cycle);
+      }
+    }
+
+    cpu.acc = song;
+    cpu.x = u8::from(self.is_pal);
+    cpu.y = 0;
+    cpu.stack_pointer = 0xFD;
+    cpu.call(self.init_address, RETURN_ADDRESS);
+    self.is_in_routine = true;
+    self.play_timer = self.play_period;
+    self.is_play_due = false;
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::apu::Apu;
+  use crate::apu::audio_sink::NullSink;
+  use crate::bus::Bus;
+  use crate::cartridge::Cartridge;
+  use crate::cpu::Cpu;
+  use crate::nes::controller::Controller;
+  use crate::nes::region::Region;
+  use crate::nsf::Nsf;
+  use crate::nsf::driver::NsfDriver;
+  use crate::nsf::test::mock_nsf_bytes;
+  use crate::ppu::Ppu;
+  use crate::ppu::registers::Registers;
+
+  fn nsf_cpu(nsf: &Nsf) -> Cpu {
+    let cart = Rc::new(RefCell::new(Cartridge::from_nsf(nsf)));
+    let registers = Rc::new(RefCell::new(Registers::new(cart.clone(), Region::Ntsc)));
+    let off_screen_pixels = Rc::new(RefCell::new([[0u8; 3]; 256 * 240]));
+    let ppu = Rc::new(RefCell::new(Ppu::new(registers.clone(), off_screen_pixels, Region::Ntsc)));
+    let controller = Rc::new(RefCell::new(Controller::new()));
+    let apu = Rc::new(RefCell::new(Apu::new(Region::Ntsc, 44_100, Box::new(NullSink))));
+    Cpu::new(Bus::new(cart, registers, ppu, controller, apu))
+  }
+
+  #[test]
+  fn init_and_play_calls() {
+    let mut program = vec![0xEA; 0x20];
+    // INIT: STA $00, STX $01, RTS
+    program[0x00..0x05].copy_from_slice(&[0x85, 0x00, 0x86, 0x01, 0x60]);
+    // PLAY: INC $02, RTS
+    program[0x10..0x13].copy_from_slice(&[0xE6, 0x02, 0x60]);
+    let nsf = Nsf::from_bytes(&mock_nsf_bytes(&program, [0; 8]));
+
+    let mut cpu = nsf_cpu(&nsf);
+    let mut driver = NsfDriver::new(&nsf, Region::Pal, None);
+    let period = nsf.play_period(Region::Pal);
+    for cycle in 0..period * 10 {
+      driver.clock(&mut cpu, cycle);
+    }
+    assert_eq!(cpu.bus.ram[0..3], [1, 1, 9]);
+
+    driver.next_song();
+    for cycle in 0..period / 2 {
+      driver.clock(&mut cpu, cycle);
+    }
+    assert_eq!(driver.song(), 2);
+    assert_eq!(cpu.bus.ram[0..3], [2, 1, 0]);
+  }
+
+  #[test]
+  fn song_selection_wraps() {
+    let mut nsf = Nsf::from_bytes(&mock_nsf_bytes(&[0x60; 0x20], [0; 8]));
+    nsf.total_songs = 200;
+    let mut driver = NsfDriver::new(&nsf, Region::Ntsc, Some(99));
+    driver.previous_song();
+    assert_eq!(driver.song(), 98);
+
+    let mut driver = NsfDriver::new(&nsf, Region::Ntsc, Some(0));
+    driver.previous_song();
+    assert_eq!(driver.song(), 199);
+    driver.next_song();
+    assert_eq!(driver.song(), 0);
+  }
+
+  #[test]
+  #[should_panic(expected = "Track 4 not found")]
+  fn track_past_the_last_one() {
+    let nsf = Nsf::from_bytes(&mock_nsf_bytes(&[0x60; 0x20], [0; 8]));
+    NsfDriver::new(&nsf, Region::Ntsc, Some(3));
+  }
+}
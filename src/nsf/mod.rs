@@ -0,0 +1,260 @@
+use crate::nes::region::Region;
+
+pub mod driver;
+
+// Play routine periods used when the file leaves them out, in microseconds
+const DEFAULT_NTSC_SPEED: u16 = 16_639;
+const DEFAULT_PAL_SPEED: u16 = 19_997;
+const HEADER_LEN: usize = 0x80;
+pub const BANK_SIZE: usize = 0x1000;
+/// `expansion_chips` bit of the MMC5, the only expansion audio emulated
+pub const MMC5_AUDIO: u8 = 0x08;
+
+/// Music rip in the NSF or NSFe format, the sound code of a game with the INIT and PLAY routines the game calls
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Nsf {
+  pub title: String,
+  pub artist: String,
+  pub copyright: String,
+  pub total_songs: u8,
+  /// Zero-based index of the song played first
+  pub starting_song: u8,
+  pub load_address: u16,
+  pub init_address: u16,
+  pub play_address: u16,
+  /// Play routine period on NTSC in microseconds
+  pub ntsc_speed: u16,
+  /// Play routine period on PAL in microseconds
+  pub pal_speed: u16,
+  /// Initial values of the 0x5FF8-0x5FFF bank registers, all zero when the file does not bankswitch
+  pub bank_init: [u8; 8],
+  /// `None` when the file supports both regions
+  pub region: Option<Region>,
+  /// Expansion audio chips the music is written for, VRC6 is 0x01, VRC7 0x02, FDS 0x04, MMC5 0x08, Namco 163 0x10
+  /// and Sunsoft 5B 0x20
+  pub expansion_chips: u8,
+  pub data: Vec<u8>,
+}
+
+impl Nsf {
+  pub fn is_nsf(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"NESM\x1A") || bytes.starts_with(b"NSFE")
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> Nsf {
+    if bytes.starts_with(b"NSFE") {
+      Nsf::read_nsfe(&bytes[4..])
+    } else {
+      Nsf::read_nsf(bytes)
+    }
+  }
+
+  fn read_nsf(bytes: &[u8]) -> Nsf {
+    if bytes.len() <= HEADER_LEN || !bytes.starts_with(b"NESM\x1A") {
+      panic!("Invalid NSF header");
+    }
+    let word = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+
+    // NSF2 headers may give the program length, metadata follows it
+    let data_len = usize::from(bytes[0x7D]) | usize::from(bytes[0x7E]) << 8 | usize::from(bytes[0x7F]) << 16;
+    let data_end = if bytes[0x05] >= 2 && data_len > 0 { HEADER_LEN + data_len } else { bytes.len() };
+
+    Nsf {
+      title: read_string(&bytes[0x0E..0x2E]),
+      artist: read_string(&bytes[0x2E..0x4E]),
+      copyright: read_string(&bytes[0x4E..0x6E]),
+      total_songs: bytes[0x06],
+      starting_song: bytes[0x07].saturating_sub(1),
+      load_address: word(0x08),
+      init_address: word(0x0A),
+      play_address: word(0x0C),
+      ntsc_speed: speed_or_default(word(0x6E), DEFAULT_NTSC_SPEED),
+      pal_speed: speed_or_default(word(0x78), DEFAULT_PAL_SPEED),
+      bank_init: bytes[0x70..0x78].try_into().unwrap(),
+      region: region_from_flags(bytes[0x7A]),
+      expansion_chips: bytes[0x7B],
+      data: bytes[HEADER_LEN..data_end.min(bytes.len())].to_vec(),
+    }
+  }
+
+  // Chunks of a 32-bit length, a four character id and the data. Upper case ids are required to play the file,
+  // unknown lower case ones can be skipped.
+  fn read_nsfe(mut bytes: &[u8]) -> Nsf {
+    let mut nsf = Nsf {
+      total_songs: 1,
+      ntsc_speed: DEFAULT_NTSC_SPEED,
+      pal_speed: DEFAULT_PAL_SPEED,
+      ..Nsf::default()
+    };
+    let mut has_info = false;
+
+    while bytes.len() >= 8 {
+      let len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+      let id = &bytes[4..8];
+      let chunk = bytes.get(8..8 + len).unwrap_or_else(|| panic!("Truncated NSFe chunk {}", String::from_utf8_lossy(id)));
+      bytes = &bytes[8 + len..];
+      let word = |idx: usize| u16::from_le_bytes([chunk[idx], chunk[idx + 1]]);
+
+      match id {
+        b"INFO" => {
+          if chunk.len() < 8 {
+            panic!("Invalid NSFe INFO chunk");
+          }
+          has_info = true;
+          nsf.load_address = word(0);
+          nsf.init_address = word(2);
+          nsf.play_address = word(4);
+          nsf.region = region_from_flags(chunk[6]);
+          nsf.expansion_chips = chunk[7];
+          nsf.total_songs = chunk.get(8).copied().unwrap_or(1);
+          nsf.starting_song = chunk.get(9).copied().unwrap_or(0);
+        }
+        b"DATA" => nsf.data = chunk.to_vec(),
+        b"BANK" => {
+          for (bank, value) in nsf.bank_init.iter_mut().zip(chunk) {
+            *bank = *value;
+          }
+        }
+        b"RATE" => {
+          if chunk.len() >= 2 {
+            nsf.ntsc_speed = speed_or_default(word(0), DEFAULT_NTSC_SPEED);
+          }
+          if chunk.len() >= 4 {
+            nsf.pal_speed = speed_or_default(word(2), DEFAULT_PAL_SPEED);
+          }
+        }
+        b"auth" => {
+          let mut fields = chunk.split(|&byte| byte == 0).map(read_string);
+          nsf.title = fields.next().unwrap_or_default();
+          nsf.artist = fields.next().unwrap_or_default();
+          nsf.copyright = fields.next().unwrap_or_default();
+        }
+        b"NEND" => break,
+        _ if id[0].is_ascii_uppercase() => panic!("Unsupported NSFe chunk {}", String::from_utf8_lossy(id)),
+        _ => (),
+      }
+    }
+
+    if !has_info || nsf.data.is_empty() {
+      panic!("NSFe file without INFO or DATA chunk");
+    }
+    nsf
+  }
+
+  pub fn is_bankswitched(&self) -> bool {
+    self.bank_init.iter().any(|&bank| bank != 0)
+  }
+
+  /// Program image laid out in 4 KiB banks, and the banks mapped to 0x8000-0xFFFF at start
+  pub fn prg_image(&self) -> (Vec<u8>, [u8; 8]) {
+    if self.is_bankswitched() {
+      // The data starts at the offset of the load address within the first bank
+      let padding = usize::from(self.load_address) & (BANK_SIZE - 1);
+      let mut image = vec![0u8; padding];
+      image.extend_from_slice(&self.data);
+      image.resize(image.len().div_ceil(BANK_SIZE) * BANK_SIZE, 0);
+      (image, self.bank_init)
+    } else {
+      let offset = usize::from(self.load_address.saturating_sub(0x8000));
+      let mut image = vec![0u8; 0x8000];
+      let len = self.data.len().min(0x8000 - offset);
+      image[offset..offset + len].copy_from_slice(&self.data[..len]);
+      (image, [0, 1, 2, 3, 4, 5, 6, 7])
+    }
+  }
+
+  /// Play routine period in CPU cycles
+  pub fn play_period(&self, region: Region) -> u32 {
+    let speed = match region {
+      Region::Pal => self.pal_speed,
+      Region::Ntsc | Region::Dendy => self.ntsc_speed,
+    };
+    (u64::from(speed) * u64::from(region.cpu_clock_rate()) / 1_000_000) as u32
+  }
+}
+
+fn read_string(bytes: &[u8]) -> String {
+  let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+  String::from_utf8_lossy(&bytes[..end]).trim().to_string()
+}
+
+fn speed_or_default(speed: u16, default: u16) -> u16 {
+  if speed == 0 { default } else { speed }
+}
+
+fn region_from_flags(flags: u8) -> Option<Region> {
+  match flags & 0x03 {
+    0 => Some(Region::Ntsc),
+    1 => Some(Region::Pal),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+pub mod test {
+  use crate::nes::region::Region;
+  use crate::nsf::Nsf;
+
+  /// NSF file with the given program loaded at 0x8000, INIT at 0x8000 and PLAY at 0x8010
+  pub fn mock_nsf_bytes(program: &[u8], bank_init: [u8; 8]) -> Vec<u8> {
+    let mut bytes = vec![0u8; 0x80];
+    bytes[0..5].copy_from_slice(b"NESM\x1A");
+    bytes[0x05] = 1;
+    bytes[0x06] = 3;
+    bytes[0x07] = 2;
+    bytes[0x08..0x0E].copy_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x10, 0x80]);
+    bytes[0x0E..0x13].copy_from_slice(b"Title");
+    bytes[0x2E..0x34].copy_from_slice(b"Artist");
+    bytes[0x6E..0x70].copy_from_slice(&16_639u16.to_le_bytes());
+    bytes[0x70..0x78].copy_from_slice(&bank_init);
+    bytes[0x78..0x7A].copy_from_slice(&19_997u16.to_le_bytes());
+    bytes.extend_from_slice(program);
+    bytes
+  }
+
+  #[test]
+  fn read_nsf_header() {
+    let nsf = Nsf::from_bytes(&mock_nsf_bytes(&[0x60; 0x20], [0; 8]));
+    assert_eq!(nsf.title, "Title");
+    assert_eq!(nsf.artist, "Artist");
+    assert_eq!((nsf.total_songs, nsf.starting_song), (3, 1));
+    assert_eq!((nsf.load_address, nsf.init_address, nsf.play_address), (0x8000, 0x8000, 0x8010));
+    assert_eq!(nsf.region, Some(Region::Ntsc));
+    assert_eq!(nsf.data.len(), 0x20);
+    assert_eq!(nsf.play_period(Region::Ntsc), 29_780);
+    assert_eq!(nsf.play_period(Region::Pal), 33_247);
+  }
+
+  #[test]
+  fn read_nsfe_chunks() {
+    let nsf = Nsf::from_bytes(&mock_nsf_bytes(&[0x60; 0x20], [0; 8]));
+
+    let mut bytes = b"NSFE".to_vec();
+    let mut chunk = |id: &[u8], data: &[u8]| {
+      bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+      bytes.extend_from_slice(id);
+      bytes.extend_from_slice(data);
+    };
+    chunk(b"INFO", &[0x00, 0x80, 0x00, 0x80, 0x10, 0x80, 0x00, 0x00, 0x03, 0x01]);
+    chunk(b"DATA", &[0x60; 0x20]);
+    chunk(b"auth", b"Title\0Artist\0\0Ripper\0");
+    chunk(b"tlbl", b"Skipped\0");
+    chunk(b"NEND", &[]);
+
+    assert_eq!(Nsf::from_bytes(&bytes), nsf);
+  }
+
+  #[test]
+  fn bankswitched_image() {
+    let mut program = vec![0u8; 0x1800];
+    program[0] = 0xAA;
+    let mut bytes = mock_nsf_bytes(&program, [0, 1, 0, 1, 0, 1, 0, 1]);
+    // Load at 0x8F00, the image starts 0xF00 bytes into the first bank
+    bytes[0x08..0x0A].copy_from_slice(&[0x00, 0x8F]);
+
+    let (image, banks) = Nsf::from_bytes(&bytes).prg_image();
+    assert_eq!(image.len(), 0x3000);
+    assert_eq!(image[0xF00], 0xAA);
+    assert_eq!(banks, [0, 1, 0, 1, 0, 1, 0, 1]);
+  }
+}
@@ -1,13 +1,21 @@
 use std::cell::{Ref, RefCell, RefMut};
+use std::ops::Range;
 use std::rc::Rc;
 
-use crate::nes::constants::{Color, COLORS};
-use crate::nes::OffScreenBuffer;
+use crate::mapper::{PpuBusAccess, PpuBusWatch};
+use crate::nes::constants::{COLORS, SCREEN_RES_X, SCREEN_RES_Y};
+use crate::nes::{OffScreenBuffer, PaletteIndexBuffer};
+use crate::nes::region::Region;
 use crate::ppu::oam_sprite::Sprite;
 use crate::ppu::registers::{get_nth_bit, Registers};
+use crate::ppu::sprite_evaluation::SpriteEvaluation;
 
 pub mod registers;
 mod oam_sprite;
+mod sprite_evaluation;
+
+// First sprite pattern fetch of a line, where mappers counting A12 rises see the line go by
+const SPRITE_FETCH_DOT: usize = 261;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum PpuState {
@@ -19,7 +27,6 @@ pub struct Ppu {
   pub cycles: usize,
   scan_line: usize,
   registers: Rc<RefCell<Registers>>,
-  pub nmi: bool,
   nametable_entry: u8,
   bg_next_tile_attribute: u8,
   bg_next_tile_lo: u8,
@@ -33,18 +40,30 @@ pub struct Ppu {
   attribute_shift_hi: u8,
   pub is_frame_ready: bool,
   primary_oam: Vec<Sprite>,
+  // Sprites of the next line, their patterns are filled in by the sprite fetches
   secondary_oam: Vec<Sprite>,
+  // Sprite color, priority and sprite zero of each pixel of the line, built when the sprites are fetched
+  sprite_line: [(u8, bool, bool); 256],
+  sprite_evaluation: SpriteEvaluation,
+  was_rendering: bool,
   pub is_even_frame: bool,
   off_screen_pixels: Rc<RefCell<OffScreenBuffer>>,
+  palette_indices: Box<PaletteIndexBuffer>,
+  region: Region,
+  /// Render visible scanlines in one go when nothing touches the PPU in between, see `sync`
+  pub is_catch_up: bool,
+  // Dots of the current visible scanline that have been clocked but not run yet
+  deferred_dots: usize,
+  // PPU dots run since power up
+  dots: u64,
 }
 
 impl Ppu {
-  pub fn new(registers: Rc<RefCell<Registers>>, off_screen_pixels: Rc<RefCell<OffScreenBuffer>>) -> Ppu {
+  pub fn new(registers: Rc<RefCell<Registers>>, off_screen_pixels: Rc<RefCell<OffScreenBuffer>>, region: Region) -> Ppu {
     Ppu {
       cycles: 0,
       scan_line: 0,
       registers,
-      nmi: false,
       nametable_entry: 0,
       bg_next_tile_attribute: 0,
       bg_next_tile_lo: 0,
@@ -58,9 +77,17 @@ impl Ppu {
       attribute_shift_hi: 0,
       primary_oam: Vec::with_capacity(8),
       secondary_oam: Vec::with_capacity(8),
+      sprite_line: [(0, false, false); 256],
+      sprite_evaluation: SpriteEvaluation::new(),
+      was_rendering: false,
       is_frame_ready: false,
       is_even_frame: true,
       off_screen_pixels,
+      palette_indices: Box::new([0u16; (SCREEN_RES_X * SCREEN_RES_Y) as usize]),
+      region,
+      is_catch_up: true,
+      deferred_dots: 0,
+      dots: 0,
     }
   }
 
@@ -69,29 +96,20 @@ impl Ppu {
     self.registers.borrow_mut()
   }
 
-  #[inline]
-  fn get_mut_off_screen_pixels(&mut self) -> RefMut<OffScreenBuffer> {
-    self.off_screen_pixels.borrow_mut()
-  }
-
   #[inline]
   pub fn get_registers(&self) -> Ref<Registers> {
     self.registers.borrow()
   }
 
-  #[inline]
-  fn read_ppu_u8(&self, address: u16) -> u8 {
-    self.get_registers().ppu_read_reg(address)
+  /// NMI output of the PPU, low whenever either the vblank flag or NMI enable is off
+  pub fn nmi_line(&self) -> bool {
+    let registers = self.get_registers();
+    registers.status_flags.vertical_blank() && registers.ctrl_flags.enable_nmi()
   }
 
-  fn get_pixel_color(&mut self, pixel: u8) -> Color {
-    let palette: u16 = if self.get_registers().mask_flags.is_rendering() {
-      pixel
-    } else {
-      0
-    }.into();
-    let idx = self.read_ppu_u8(0x3F00 + palette);
-    COLORS[usize::from(idx)]
+  /// Last rendered frame as 9-bit pixels, emphasis bits on top of the 6-bit palette index
+  pub fn palette_indices(&self) -> &PaletteIndexBuffer {
+    &self.palette_indices
   }
 
   pub fn reset(&mut self) {
@@ -109,8 +127,12 @@ impl Ppu {
     self.attribute_shift_hi = 0;
     self.primary_oam.clear();
     self.secondary_oam.clear();
+    self.sprite_line = [(0, false, false); 256];
+    self.sprite_evaluation = SpriteEvaluation::new();
+    self.was_rendering = false;
     self.is_frame_ready = false;
     self.is_even_frame = true;
+    self.deferred_dots = 0;
     self.get_mut_registers().reset();
   }
 
@@ -130,108 +152,309 @@ impl Ppu {
     self.bg_attribute_latch_hi = (self.bg_next_tile_attribute & 2) >> 1;
   }
 
-  fn increment_scroll_x(&mut self) {
-    if self.get_registers().mask_flags.is_rendering() {
-      if self.get_registers().vram_addr.coarse_x() == 31 {
-        self.get_mut_registers().vram_addr.set_coarse_x(0);
-        self.get_mut_registers().vram_addr.0 ^= 0x0400;
+  fn increment_scroll_x(registers: &mut Registers) {
+    if registers.mask_flags.is_rendering() {
+      if registers.vram_addr.coarse_x() == 31 {
+        registers.vram_addr.set_coarse_x(0);
+        registers.vram_addr.0 ^= 0x0400;
       } else {
-        let coarse_x = self.get_registers().vram_addr.coarse_x();
-        self.get_mut_registers().vram_addr.set_coarse_x(coarse_x + 1);
+        let coarse_x = registers.vram_addr.coarse_x();
+        registers.vram_addr.set_coarse_x(coarse_x + 1);
       }
     }
   }
 
-  fn increment_scroll_y(&mut self) {
-    if self.get_registers().mask_flags.is_rendering() {
-      let fine_y = self.get_registers().vram_addr.fine_y();
+  fn increment_scroll_y(registers: &mut Registers) {
+    if registers.mask_flags.is_rendering() {
+      let fine_y = registers.vram_addr.fine_y();
       if fine_y < 7 {
-        self.get_mut_registers().vram_addr.set_fine_y(fine_y + 1);
+        registers.vram_addr.set_fine_y(fine_y + 1);
       } else {
-        self.get_mut_registers().vram_addr.set_fine_y(0);
-        let coarse_y = self.get_registers().vram_addr.coarse_y();
+        registers.vram_addr.set_fine_y(0);
+        let coarse_y = registers.vram_addr.coarse_y();
 
         if coarse_y == 29 {
-          self.get_mut_registers().vram_addr.set_coarse_y(0);
-          let nametable_y = self.get_mut_registers().vram_addr.nametable_y();
-          self.get_mut_registers().vram_addr.set_nametable_y(!nametable_y);
+          registers.vram_addr.set_coarse_y(0);
+          let nametable_y = registers.vram_addr.nametable_y();
+          registers.vram_addr.set_nametable_y(!nametable_y);
         } else if coarse_y == 31 {
-          self.get_mut_registers().vram_addr.set_coarse_y(0);
+          registers.vram_addr.set_coarse_y(0);
         } else {
-          self.get_mut_registers().vram_addr.set_coarse_y(coarse_y + 1);
+          registers.vram_addr.set_coarse_y(coarse_y + 1);
         }
       }
     }
   }
 
-  fn transfer_address_x(&mut self) {
-    if self.get_registers().mask_flags.is_rendering() {
-      let tram_addr = self.get_registers().tram_addr;
-      self.get_mut_registers().vram_addr.set_nametable_x(tram_addr.nametable_x());
-      self.get_mut_registers().vram_addr.set_coarse_x(tram_addr.coarse_x());
+  fn transfer_address_x(registers: &mut Registers) {
+    if registers.mask_flags.is_rendering() {
+      let tram_addr = registers.tram_addr;
+      registers.vram_addr.set_nametable_x(tram_addr.nametable_x());
+      registers.vram_addr.set_coarse_x(tram_addr.coarse_x());
     }
   }
 
-  fn transfer_address_y(&mut self) {
-    if self.get_registers().mask_flags.is_rendering() {
-      let tram_addr = self.get_registers().tram_addr;
-      self.get_mut_registers().vram_addr.set_fine_y(tram_addr.fine_y());
-      self.get_mut_registers().vram_addr.set_nametable_y(tram_addr.nametable_y());
-      self.get_mut_registers().vram_addr.set_coarse_y(tram_addr.coarse_y());
+  fn transfer_address_y(registers: &mut Registers) {
+    if registers.mask_flags.is_rendering() {
+      let tram_addr = registers.tram_addr;
+      registers.vram_addr.set_fine_y(tram_addr.fine_y());
+      registers.vram_addr.set_nametable_y(tram_addr.nametable_y());
+      registers.vram_addr.set_coarse_y(tram_addr.coarse_y());
     }
   }
 
+  /// Advances one dot. Visible scanlines are only counted here and rendered in one go once they end, unless the CPU
+  /// accesses the PPU or the mapper in the middle of the line, see `sync`. Mappers watching the sprite fetches get
+  /// them on time, only the first part of their lines is rendered in one go, and mappers watching every fetch get
+  /// their lines run dot by dot.
   pub fn clock(&mut self) -> PpuState {
-    let mut state = PpuState::NoOp;
-    match self.scan_line {
-      (0..=239) => {
-        self.process_sprites(false);
-        self.update_image_buffer();
-        self.process_background(false);
+    if self.deferred_dots > 0 || (self.is_catch_up && self.cycles == 0 && self.scan_line < 240
+      && self.get_registers().mapper_bus_watch() != PpuBusWatch::AllFetches) {
+      self.deferred_dots += 1;
+      if self.deferred_dots == 341 {
+        self.deferred_dots = 0;
+        self.render_scan_line();
+      } else if self.deferred_dots == SPRITE_FETCH_DOT
+        && self.get_registers().mapper_bus_watch() == PpuBusWatch::SpriteFetches {
+        self.deferred_dots = 0;
+        self.render_line_start();
+      }
+      return PpuState::NoOp;
+    }
+    self.step()
+  }
+
+  /// Runs the deferred dots of the current scanline one by one. Called before every CPU access that could observe or
+  /// change the PPU state mid-line, the rest of the line then continues dot by dot.
+  pub fn sync(&mut self) {
+    let dots = std::mem::take(&mut self.deferred_dots);
+    for _ in 0..dots {
+      self.step();
+    }
+  }
+
+  /// Renders a whole visible scanline at once. Fetches happen in the same order, the mapper sees them on the same
+  /// dots, and the PPU ends up in the same state as if the line had been run dot by dot.
+  fn render_scan_line(&mut self) {
+    self.render_line_start();
+
+    let registers = Rc::clone(&self.registers);
+    let mut registers = registers.borrow_mut();
+    let is_rendering = registers.mask_flags.is_rendering();
+    self.evaluate_sprites(&mut registers, SPRITE_FETCH_DOT..341, is_rendering);
+    if is_rendering {
+      for slot in 0..8 {
+        registers.bus_dot = self.bus_dot(SPRITE_FETCH_DOT + slot * 8);
+        self.fetch_sprite_pattern(&registers, slot, false);
+        registers.bus_dot = self.bus_dot(SPRITE_FETCH_DOT + slot * 8 + 2);
+        self.fetch_sprite_pattern(&registers, slot, true);
+      }
+      self.load_sprites();
+    }
+    self.prefetch_next_line(&mut registers);
+
+    self.dots += (341 - SPRITE_FETCH_DOT) as u64;
+    self.cycles = 0;
+    self.scan_line += 1;
+  }
+
+  // Dots 0-260 of a visible scanline, the line is drawn and the sprite fetches are next
+  fn render_line_start(&mut self) {
+    let registers = Rc::clone(&self.registers);
+    let mut registers = registers.borrow_mut();
+    let is_rendering = registers.mask_flags.is_rendering();
+    if self.was_rendering && !is_rendering {
+      registers.corrupt_oam_row();
+    } else if !self.was_rendering && is_rendering {
+      registers.apply_oam_corruption();
+    }
+    self.was_rendering = is_rendering;
+
+    self.evaluate_sprites(&mut registers, 0..SPRITE_FETCH_DOT, is_rendering);
+    let bg_line = self.fetch_background_line(&mut registers);
+    self.draw_scan_line(&mut registers, &bg_line);
+
+    self.dots += SPRITE_FETCH_DOT as u64;
+    self.cycles = SPRITE_FETCH_DOT;
+  }
+
+  // Time of a dot of the line being rendered in one go
+  fn bus_dot(&self, dot: usize) -> u64 {
+    self.dots - self.cycles as u64 + dot as u64
+  }
+
+  fn evaluate_sprites(&mut self, registers: &mut Registers, dots: Range<usize>, is_rendering: bool) {
+    for dot in dots {
+      registers.clock_oam();
+      // Outside of these dots evaluation only drives the OAM bus, which matters only at the end of the line
+      if is_rendering && ((64..=257).contains(&dot) || dot == 340) {
+        registers.oam_bus = Some(self.sprite_evaluation.clock(registers, dot, self.scan_line, false));
+      }
+      if is_rendering && dot == 257 {
+        self.secondary_oam = self.sprite_evaluation.sprites();
+      }
+    }
+  }
+
+  // Fetches of dots 1-256, returns the background pixels of the line before fine X scrolling. The first two tiles
+  // are still in the shifters from the previous line.
+  fn fetch_background_line(&mut self, registers: &mut Registers) -> [u8; 272] {
+    let mut bg_line = [0u8; 272];
+    for (i, pixel) in bg_line[..16].iter_mut().enumerate() {
+      let pattern = get_nth_bit(self.bg_shifter_hi, 15 - i as u16) << 1 | get_nth_bit(self.bg_shifter_lo, 15 - i as u16);
+      let attribute = if i < 8 {
+        get_nth_bit(self.attribute_shift_hi, 7 - i as u16) << 1 | get_nth_bit(self.attribute_shift_lo, 7 - i as u16)
+      } else {
+        self.bg_attribute_latch_hi << 1 | self.bg_attribute_latch_lo
+      };
+      *pixel = if pattern != 0 { pattern | attribute << 2 } else { 0 };
+    }
+
+    for tile in 0..32 {
+      self.fetch_tile(registers, tile * 8 + 1);
+      let attribute = (self.bg_next_tile_attribute & 0x03) << 2;
+      for (bit, pixel) in bg_line[16 + tile * 8..24 + tile * 8].iter_mut().enumerate() {
+        let pattern = (self.bg_next_tile_hi >> (7 - bit) & 1) << 1 | self.bg_next_tile_lo >> (7 - bit) & 1;
+        *pixel = if pattern != 0 { pattern | attribute } else { 0 };
       }
-      261 => {
-        self.process_sprites(true);
-        self.update_image_buffer();
-        self.process_background(true);
+      // The last tile is followed by the Y increment instead
+      if tile < 31 {
+        Ppu::increment_scroll_x(registers);
       }
+    }
+    Ppu::increment_scroll_y(registers);
+    Ppu::transfer_address_x(registers);
+    bg_line
+  }
+
+  // Same fetches as phases 1 to 0 of `process_background`, starting on `dot`
+  fn fetch_tile(&mut self, registers: &mut Registers, dot: usize) {
+    self.curr_address = 0x2000 | (registers.vram_addr.0 & 0x0FFF);
+    registers.bus_dot = self.bus_dot(dot + 1);
+    self.nametable_entry = registers.fetch(self.curr_address, PpuBusAccess::Background);
+    self.curr_address = Ppu::fetch_next_bg_tile_attribute(registers);
+    registers.bus_dot = self.bus_dot(dot + 3);
+    self.bg_next_tile_attribute = registers.fetch(self.curr_address, PpuBusAccess::Background);
+    if (registers.vram_addr.coarse_y() & 0x02) > 0 {
+      self.bg_next_tile_attribute >>= 4;
+    }
+    if (registers.vram_addr.coarse_x() & 0x02) > 0 {
+      self.bg_next_tile_attribute >>= 2;
+    }
+    self.curr_address = registers.ctrl_flags.get_pattern_background()
+      + ((0x10 * u16::from(self.nametable_entry)) | u16::from(registers.vram_addr.fine_y()));
+    registers.bus_dot = self.bus_dot(dot + 5);
+    self.bg_next_tile_lo = registers.fetch(self.curr_address, PpuBusAccess::Background);
+    self.curr_address += 8;
+    registers.bus_dot = self.bus_dot(dot + 7);
+    self.bg_next_tile_hi = registers.fetch(self.curr_address, PpuBusAccess::Background);
+  }
+
+  fn draw_scan_line(&mut self, registers: &mut Registers, bg_line: &[u8; 272]) {
+    let fine_x = usize::from(registers.fine_x);
+    let row = (239 - self.scan_line) * 256;
+    let mut off_screen_pixels = self.off_screen_pixels.borrow_mut();
+
+    let mut palette = [(0u16, [0u8; 3]); 32];
+    for (color, entry) in palette.iter_mut().enumerate() {
+      let palette_idx = Ppu::get_pixel_palette_index(registers, color as u8);
+      *entry = (palette_idx, COLORS[usize::from(palette_idx & 0x3F)].to_value());
+    }
+
+    let mask_flags = registers.mask_flags;
+    let pixels = off_screen_pixels[row..row + 256].iter_mut().zip(self.palette_indices[row..row + 256].iter_mut());
+    for (x, (pixel, pixel_palette_idx)) in pixels.enumerate() {
+      let bg_pixel = if mask_flags.is_rendering_background(x) { bg_line[x + fine_x] } else { 0 };
+      let (sprite_pixel, sprite_behind, possible_zero_hit) = if mask_flags.is_rendering_sprites(x) {
+        self.sprite_line[x]
+      } else {
+        (0, false, false)
+      };
+
+      if possible_zero_hit && bg_pixel != 0 {
+        registers.status_flags.set_sprite_zero_hit(true);
+      }
+
+      let color = if sprite_pixel > 0 && (!sprite_behind || bg_pixel == 0) { sprite_pixel } else { bg_pixel };
+      (*pixel_palette_idx, *pixel) = palette[usize::from(color)];
+    }
+  }
+
+  // Dots 321-340, the first two tiles of the next line end up in the shifters
+  fn prefetch_next_line(&mut self, registers: &mut Registers) {
+    self.fetch_tile(registers, 321);
+    Ppu::increment_scroll_x(registers);
+    let (first_lo, first_hi, first_attribute) = (self.bg_next_tile_lo, self.bg_next_tile_hi, self.bg_next_tile_attribute);
+    self.fetch_tile(registers, 329);
+    Ppu::increment_scroll_x(registers);
+
+    self.bg_shifter_lo = u16::from(first_lo) << 8 | u16::from(self.bg_next_tile_lo);
+    self.bg_shifter_hi = u16::from(first_hi) << 8 | u16::from(self.bg_next_tile_hi);
+    self.attribute_shift_lo = if first_attribute & 1 > 0 { 0xFF } else { 0x00 };
+    self.attribute_shift_hi = if first_attribute & 2 > 0 { 0xFF } else { 0x00 };
+    self.bg_attribute_latch_lo = self.bg_next_tile_attribute & 1;
+    self.bg_attribute_latch_hi = (self.bg_next_tile_attribute & 2) >> 1;
+
+    self.curr_address = 0x2000 | (registers.vram_addr.0 & 0x0FFF);
+    registers.bus_dot = self.bus_dot(338);
+    self.nametable_entry = registers.fetch(self.curr_address, PpuBusAccess::Background);
+    registers.bus_dot = self.bus_dot(340);
+    self.nametable_entry = registers.fetch(self.curr_address, PpuBusAccess::Background);
+  }
+
+  fn step(&mut self) -> PpuState {
+    let mut state = PpuState::NoOp;
+    let pre_render_line = self.region.scan_lines() - 1;
+    let vblank_line = self.region.vblank_scan_line();
+    {
+      let dots = self.dots;
+      let mut registers = self.get_mut_registers();
+      registers.clock_oam();
+      registers.bus_dot = dots;
+    }
+    match self.scan_line {
+      (0..=239) => self.render_dot_with_borrows(false),
+      line if line == pre_render_line => self.render_dot_with_borrows(true),
       240 => {
         if self.cycles == 0 {
+          self.get_mut_registers().decay_io_latch();
           self.is_frame_ready = true;
           state = PpuState::Render
         }
       }
-      241 => {
-        if self.cycles == 1 && !self.get_registers().vblank_suppress {
-          self.get_mut_registers().status_flags.set_vertical_blank(true);
-
-          let nmi_val = self.get_registers().ctrl_flags.enable_nmi();
-          self.nmi = nmi_val;
+      line if line == vblank_line => {
+        let cycles = self.cycles;
+        let mut registers = self.get_mut_registers();
+        match cycles {
+          // A $2002 read right before the flag is set suppresses it for the whole frame
+          0 => registers.is_vbl_pending = true,
+          1 => {
+            if !registers.is_vbl_suppressed {
+              registers.status_flags.set_vertical_blank(true);
+            }
+            registers.is_vbl_pending = false;
+            registers.is_vbl_suppressed = false;
+          }
+          _ => (),
         }
       }
       _ => ()
     }
 
-    if !self.nmi {
-      self.nmi = {
-        let reg = self.get_mut_registers();
-        reg.status_flags.vertical_blank() && reg.force_nmi && !reg.vblank_suppress
-      };
-    }
-
-    self.get_mut_registers().force_nmi = false;
-    self.get_mut_registers().vblank_suppress = false;
-
     self.cycles += 1;
-    let mask_flags = self.get_registers().mask_flags;
-    if mask_flags.is_rendering() && self.cycles == 260 && self.scan_line < 240 {
-      self.get_mut_registers().get_mut_cartridge().mapper.signal_scanline();
+    self.dots += 1;
+
+    // Odd frames jump from dot 339 of the pre-render line straight to the first visible dot when rendering is on
+    if self.cycles == 340 && self.scan_line == pre_render_line && !self.is_even_frame
+      && self.region.skips_odd_frame_dot() && self.get_registers().mask_flags.is_rendering() {
+      self.cycles += 1;
     }
 
     if self.cycles > 340 {
       self.cycles = 0;
       self.scan_line += 1;
 
-      if self.scan_line > 261 {
+      if self.scan_line > pre_render_line {
         self.scan_line = 0;
         self.is_even_frame = !self.is_even_frame;
       }
@@ -240,191 +463,189 @@ impl Ppu {
     state
   }
 
-  fn process_background(&mut self, is_pre_render: bool) {
+  fn render_dot_with_borrows(&mut self, is_pre_render: bool) {
+    let registers = Rc::clone(&self.registers);
+    let off_screen_pixels = Rc::clone(&self.off_screen_pixels);
+    self.render_dot(&mut registers.borrow_mut(), &mut off_screen_pixels.borrow_mut(), is_pre_render);
+  }
+
+  fn render_dot(&mut self, registers: &mut Registers, off_screen_pixels: &mut OffScreenBuffer, is_pre_render: bool) {
+    self.process_sprites(registers, is_pre_render);
+    self.update_image_buffer(registers, off_screen_pixels);
+    self.process_background(registers, is_pre_render);
+  }
+
+  fn process_background(&mut self, registers: &mut Registers, is_pre_render: bool) {
     if (2..=255).contains(&self.cycles) || (322..=337).contains(&self.cycles) {
       match self.cycles % 8 {
         0x01 => {
-          let vram_addr = self.get_registers().vram_addr;
-          self.curr_address = 0x2000 | (vram_addr.0 & 0x0FFF);
+          self.curr_address = 0x2000 | (registers.vram_addr.0 & 0x0FFF);
           self.load_background_shifters();
         }
         0x02 => {
-          self.nametable_entry = self.read_ppu_u8(self.curr_address);
+          self.nametable_entry = registers.fetch(self.curr_address, PpuBusAccess::Background);
         }
         0x03 => {
-          self.curr_address = self.fetch_next_bg_tile_attribute();
+          self.curr_address = Ppu::fetch_next_bg_tile_attribute(registers);
         }
         0x04 => {
-          self.bg_next_tile_attribute = self.read_ppu_u8(self.curr_address);
-          if (self.get_registers().vram_addr.coarse_y() & 0x02) > 0 {
+          self.bg_next_tile_attribute = registers.fetch(self.curr_address, PpuBusAccess::Background);
+          if (registers.vram_addr.coarse_y() & 0x02) > 0 {
             self.bg_next_tile_attribute >>= 4;
           }
-          if (self.get_registers().vram_addr.coarse_x() & 0x02) > 0 {
+          if (registers.vram_addr.coarse_x() & 0x02) > 0 {
             self.bg_next_tile_attribute >>= 2;
           }
         }
         0x05 => {
-          let vram_addr = self.get_registers().vram_addr;
-          let ctrl_flags = self.get_registers().ctrl_flags;
-          self.curr_address = ctrl_flags.get_pattern_background()
-            + ((0x10 * u16::from(self.nametable_entry)) | u16::from(vram_addr.fine_y()));
+          self.curr_address = registers.ctrl_flags.get_pattern_background()
+            + ((0x10 * u16::from(self.nametable_entry)) | u16::from(registers.vram_addr.fine_y()));
         }
         0x06 => {
-          self.bg_next_tile_lo = self.read_ppu_u8(self.curr_address);
+          self.bg_next_tile_lo = registers.fetch(self.curr_address, PpuBusAccess::Background);
         }
         0x07 => {
           self.curr_address += 8;
         }
         0x00 => {
-          self.bg_next_tile_hi = self.read_ppu_u8(self.curr_address);
-          if self.get_registers().mask_flags.is_rendering() {
-            self.increment_scroll_x();
-          }
+          self.bg_next_tile_hi = registers.fetch(self.curr_address, PpuBusAccess::Background);
+          Ppu::increment_scroll_x(registers);
         }
         _ => panic!("Invalid cycle, modulo operation error"),
       }
     }
 
-    if self.cycles == 256 {
-      self.bg_next_tile_hi = self.read_ppu_u8(self.curr_address);
-      self.increment_scroll_y();
-    }
-
-    if self.cycles == 257 {
-      self.load_background_shifters();
-      self.transfer_address_x();
-    }
-
-    if (280..=304).contains(&self.cycles) && is_pre_render {
-      self.transfer_address_y()
-    }
-
-    if self.cycles == 1 {
-      let vram_addr = self.get_registers().vram_addr;
-      self.curr_address = 0x2000 | (vram_addr.0 & 0x0FFF);
-      if is_pre_render {
-        self.get_mut_registers().status_flags.set_vertical_blank(false);
+    match self.cycles {
+      1 => {
+        self.curr_address = 0x2000 | (registers.vram_addr.0 & 0x0FFF);
+        if is_pre_render {
+          registers.status_flags.set_vertical_blank(false);
+        }
       }
+      256 => {
+        self.bg_next_tile_hi = registers.fetch(self.curr_address, PpuBusAccess::Background);
+        Ppu::increment_scroll_y(registers);
+      }
+      257 => {
+        self.load_background_shifters();
+        Ppu::transfer_address_x(registers);
+      }
+      280..=304 if is_pre_render => Ppu::transfer_address_y(registers),
+      321 | 339 => self.curr_address = 0x2000 | (registers.vram_addr.0 & 0x0FFF),
+      338 | 340 => self.nametable_entry = registers.fetch(self.curr_address, PpuBusAccess::Background),
+      _ => (),
     }
+  }
 
-    if self.cycles == 321 || self.cycles == 339 {
-      let vram_addr = self.get_registers().vram_addr;
-      self.curr_address = 0x2000 | (vram_addr.0 & 0x0FFF);
+  fn process_sprites(&mut self, registers: &mut Registers, is_pre_render: bool) {
+    if self.cycles == 1 && is_pre_render {
+      registers.status_flags.set_sprite_overflow(false);
+      registers.status_flags.set_sprite_zero_hit(false);
     }
 
-    if self.cycles == 338 {
-      self.nametable_entry = self.read_ppu_u8(self.curr_address);
+    let is_rendering = registers.mask_flags.is_rendering();
+    if self.was_rendering && !is_rendering {
+      registers.corrupt_oam_row();
+    } else if !self.was_rendering && is_rendering {
+      registers.apply_oam_corruption();
     }
+    self.was_rendering = is_rendering;
 
-    if self.cycles == 340 {
-      self.nametable_entry = self.read_ppu_u8(self.curr_address);
-
-      if is_pre_render && self.get_registers().mask_flags.is_rendering() && !self.is_even_frame {
-        self.cycles += 1;
-      }
+    if !is_rendering {
+      return;
     }
-  }
 
-  fn process_sprites(&mut self, is_pre_render: bool) {
+    let bus = self.sprite_evaluation.clock(registers, self.cycles, self.scan_line, is_pre_render);
+    registers.oam_bus = Some(bus);
+
     match self.cycles {
-      1 => {
-        self.secondary_oam.clear();
-        if is_pre_render {
-          self.get_mut_registers().status_flags.set_sprite_overflow(false);
-          self.get_mut_registers().status_flags.set_sprite_zero_hit(false);
-        }
+      257 => self.secondary_oam = self.sprite_evaluation.sprites(),
+      SPRITE_FETCH_DOT..=320 if self.cycles % 8 == 5 || self.cycles % 8 == 7 => {
+        let slot = (self.cycles - SPRITE_FETCH_DOT) / 8;
+        self.fetch_sprite_pattern(registers, slot, self.cycles % 8 == 7);
       }
-      257 => self.evaluate_sprites(),
       321 => self.load_sprites(),
       _ => ()
     }
   }
 
-  fn evaluate_sprites(&mut self) {
-    self.secondary_oam.clear();
-    for idx in 0..=63 {
-      let address = idx * 4;
-      let sprite = Sprite::new(idx, &self.get_registers().oam_ram[address..(address + 4)]);
-
-      let sprite_size = usize::from(self.get_registers().ctrl_flags.get_sprite_size());
-      let scan_line = self.scan_line;
-      let sprite_y = usize::from(sprite.y);
-
-      if scan_line >= sprite_y && scan_line < (sprite_y + sprite_size) {
-        if self.secondary_oam.len() == 8 {
-          self.get_mut_registers().status_flags.set_sprite_overflow(true);
-          break;
-        }
-        self.secondary_oam.push(sprite);
+  // Pattern fetches of a slot, on the first dot of their second and third pair. Empty slots still fetch tile $FF,
+  // which mappers watching A12 count on.
+  fn fetch_sprite_pattern(&mut self, registers: &Registers, slot: usize, is_high: bool) {
+    let empty_slot = Sprite::new(false, &[0xFF; 4]);
+    let sprite = self.secondary_oam.get(slot).unwrap_or(&empty_slot);
+    let tile_address = sprite.tile_address(registers.ctrl_flags, self.scan_line);
+    let data = registers.fetch(if is_high { tile_address + 8 } else { tile_address }, PpuBusAccess::Sprite);
+    if let Some(sprite) = self.secondary_oam.get_mut(slot) {
+      if is_high {
+        sprite.data_hi = data;
+      } else {
+        sprite.data_lo = data;
       }
     }
   }
 
   fn load_sprites(&mut self) {
-    let mut sprites = self.secondary_oam.clone();
-    for sprite in sprites.iter_mut() {
-      let scan_line = self.scan_line;
-      let tile_address = sprite.tile_address(self.get_registers().ctrl_flags, scan_line);
-      sprite.data_lo = self.get_registers().ppu_read_reg(tile_address);
-      sprite.data_hi = self.get_registers().ppu_read_reg(tile_address + 8);
-    }
-    self.primary_oam = sprites;
-  }
+    self.primary_oam = self.secondary_oam.clone();
 
-  fn render_sprite_pixel(&mut self, x: usize) -> (u8, bool, bool) {
-    let mut color = 0;
-    let mut is_behind = false;
-    let mut possible_zero_hit = false;
-
-    if self.get_registers().mask_flags.is_rendering_sprites(x) {
-      for sprite in self.primary_oam.iter().rev() {
+    // Lower OAM indices are drawn on top, so they are painted last
+    self.sprite_line = [(0, false, false); 256];
+    for sprite in self.primary_oam.iter().rev() {
+      for x in usize::from(sprite.x)..(usize::from(sprite.x) + 8).min(256) {
         let sprite_color_idx = sprite.color_index(x);
-
         if sprite_color_idx > 0 {
-          possible_zero_hit = sprite.oam_index == 0 && x != 0xFF;
-          color = 0b1_00_00 | sprite.attributes.palette() << 2 | sprite_color_idx;
-          is_behind = sprite.attributes.is_behind_background();
+          let color = 0b1_00_00 | sprite.attributes.palette() << 2 | sprite_color_idx;
+          let is_behind = sprite.attributes.is_behind_background();
+          self.sprite_line[x] = (color, is_behind, sprite.is_sprite_zero && x != 0xFF);
         }
       }
     }
+  }
 
-    (color, is_behind, possible_zero_hit)
+  fn render_sprite_pixel(&self, registers: &Registers, x: usize) -> (u8, bool, bool) {
+    if registers.mask_flags.is_rendering_sprites(x) {
+      self.sprite_line[x]
+    } else {
+      (0, false, false)
+    }
   }
 
-  fn render_background_pixel(&mut self, x: usize) -> u8 {
+  fn render_background_pixel(&self, registers: &Registers, x: usize) -> u8 {
     let mut res = 0;
-    if self.get_registers().mask_flags.is_rendering_background(x) {
-      let nth = 15 - self.get_registers().fine_x;
+    if registers.mask_flags.is_rendering_background(x) {
+      let nth = 15 - registers.fine_x;
       res = get_nth_bit(self.bg_shifter_hi, nth) << 1 | get_nth_bit(self.bg_shifter_lo, nth);
 
       if res != 0 {
-        let nth = 7 - self.get_registers().fine_x;
+        let nth = 7 - registers.fine_x;
         res |= (get_nth_bit(self.attribute_shift_hi, nth) << 1 | get_nth_bit(self.attribute_shift_lo, nth)) << 2;
       }
     }
     res
   }
 
-  fn update_image_buffer(&mut self) {
+  fn update_image_buffer(&mut self, registers: &mut Registers, off_screen_pixels: &mut OffScreenBuffer) {
     if (2..=257).contains(&self.cycles) || (322..=337).contains(&self.cycles) {
       let x = self.cycles - 2;
       let y = self.scan_line;
 
-      if let Some(color) = self.get_screen_pixel(x, y) {
-        let pixel = self.get_pixel_color(color).to_value();
-        self.get_mut_off_screen_pixels()[(239 - y) * 256 + x] = pixel;
+      if let Some(color) = self.get_screen_pixel(registers, x, y) {
+        let palette_idx = Ppu::get_pixel_palette_index(registers, color);
+        let idx = (239 - y) * 256 + x;
+        off_screen_pixels[idx] = COLORS[usize::from(palette_idx & 0x3F)].to_value();
+        self.palette_indices[idx] = palette_idx;
       }
       self.update_shifters();
     }
   }
 
-  fn get_screen_pixel(&mut self, x: usize, y: usize) -> Option<u8> {
+  fn get_screen_pixel(&mut self, registers: &mut Registers, x: usize, y: usize) -> Option<u8> {
     if x < 256 && y < 240 {
-      let bg_pixel = self.render_background_pixel(x);
-      let (sprite_pixel, sprite_behind, possible_zero_hit) = self.render_sprite_pixel(x);
+      let bg_pixel = self.render_background_pixel(registers, x);
+      let (sprite_pixel, sprite_behind, possible_zero_hit) = self.render_sprite_pixel(registers, x);
 
       if possible_zero_hit && bg_pixel != 0 {
-        self.get_mut_registers().status_flags.set_sprite_zero_hit(true);
+        registers.status_flags.set_sprite_zero_hit(true);
       }
 
       let colors = if !sprite_behind {
@@ -439,8 +660,19 @@ impl Ppu {
     None
   }
 
-  fn fetch_next_bg_tile_attribute(&mut self) -> u16 {
-    let vram_addr = self.get_registers().vram_addr;
+  fn get_pixel_palette_index(registers: &Registers, pixel: u8) -> u16 {
+    let mask_flags = registers.mask_flags;
+    let palette: u16 = if mask_flags.is_rendering() {
+      pixel
+    } else {
+      0
+    }.into();
+    let idx = registers.ppu_read_reg(0x3F00 + palette) & 0x3F;
+    u16::from(idx) | (u16::from(mask_flags.0 >> 5) << 6)
+  }
+
+  fn fetch_next_bg_tile_attribute(registers: &Registers) -> u16 {
+    let vram_addr = registers.vram_addr;
 
     let nametable_x = u16::from(vram_addr.nametable_x());
     let nametable_y = u16::from(vram_addr.nametable_y());
@@ -450,3 +682,183 @@ impl Ppu {
     0x23C0 | (nametable_y << 11) | (nametable_x << 10) | ((coarse_y >> 2) << 3) | (coarse_x >> 2)
   }
 }
+
+#[cfg(test)]
+mod test {
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  use crate::cartridge::Cartridge;
+  use crate::cartridge::rom_reading::Rom;
+  use crate::cartridge::rom_with_pager::RomData;
+  use crate::mapper::{Mapper, PpuBusAccess, PpuBusWatch};
+  use crate::mapper::mapper0::Mapper0;
+  use crate::nes::constants::{SCREEN_RES_X, SCREEN_RES_Y};
+  use crate::nes::region::Region;
+  use crate::ppu::{Ppu, PpuState};
+  use crate::ppu::registers::Registers;
+
+  fn random_bytes(seed: u32, len: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..len).map(|_| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state as u8
+    }).collect()
+  }
+
+  type BusLog = Rc<RefCell<Vec<(u16, PpuBusAccess, u64)>>>;
+
+  // Records the PPU bus like a mapper watching the sprite fetches would see it
+  #[derive(Clone)]
+  struct BusLogMapper {
+    mapper: Mapper0,
+    log: BusLog,
+  }
+
+  impl Mapper for BusLogMapper {
+    fn mapped_read_cpu_u8(&self, address: u16) -> u8 {
+      self.mapper.mapped_read_cpu_u8(address)
+    }
+
+    fn mapped_write_cpu_u8(&mut self, address: u16, data: u8) {
+      self.mapper.mapped_write_cpu_u8(address, data)
+    }
+
+    fn mapped_read_ppu_u8(&self, address: u16) -> u8 {
+      self.mapper.mapped_read_ppu_u8(address)
+    }
+
+    fn mapped_write_ppu_u8(&mut self, address: u16, data: u8) {
+      self.mapper.mapped_write_ppu_u8(address, data)
+    }
+
+    fn ppu_bus_access(&mut self, address: u16, access: PpuBusAccess, dot: u64) -> Option<u8> {
+      self.log.borrow_mut().push((address, access, dot));
+      None
+    }
+
+    fn ppu_bus_watch(&self) -> PpuBusWatch {
+      PpuBusWatch::SpriteFetches
+    }
+  }
+
+  fn mock_ppu(is_catch_up: bool) -> Ppu {
+    mock_ppu_with_bus_log(is_catch_up, None)
+  }
+
+  fn mock_ppu_with_bus_log(is_catch_up: bool, log: Option<BusLog>) -> Ppu {
+    let mut rom = Rom::mock_rom();
+    rom.chr_rom = random_bytes(1, rom.chr_rom.len());
+    let rom_header = rom.rom_header;
+    let mapper0 = Mapper0::new(Rc::new(RefCell::new(RomData::new(rom))));
+    let mapper: Box<dyn Mapper> = match log {
+      Some(log) => Box::new(BusLogMapper { mapper: mapper0, log }),
+      None => Box::new(mapper0),
+    };
+    let cartridge = Rc::new(RefCell::new(Box::new(Cartridge { mapper, rom_header })));
+
+    let mut registers = Registers::new(cartridge, Region::Ntsc);
+    for (idx, data) in random_bytes(2, 0x1000).into_iter().enumerate() {
+      registers.ppu_write_reg(0x2000 + idx as u16, data);
+    }
+    for (idx, data) in random_bytes(3, 0x20).into_iter().enumerate() {
+      registers.ppu_write_reg(0x3F00 + idx as u16, data);
+    }
+    registers.oam_ram.copy_from_slice(&random_bytes(4, 0x100));
+    registers.oam_ram[0] = 20;
+    registers.oam_ram[3] = 40;
+    // 8x16 sprites, sprite and background tables swapped
+    registers.bus_write_ppu_reg(0x2000, 0x31);
+    registers.bus_write_ppu_reg(0x2001, 0x1E);
+    registers.bus_write_ppu_reg(0x2005, 0x0D);
+    registers.bus_write_ppu_reg(0x2005, 0x2B);
+
+    let off_screen_pixels = Rc::new(RefCell::new([[0u8; 3]; (SCREEN_RES_X * SCREEN_RES_Y) as usize]));
+    let mut ppu = Ppu::new(Rc::new(RefCell::new(registers)), off_screen_pixels, Region::Ntsc);
+    ppu.is_catch_up = is_catch_up;
+    ppu
+  }
+
+  // Runs until the next frame is done, calling `on_dot` at every dot like the CPU would between PPU clocks
+  fn run_frame(ppu: &mut Ppu, on_dot: &dyn Fn(&mut Ppu, usize, usize)) {
+    loop {
+      let (scan_line, dot) = if ppu.deferred_dots > 0 { (ppu.scan_line, ppu.deferred_dots) } else { (ppu.scan_line, ppu.cycles) };
+      if ppu.clock() == PpuState::Render {
+        return;
+      }
+      on_dot(ppu, scan_line, dot);
+    }
+  }
+
+  fn assert_same_state(catch_up: &Ppu, dot_accurate: &Ppu) {
+    assert!(catch_up.off_screen_pixels.borrow().iter().eq(dot_accurate.off_screen_pixels.borrow().iter()));
+    assert!(catch_up.palette_indices.iter().eq(dot_accurate.palette_indices.iter()));
+    assert_eq!((catch_up.scan_line, catch_up.cycles), (dot_accurate.scan_line, dot_accurate.cycles));
+    assert_eq!(catch_up.bg_shifter_lo, dot_accurate.bg_shifter_lo);
+    assert_eq!(catch_up.attribute_shift_hi, dot_accurate.attribute_shift_hi);
+    assert_eq!(catch_up.curr_address, dot_accurate.curr_address);
+
+    let (registers, expected) = (catch_up.get_registers(), dot_accurate.get_registers());
+    assert_eq!(registers.vram_addr, expected.vram_addr);
+    assert_eq!(registers.status_flags, expected.status_flags);
+    assert_eq!(registers.oam_address, expected.oam_address);
+    assert_eq!(registers.oam_bus, expected.oam_bus);
+    assert!(registers.oam_ram.iter().eq(expected.oam_ram.iter()));
+  }
+
+  #[test]
+  fn catch_up_matches_dot_accurate() {
+    let mut catch_up = mock_ppu(true);
+    let mut dot_accurate = mock_ppu(false);
+
+    for _ in 0..3 {
+      run_frame(&mut catch_up, &|_, _, _| ());
+      run_frame(&mut dot_accurate, &|_, _, _| ());
+      assert_same_state(&catch_up, &dot_accurate);
+    }
+    assert!(catch_up.get_registers().status_flags.sprite_zero_hit());
+  }
+
+  #[test]
+  fn mid_line_writes_fall_back_to_dots() {
+    // Hides sprites and changes the scroll in the middle of a few lines, like a status bar split
+    let split = |ppu: &mut Ppu, scan_line: usize, dot: usize| {
+      if (100..104).contains(&scan_line) && dot == 150 {
+        ppu.sync();
+        let mut registers = ppu.get_mut_registers();
+        registers.bus_write_ppu_reg(0x2001, 0x0A);
+        registers.bus_write_ppu_reg(0x2005, 0x40);
+      } else if scan_line == 104 && dot == 10 {
+        ppu.sync();
+        ppu.get_mut_registers().bus_write_ppu_reg(0x2001, 0x1E);
+      }
+    };
+
+    let mut catch_up = mock_ppu(true);
+    let mut dot_accurate = mock_ppu(false);
+    for _ in 0..2 {
+      run_frame(&mut catch_up, &split);
+      run_frame(&mut dot_accurate, &split);
+      assert_same_state(&catch_up, &dot_accurate);
+    }
+  }
+
+  #[test]
+  fn catch_up_keeps_bus_timing() {
+    let catch_up_log = Rc::new(RefCell::new(Vec::new()));
+    let dot_accurate_log = Rc::new(RefCell::new(Vec::new()));
+    let mut catch_up = mock_ppu_with_bus_log(true, Some(Rc::clone(&catch_up_log)));
+    let mut dot_accurate = mock_ppu_with_bus_log(false, Some(Rc::clone(&dot_accurate_log)));
+
+    for _ in 0..2 {
+      run_frame(&mut catch_up, &|_, _, _| ());
+      run_frame(&mut dot_accurate, &|_, _, _| ());
+      assert_same_state(&catch_up, &dot_accurate);
+    }
+    assert_eq!(*catch_up_log.borrow(), *dot_accurate_log.borrow());
+    // 34 tiles, two extra nametable fetches and eight sprites on every rendered line
+    assert!(dot_accurate_log.borrow().len() >= 2 * 240 * (34 * 4 + 2 + 8 * 2));
+  }
+}
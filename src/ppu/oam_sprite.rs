@@ -21,11 +21,11 @@ pub struct Sprite {
   pub x: u8,
   pub data_lo: u8,
   pub data_hi: u8,
-  pub oam_index: usize,
+  pub is_sprite_zero: bool,
 }
 
 impl Sprite {
-  pub fn new(oam_index: usize, bytes: &[u8]) -> Sprite {
+  pub fn new(is_sprite_zero: bool, bytes: &[u8]) -> Sprite {
     Sprite {
       y: bytes[0],
       index: SpriteTileIndex(bytes[1]),
@@ -33,11 +33,11 @@ impl Sprite {
       x: bytes[3],
       data_lo: 0,
       data_hi: 0,
-      oam_index,
+      is_sprite_zero,
     }
   }
 
-  pub fn tile_address(&mut self, control_flags: PpuCtrlFlags, scan_line: usize) -> u16 {
+  pub fn tile_address(&self, control_flags: PpuCtrlFlags, scan_line: usize) -> u16 {
     let tile_address = if control_flags.sprite_size() {
       0x1000 * u16::from(self.index.0 & 1) + 0x10 * u16::from(self.index.0 & !1)
     } else {
@@ -45,7 +45,7 @@ impl Sprite {
     };
 
     let sprite_size = control_flags.get_sprite_size();
-    let mut y_offset = (scan_line - self.y as usize) as u16 % sprite_size;
+    let mut y_offset = scan_line.wrapping_sub(self.y as usize) as u16 % sprite_size;
 
     if self.attributes.flip_y() {
       y_offset = control_flags.get_sprite_size() - 1 - y_offset;
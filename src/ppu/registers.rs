@@ -3,7 +3,9 @@ use std::convert::TryInto;
 use std::rc::Rc;
 
 use crate::cartridge::Cartridge;
-use crate::cartridge::rom_reading::Mirroring;
+use crate::cartridge::rom_reading::NameTableSource;
+use crate::mapper::{PpuBusAccess, PpuBusWatch};
+use crate::nes::region::Region;
 
 bitfield! {
   #[derive(Copy, Clone, Eq, PartialEq)]
@@ -84,6 +86,9 @@ bitfield! {
     pub u8,    lo_byte,      set_lo_byte:       7,  0;
 }
 
+// Bits of the I/O latch fade to zero roughly 600 ms after they were last driven
+const OPEN_BUS_DECAY_FRAMES: u8 = 36;
+
 pub fn get_nth_bit<T: Into<u16>, U: Into<u16>>(number: T, nth: U) -> u8 {
   ((number.into() >> nth.into()) & 1).try_into().unwrap()
 }
@@ -96,22 +101,35 @@ pub struct Registers {
   pub vram_addr: AddressRegister,
   pub tram_addr: AddressRegister,
   pub palette_table: [u8; 0x20],
-  name_table: [[u8; 0x0400]; 2],
+  // Two pages of console VRAM and two of four-screen cartridge VRAM
+  name_table: [[u8; 0x0400]; 4],
   address_latch: bool,
-  pub ppu_data_buffer: u8,
+  // I/O latch between the CPU and PPU, reads of write-only registers and undriven bits return it
+  io_latch: u8,
+  io_latch_decay: [u8; 8],
   pub fine_x: u8,
   cartridge: Rc<RefCell<Box<Cartridge>>>,
 
   pub oam_address: u8,
   pub oam_ram: [u8; 0x100],
-
-  pub vblank_suppress: bool,
-  pub force_nmi: bool,
+  /// Value on the OAM bus while the PPU renders, `None` outside rendering
+  pub oam_bus: Option<u8>,
+  oam_clock: u64,
+  oam_decay_dots: Option<u64>,
+  // Last access time of each 8-byte OAM row
+  oam_row_access: [u64; 32],
+  oam_corrupted_rows: u32,
+  /// PPU dot of the current access to the PPU bus, kept up to date by the PPU
+  pub bus_dot: u64,
+
+  /// Set on the dot before vblank starts, a $2002 read then keeps the vblank flag from being set this frame
+  pub is_vbl_pending: bool,
+  pub is_vbl_suppressed: bool,
   read_buffer: u8,
 }
 
 impl Registers {
-  pub fn new(cartridge: Rc<RefCell<Box<Cartridge>>>) -> Registers {
+  pub fn new(cartridge: Rc<RefCell<Box<Cartridge>>>, region: Region) -> Registers {
     Registers {
       ctrl_flags: PpuCtrlFlags(0x00),
       mask_flags: PpuMaskFlags(0x00),
@@ -119,16 +137,23 @@ impl Registers {
       vram_addr: AddressRegister(0x00),
       tram_addr: AddressRegister(0x00),
       palette_table: [0; 0x20],
-      name_table: [[0xFF; 0x0400]; 2],
+      name_table: [[0xFF; 0x0400]; 4],
       address_latch: false,
-      ppu_data_buffer: 0x00,
+      io_latch: 0x00,
+      io_latch_decay: [0; 8],
       fine_x: 0x00,
       cartridge,
 
       oam_address: 0,
       oam_ram: [0u8; 0x100],
-      vblank_suppress: false,
-      force_nmi: false,
+      oam_bus: None,
+      oam_clock: 0,
+      oam_decay_dots: region.oam_decay_dots(),
+      oam_row_access: [0; 32],
+      oam_corrupted_rows: 0,
+      bus_dot: 0,
+      is_vbl_pending: false,
+      is_vbl_suppressed: false,
       read_buffer: 0,
     }
   }
@@ -139,11 +164,18 @@ impl Registers {
     self.ctrl_flags = PpuCtrlFlags(0);
     self.vram_addr = AddressRegister(0);
     self.tram_addr = AddressRegister(0);
-    self.ppu_data_buffer = 0;
+    self.io_latch = 0;
+    self.io_latch_decay = [0; 8];
     self.fine_x = 0;
     self.oam_ram = [0; 0x0100];
+    self.oam_bus = None;
+    self.oam_clock = 0;
+    self.oam_row_access = [0; 32];
+    self.oam_corrupted_rows = 0;
     self.palette_table = [0; 0x20];
-    self.name_table = [[0u8; 0x0400]; 2];
+    self.name_table = [[0u8; 0x0400]; 4];
+    self.is_vbl_pending = false;
+    self.is_vbl_suppressed = false;
   }
 
   fn write_oam_address(&mut self, address: u8) {
@@ -151,12 +183,22 @@ impl Registers {
   }
 
   pub fn write_oam_data(&mut self, data: u8) {
+    if self.oam_bus.is_some() {
+      // Writes during rendering don't reach OAM but bump the sprite index part of OAMADDR
+      self.oam_address = self.oam_address.wrapping_add(4);
+      return;
+    }
+    self.refresh_oam_row();
     let idx = usize::from(self.oam_address);
     self.oam_ram[idx] = data;
     self.oam_address = self.oam_address.wrapping_add(1);
   }
 
-  fn read_oam_data(&self) -> u8 {
+  fn read_oam_data(&mut self) -> u8 {
+    if let Some(data) = self.oam_bus {
+      return data;
+    }
+    self.refresh_oam_row();
     let idx = usize::from(self.oam_address);
     if idx % 4 == 2 {
       self.oam_ram[idx] & 0xE3
@@ -165,6 +207,42 @@ impl Registers {
     }
   }
 
+  pub fn read_oam_for_evaluation(&mut self) -> u8 {
+    self.refresh_oam_row();
+    self.oam_ram[usize::from(self.oam_address)]
+  }
+
+  /// Advances the OAM decay clock by one PPU dot, the OAM bus is released until sprite evaluation drives it again
+  pub fn clock_oam(&mut self) {
+    self.oam_clock += 1;
+    self.oam_bus = None;
+  }
+
+  // Decayed rows read back as $10 bytes, the same pattern Mesen uses
+  fn refresh_oam_row(&mut self) {
+    let row = usize::from(self.oam_address >> 3);
+    let idle_dots = self.oam_clock - self.oam_row_access[row];
+    if self.oam_decay_dots.is_some_and(|decay_dots| idle_dots > decay_dots) {
+      self.oam_ram[row * 8..row * 8 + 8].fill(0x10);
+    }
+    self.oam_row_access[row] = self.oam_clock;
+  }
+
+  /// Disabling rendering mid-frame corrupts the OAM row OAMADDR points to
+  pub fn corrupt_oam_row(&mut self) {
+    self.oam_corrupted_rows |= 1 << (self.oam_address >> 3);
+  }
+
+  /// Corruption shows up once rendering is enabled again, the first row gets copied over the corrupted ones
+  pub fn apply_oam_corruption(&mut self) {
+    for row in 1..32 {
+      if self.oam_corrupted_rows & (1 << row) > 0 {
+        self.oam_ram.copy_within(0..8, row * 8);
+      }
+    }
+    self.oam_corrupted_rows = 0;
+  }
+
   pub fn get_mut_cartridge(&mut self) -> RefMut<Box<Cartridge>> {
     self.cartridge.borrow_mut()
   }
@@ -173,13 +251,36 @@ impl Registers {
     self.cartridge.borrow()
   }
 
+  pub fn mapper_bus_watch(&self) -> PpuBusWatch {
+    self.get_cartridge().mapper.ppu_bus_watch()
+  }
+
+  // Shows an address access to the mapper, the palette is inside the PPU and never shows up on the bus
+  fn drive_bus(&self, address: u16, access: PpuBusAccess) -> Option<u8> {
+    let address = address & 0x3FFF;
+    if address < 0x3F00 {
+      self.cartridge.borrow_mut().mapper.ppu_bus_access(address, access, self.bus_dot)
+    } else {
+      None
+    }
+  }
+
+  /// Nametable, attribute and pattern reads of rendering, seen by the mapper while rendering is on
+  pub fn fetch(&self, address: u16, access: PpuBusAccess) -> u8 {
+    let data = if self.mask_flags.is_rendering() { self.drive_bus(address, access) } else { None };
+    data.unwrap_or_else(|| self.ppu_read_reg(address))
+  }
+
   pub fn ppu_read_reg(&self, address: u16) -> u8 {
     let addr = address & 0x3FFF;
     if (0x0000..=0x1FFF).contains(&addr) {
       self.get_cartridge().mapper.mapped_read_ppu_u8(addr & 0x3FFF)
     } else if (0x2000..=0x3EFF).contains(&addr) {
-      let (fst_idx, snd_idx) = mirror_name_table(self.get_cartridge().get_mirror_mode(), addr);
-      self.name_table[fst_idx][snd_idx]
+      let cartridge = self.get_cartridge();
+      match mirror_name_table(cartridge.name_tables(), addr) {
+        (NameTableSource::Vram(page), idx) => self.name_table[page][idx],
+        (NameTableSource::Cartridge, _) => cartridge.mapper.read_name_table(addr),
+      }
     } else if (0x3F00..=0x3FFF).contains(&addr) {
       self.palette_table[palette_table(addr)]
     } else {
@@ -192,15 +293,18 @@ impl Registers {
     if (0x0000..=0x1FFF).contains(&addr) {
       self.get_mut_cartridge().mapper.mapped_write_ppu_u8(addr & 0x3FFF, data)
     } else if (0x2000..=0x3EFF).contains(&addr) {
-      let (fst_idx, snd_idx) = mirror_name_table(self.get_cartridge().get_mirror_mode(), addr);
-      self.name_table[fst_idx][snd_idx] = data;
+      let name_tables = self.get_cartridge().name_tables();
+      match mirror_name_table(name_tables, addr) {
+        (NameTableSource::Vram(page), idx) => self.name_table[page][idx] = data,
+        (NameTableSource::Cartridge, _) => self.get_mut_cartridge().mapper.write_name_table(addr, data),
+      }
     } else if (0x3F00..=0x3FFF).contains(&addr) {
       self.palette_table[palette_table(addr)] = data;
     }
   }
 
   pub fn bus_write_ppu_reg(&mut self, address: u16, data: u8) {
-    self.ppu_data_buffer = data;
+    self.refresh_io_latch(data, 0xFF);
     match address % 8 {
       0x00 => self.write_control(data),
       0x01 => { self.mask_flags.0 = data; }
@@ -215,9 +319,6 @@ impl Registers {
   }
 
   fn write_control(&mut self, data: u8) {
-    if !self.ctrl_flags.enable_nmi() && PpuCtrlFlags(data).enable_nmi() {
-      self.force_nmi = true;
-    }
     self.ctrl_flags.0 = data;
 
     let ctrl_flags = self.ctrl_flags;
@@ -243,6 +344,7 @@ impl Registers {
       let tram_addr = self.tram_addr;
       self.vram_addr = tram_addr;
       self.address_latch = false;
+      self.drive_idle_bus();
     } else {
       self.tram_addr.set_hi_byte(data);
       self.address_latch = true;
@@ -251,83 +353,98 @@ impl Registers {
 
   fn write_data(&mut self, data: u8) {
     let increment_val = if self.ctrl_flags.vram_addr_increment_mode() { 32 } else { 1 };
+    self.drive_idle_bus();
     self.ppu_write_reg(self.vram_addr.0, data);
     let addr = self.vram_addr.0;
     self.vram_addr = AddressRegister(addr.wrapping_add(increment_val));
+    self.drive_idle_bus();
   }
 
+  // Outside of rendering the bus holds the VRAM address, so $2006 and $2007 accesses can clock A12 based counters
+  fn drive_idle_bus(&self) {
+    if !self.mask_flags.is_rendering() {
+      self.drive_bus(self.vram_addr.0, PpuBusAccess::Cpu);
+    }
+  }
+
+  /// Reads only drive the bits the register implements, the rest come from the I/O latch
   pub fn bus_read_ppu_reg(&mut self, address: u16) -> u8 {
-    let res = match address % 8 {
-      0x00 => self.ppu_data_buffer,
-      0x01 => self.ppu_data_buffer,
-      0x02 => self.read_reg_status(),
-      0x03 => self.ppu_data_buffer,
-      0x04 => self.read_oam_data(),
-      0x05 => self.ppu_data_buffer,
-      0x06 => self.ppu_data_buffer,
+    match address % 8 {
+      0x00 | 0x01 | 0x03 | 0x05 | 0x06 => self.io_latch,
+      0x02 => {
+        let status = self.read_reg_status();
+        self.refresh_io_latch(status, 0xE0)
+      }
+      0x04 => {
+        let data = self.read_oam_data();
+        self.refresh_io_latch(data, 0xFF)
+      }
       0x07 => self.read_ppu_data(),
       _ => panic!("cpu_read_reg address: {} not in range", address),
-    };
-    self.ppu_data_buffer = res;
-    res
+    }
+  }
+
+  fn refresh_io_latch(&mut self, data: u8, mask: u8) -> u8 {
+    self.io_latch = (self.io_latch & !mask) | (data & mask);
+    for (bit, decay) in self.io_latch_decay.iter_mut().enumerate() {
+      if mask & (1 << bit) > 0 {
+        *decay = OPEN_BUS_DECAY_FRAMES;
+      }
+    }
+    self.io_latch
+  }
+
+  /// Called once per frame, clears the latch bits that haven't been refreshed for a while
+  pub fn decay_io_latch(&mut self) {
+    for (bit, decay) in self.io_latch_decay.iter_mut().enumerate() {
+      if *decay > 0 {
+        *decay -= 1;
+        if *decay == 0 {
+          self.io_latch &= !(1 << bit);
+        }
+      }
+    }
   }
 
   fn read_reg_status(&mut self) -> u8 {
     let res = self.status_flags.0;
     self.status_flags.set_vertical_blank(false);
     self.address_latch = false;
-    self.vblank_suppress = true;
-    res | (self.ppu_data_buffer & 0x1F)
-  }
-
-  fn read_ppu_data(&mut self) -> u8 {
-    let vram_addr = self.vram_addr.address();
-    if (0x3F00..=0x3FFF).contains(&vram_addr) {
-      self.read_data() | (self.ppu_data_buffer & 0xC0)
-    } else {
-      self.read_data()
+    if self.is_vbl_pending {
+      self.is_vbl_suppressed = true;
     }
+    res
   }
 
-  fn read_data(&mut self) -> u8 {
+  fn read_ppu_data(&mut self) -> u8 {
     let addr = self.vram_addr.address();
     let increment_val = if self.ctrl_flags.vram_addr_increment_mode() { 32 } else { 1 };
+    self.drive_idle_bus();
     self.vram_addr.0 = self.vram_addr.0.wrapping_add(increment_val);
-    self.buffered_read_byte(addr)
-  }
-
-  fn buffered_read_byte(&mut self, addr: u16) -> u8 {
-    let prev_read_buffer = self.read_buffer;
-    self.read_buffer = self.ppu_read_reg(addr);
-    prev_read_buffer
-  }
-}
-
-fn mirror_name_table(mirror_mode: Mirroring, addr: u16) -> (usize, usize) {
-  let addr_range = addr & 0x0FFF;
-  let idx = usize::from(addr_range & 0x03FF);
-  match mirror_mode {
-    Mirroring::Vertical => {
-      match addr_range {
-        0x0000..=0x03FF => (0, idx),
-        0x0400..=0x07FF => (1, idx),
-        0x0800..=0x0BFF => (0, idx),
-        0x0C00..=0x0FFF => (1, idx),
-        _ => panic!("Unknown vertical mode table address")
-      }
-    }
-    Mirroring::Horizontal => {
-      match addr_range {
-        0x0000..=0x03FF => (0, idx),
-        0x0400..=0x07FF => (0, idx),
-        0x0800..=0x0BFF => (1, idx),
-        0x0C00..=0x0FFF => (1, idx),
-        _ => panic!("Unknown horizontal mode table address")
+    self.drive_idle_bus();
+
+    if (0x3F00..=0x3FFF).contains(&addr) {
+      // Palette reads aren't buffered, the buffer gets the nametable byte underneath instead
+      self.read_buffer = self.ppu_read_reg(addr - 0x1000);
+      let mut palette = self.ppu_read_reg(addr);
+      if self.mask_flags.grayscale() {
+        palette &= 0x30;
       }
+      self.refresh_io_latch(palette, 0x3F)
+    } else {
+      let data = self.read_buffer;
+      self.read_buffer = self.ppu_read_reg(addr);
+      self.refresh_io_latch(data, 0xFF)
     }
   }
 }
 
+fn mirror_name_table(name_tables: [NameTableSource; 4], addr: u16) -> (NameTableSource, usize) {
+  let quadrant = usize::from((addr >> 10) & 0x03);
+  let idx = usize::from(addr & 0x03FF);
+  (name_tables[quadrant], idx)
+}
+
 fn palette_table(addr: u16) -> usize {
   let address = (addr as usize) & 0x001F;
   match address {
@@ -342,12 +459,14 @@ mod test {
   use std::rc::Rc;
 
   use crate::cartridge::Cartridge;
+  use crate::cartridge::rom_reading::Mirroring;
+  use crate::nes::region::Region;
   use crate::ppu::registers::Registers;
 
   #[test]
   fn ppu_table_write_and_read() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.ppu_write_reg(0x2000u16, 1u8);
     let res = registers.ppu_read_reg(0x2000u16);
@@ -355,10 +474,55 @@ mod test {
     assert_eq!(res, 1u8)
   }
 
+  #[test]
+  fn name_table_mirroring() {
+    let quadrants = [0x2000u16, 0x2400, 0x2800, 0x2C00];
+    let cases = [
+      (Mirroring::Vertical, [3, 4, 3, 4]),
+      (Mirroring::Horizontal, [2, 2, 4, 4]),
+      (Mirroring::SingleScreenLower, [4, 4, 4, 4]),
+      (Mirroring::SingleScreenUpper, [4, 4, 4, 4]),
+      (Mirroring::FourScreen, [1, 2, 3, 4]),
+    ];
+
+    for (mirroring, expected) in cases {
+      let cart = Cartridge::mock_cartridge_with_mirroring(mirroring);
+      let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
+
+      for (idx, address) in quadrants.iter().enumerate() {
+        registers.ppu_write_reg(address + 0x10, idx as u8 + 1);
+      }
+      // $3000-$3EFF mirrors $2000-$2EFF
+      let res = quadrants.map(|address| registers.ppu_read_reg(address + 0x1010));
+      assert_eq!(res, expected, "{:?}", mirroring);
+    }
+  }
+
+  #[test]
+  fn single_screen_mirroring_picks_the_page() {
+    let quadrants = [0x2000u16, 0x2400, 0x2800, 0x2C00];
+
+    for (mirroring, page) in [(Mirroring::SingleScreenLower, 0), (Mirroring::SingleScreenUpper, 1)] {
+      let cart = Cartridge::mock_cartridge_with_mirroring(mirroring);
+      let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
+      registers.name_table[0] = [0xA0; 0x0400];
+      registers.name_table[1] = [0xB1; 0x0400];
+
+      let expected = [0xA0, 0xB1][page];
+      assert_eq!(quadrants.map(|address| registers.ppu_read_reg(address + 0x0123)), [expected; 4], "{:?}", mirroring);
+
+      // Writes through any quadrant land in the same page, the other one is left alone
+      registers.ppu_write_reg(0x2C00 + 0x0123, 0x42);
+      assert_eq!(registers.name_table[page][0x0123], 0x42, "{:?}", mirroring);
+      assert_eq!(registers.name_table[1 - page][0x0123], [0xB1, 0xA0][page], "{:?}", mirroring);
+      assert_eq!(registers.ppu_read_reg(0x2000 + 0x0123), 0x42, "{:?}", mirroring);
+    }
+  }
+
   #[test]
   fn ppu_status_register_write_and_read() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.status_flags.set_sprite_overflow(true);
 
@@ -374,7 +538,7 @@ mod test {
   #[test]
   fn control_reg_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.bus_write_ppu_reg(0x2000, 0xAF);
     assert_eq!(registers.ctrl_flags.0, 0xAF)
@@ -383,7 +547,7 @@ mod test {
   #[test]
   fn mask_reg_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.bus_write_ppu_reg(0x2001, 0xBF);
     assert_eq!(registers.mask_flags.0, 0xBF)
@@ -392,7 +556,7 @@ mod test {
   #[test]
   fn oam_data_reg_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.bus_write_ppu_reg(0x2003, 0xF0);
     registers.bus_write_ppu_reg(0x2004, 0x04);
@@ -403,7 +567,7 @@ mod test {
   #[test]
   fn scroll_reg_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.bus_write_ppu_reg(0x2005, 0xEE);
     assert_eq!(registers.fine_x, 6);
@@ -420,7 +584,7 @@ mod test {
   #[test]
   fn address_reg_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.bus_write_ppu_reg(0x2006, 0x1A);
     assert_eq!(registers.tram_addr.0, 0x1A00);
@@ -435,7 +599,7 @@ mod test {
   #[test]
   fn data_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.vram_addr.0 = 0x2000;
     registers.bus_write_ppu_reg(0x2007, 0xF0);
@@ -451,7 +615,7 @@ mod test {
   #[test]
   fn read_ghost_bits_reg_write() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.bus_write_ppu_reg(0x2002, 0xFA);
     registers.status_flags.0 = 0;
@@ -463,10 +627,52 @@ mod test {
     assert_eq!(registers.bus_read_ppu_reg(0x2006), 0x1A);
   }
 
+  #[test]
+  fn palette_read_keeps_open_bus_bits() {
+    let cart = Cartridge::mock_cartridge();
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
+
+    registers.ppu_write_reg(0x3F01, 0xEA);
+    registers.ppu_write_reg(0x2F01, 0x55);
+    registers.bus_write_ppu_reg(0x2006, 0x3F);
+    registers.bus_write_ppu_reg(0x2006, 0x01);
+    // The last write left 0x01 on the latch, the upper bits of the palette entry are not driven
+    assert_eq!(registers.bus_read_ppu_reg(0x2007), 0x2A);
+    assert_eq!(registers.read_buffer, 0x55);
+
+    registers.bus_write_ppu_reg(0x2006, 0x3F);
+    registers.bus_write_ppu_reg(0x2006, 0x01);
+    // Now the latch holds 0xC1, its upper bits show through and the low ones come from the palette
+    registers.bus_write_ppu_reg(0x2003, 0xC1);
+    assert_eq!(registers.bus_read_ppu_reg(0x2007), 0xEA);
+  }
+
+  #[test]
+  fn io_latch_decays_per_bit() {
+    let cart = Cartridge::mock_cartridge();
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
+
+    registers.bus_write_ppu_reg(0x2003, 0xFF);
+    for _ in 0..30 {
+      registers.decay_io_latch();
+    }
+    // Status read refreshes only the top 3 bits
+    registers.status_flags.0 = 0xE0;
+    assert_eq!(registers.bus_read_ppu_reg(0x2002), 0xFF);
+    for _ in 0..6 {
+      registers.decay_io_latch();
+    }
+    assert_eq!(registers.bus_read_ppu_reg(0x2000), 0xE0);
+    for _ in 0..30 {
+      registers.decay_io_latch();
+    }
+    assert_eq!(registers.bus_read_ppu_reg(0x2000), 0x00);
+  }
+
   #[test]
   fn delayed_read_data() {
     let cart = Cartridge::mock_cartridge();
-    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))));
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
 
     registers.ppu_write_reg(0x2001, 0x07);
     registers.ppu_write_reg(0x2002, 0x0B);
@@ -477,4 +683,60 @@ mod test {
     assert_eq!(registers.bus_read_ppu_reg(0x2007), 0x0B);
     assert_eq!(registers.bus_read_ppu_reg(0x2007), 0x0E);
   }
+
+  #[test]
+  fn oam_data_during_rendering() {
+    let cart = Cartridge::mock_cartridge();
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
+
+    registers.bus_write_ppu_reg(0x2003, 0x10);
+    registers.bus_write_ppu_reg(0x2004, 0x55);
+    assert_eq!(registers.oam_ram[0x10], 0x55);
+
+    registers.oam_bus = Some(0xFF);
+    registers.bus_write_ppu_reg(0x2004, 0xAA);
+    // Write is dropped and only the sprite index is bumped
+    assert_eq!(registers.oam_address, 0x15);
+    assert_eq!(registers.oam_ram[0x11], 0);
+    assert_eq!(registers.bus_read_ppu_reg(0x2004), 0xFF);
+  }
+
+  #[test]
+  fn oam_rows_decay() {
+    let cart = Cartridge::mock_cartridge();
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Ntsc);
+
+    registers.bus_write_ppu_reg(0x2003, 0x00);
+    registers.bus_write_ppu_reg(0x2004, 0x55);
+    registers.bus_write_ppu_reg(0x2003, 0x08);
+    registers.bus_write_ppu_reg(0x2004, 0x66);
+    for _ in 0..5000 {
+      registers.clock_oam();
+    }
+    // Touching row 1 keeps it alive
+    registers.bus_write_ppu_reg(0x2003, 0x08);
+    registers.bus_read_ppu_reg(0x2004);
+    for _ in 0..5000 {
+      registers.clock_oam();
+    }
+    registers.bus_write_ppu_reg(0x2003, 0x00);
+    assert_eq!(registers.bus_read_ppu_reg(0x2004), 0x10);
+    registers.bus_write_ppu_reg(0x2003, 0x08);
+    assert_eq!(registers.bus_read_ppu_reg(0x2004), 0x66);
+  }
+
+  #[test]
+  fn pal_oam_does_not_decay() {
+    let cart = Cartridge::mock_cartridge();
+    let mut registers = Registers::new(Rc::new(RefCell::new(Box::new(cart))), Region::Pal);
+
+    registers.bus_write_ppu_reg(0x2003, 0x00);
+    registers.bus_write_ppu_reg(0x2004, 0x55);
+    // Longer than a PAL vblank
+    for _ in 0..341 * 72 {
+      registers.clock_oam();
+    }
+    registers.bus_write_ppu_reg(0x2003, 0x00);
+    assert_eq!(registers.bus_read_ppu_reg(0x2004), 0x55);
+  }
 }
@@ -0,0 +1,122 @@
+use crate::ppu::oam_sprite::Sprite;
+use crate::ppu::registers::Registers;
+
+/// Dot by dot sprite evaluation into secondary OAM, following the nesdev pseudocode including the sprite overflow
+/// bug. It walks primary OAM with OAMADDR, so glitched OAMADDR writes during rendering affect it like on hardware.
+#[derive(Clone)]
+pub struct SpriteEvaluation {
+  secondary_oam: [u8; 32],
+  sprite_count: usize,
+  bytes_to_copy: usize,
+  is_done: bool,
+  has_sprite_zero: bool,
+  // Last byte read from primary OAM, odd dots read and even dots write
+  oam_data: u8,
+}
+
+impl SpriteEvaluation {
+  pub fn new() -> SpriteEvaluation {
+    SpriteEvaluation {
+      secondary_oam: [0xFF; 32],
+      sprite_count: 0,
+      bytes_to_copy: 0,
+      is_done: false,
+      has_sprite_zero: false,
+      oam_data: 0xFF,
+    }
+  }
+
+  /// Runs one dot of a rendering scanline and returns the value on the OAM bus, which is what $2004 reads see
+  pub fn clock(&mut self, registers: &mut Registers, dot: usize, scan_line: usize, is_pre_render: bool) -> u8 {
+    match dot {
+      1..=64 => {
+        if dot == 64 {
+          self.clear();
+        }
+        0xFF
+      }
+      // No evaluation for the first visible line happens on the pre-render line
+      65..=256 if is_pre_render => 0xFF,
+      65..=256 => {
+        if dot % 2 == 1 {
+          self.oam_data = registers.read_oam_for_evaluation();
+        } else {
+          self.write(registers, dot, scan_line);
+        }
+        self.oam_data
+      }
+      257..=320 => {
+        registers.oam_address = 0;
+        let slot = (dot - 257) / 8;
+        let byte = ((dot - 257) % 8).min(3);
+        self.secondary_oam[slot * 4 + byte]
+      }
+      _ => self.secondary_oam[0],
+    }
+  }
+
+  pub fn sprites(&self) -> Vec<Sprite> {
+    (0..self.sprite_count)
+      .map(|slot| {
+        let is_sprite_zero = slot == 0 && self.has_sprite_zero;
+        Sprite::new(is_sprite_zero, &self.secondary_oam[slot * 4..slot * 4 + 4])
+      })
+      .collect::<Vec<Sprite>>()
+  }
+
+  fn clear(&mut self) {
+    self.secondary_oam = [0xFF; 32];
+    self.sprite_count = 0;
+    self.bytes_to_copy = 0;
+    self.is_done = false;
+    self.has_sprite_zero = false;
+  }
+
+  fn write(&mut self, registers: &mut Registers, dot: usize, scan_line: usize) {
+    let sprite_size = i32::from(registers.ctrl_flags.get_sprite_size());
+    let diff = scan_line as i32 - i32::from(self.oam_data);
+    let is_in_range = (0..sprite_size).contains(&diff);
+
+    if self.is_done {
+      // Copies to the full secondary OAM fail, only n keeps counting
+      registers.oam_address = registers.oam_address.wrapping_add(4);
+    } else if self.sprite_count < 8 {
+      let slot = self.sprite_count * 4;
+      if self.bytes_to_copy > 0 {
+        self.secondary_oam[slot + 4 - self.bytes_to_copy] = self.oam_data;
+        self.bytes_to_copy -= 1;
+        if self.bytes_to_copy == 0 {
+          self.sprite_count += 1;
+        }
+        self.advance(registers, 1);
+      } else {
+        self.secondary_oam[slot] = self.oam_data;
+        if is_in_range {
+          self.has_sprite_zero |= dot == 66;
+          self.bytes_to_copy = 3;
+          self.advance(registers, 1);
+        } else {
+          self.advance(registers, 4);
+        }
+      }
+    } else if is_in_range {
+      registers.status_flags.set_sprite_overflow(true);
+      self.is_done = true;
+      self.advance(registers, 4);
+    } else {
+      // Hardware bug: both n and m are incremented, so the following checks read tile, attribute and X bytes as Y
+      let address = registers.oam_address;
+      self.is_done = address >= 0xFC;
+      registers.oam_address = (address.wrapping_add(4) & 0xFC) | (address.wrapping_add(1) & 0x03);
+    }
+  }
+
+  fn advance(&mut self, registers: &mut Registers, step: u8) {
+    let (address, is_overflow) = registers.oam_address.overflowing_add(step);
+    registers.oam_address = address;
+    // n wrapped around, all 64 sprites have been checked
+    if is_overflow {
+      self.is_done = true;
+    }
+  }
+}
@@ -0,0 +1,42 @@
+use std::fs::File;
+use std::io::{BufWriter, Result};
+use std::path::Path;
+
+use crate::gfx::frame::Frame;
+use crate::recorder::wav::WavWriter;
+use crate::recorder::y4m::Y4mWriter;
+
+pub mod vgm;
+pub mod wav;
+pub mod y4m;
+
+/// Records the raw PPU output to `<path>.y4m` and the APU samples to `<path>.wav`. Audio is written together with
+/// the video frame it was generated in, so both streams stay in sync in emulated time regardless of how fast the
+/// emulator actually runs.
+pub struct Recorder {
+  video: Y4mWriter<BufWriter<File>>,
+  audio: WavWriter<BufWriter<File>>,
+}
+
+impl Recorder {
+  pub fn create(path: &Path, width: u32, height: u32, frame_rate: (u32, u32), sample_rate: u32) -> Result<Recorder> {
+    let video_file = BufWriter::new(File::create(path.with_extension("y4m"))?);
+    let audio_file = BufWriter::new(File::create(path.with_extension("wav"))?);
+
+    Ok(Recorder {
+      video: Y4mWriter::new(video_file, width, height, frame_rate)?,
+      audio: WavWriter::new(audio_file, sample_rate, 2)?,
+    })
+  }
+
+  /// `samples` are the interleaved stereo samples generated while the frame was rendered
+  pub fn write_frame(&mut self, frame: &Frame, samples: &[i16]) -> Result<()> {
+    self.video.write_frame(frame)?;
+    self.audio.write_samples(samples)
+  }
+
+  pub fn finish(&mut self) -> Result<()> {
+    self.video.flush()?;
+    self.audio.finish()
+  }
+}
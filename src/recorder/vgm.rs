@@ -0,0 +1,223 @@
+use std::io::{Result, Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 0x100;
+const VERSION: u32 = 0x171;
+// Waits are counted in samples of this rate, whatever the rate of the player
+const VGM_SAMPLE_RATE: u64 = 44_100;
+// The FDS flag of the NES APU clock field
+const FDS_ENABLED: u32 = 0x8000_0000;
+
+const NES_APU_WRITE: u8 = 0xB4;
+const WAIT_SAMPLES: u8 = 0x61;
+const WAIT_NTSC_FRAME: u8 = 0x62;
+const WAIT_PAL_FRAME: u8 = 0x63;
+const WAIT_SHORT: u8 = 0x70;
+const DATA_BLOCK: u8 = 0x67;
+const END_OF_DATA: u8 = 0x66;
+// Data block type that writes into the memory of the NES APU, where the DMC reads its samples
+const NES_APU_RAM: u8 = 0xC2;
+
+/// VGM 1.71 writer for the NES APU. Register writes are timed in CPU cycles and turned into 44.1 kHz waits, DMC
+/// samples are sent as data blocks when the DMC fetches them since VGM players keep their own copy of the sample
+/// memory.
+/// The header sizes are patched on `finish` or drop.
+pub struct VgmWriter<W: Write + Seek> {
+  writer: W,
+  clock_rate: u32,
+  // CPU cycles since the start of the log
+  cycles: u64,
+  // Time already written as waits, in VGM samples
+  samples: u64,
+  // 0x8000-0xFFFF as sent to the player, `None` where nothing was sent yet
+  sample_memory: Vec<Option<u8>>,
+  has_fds: bool,
+  data_len: u32,
+  is_finished: bool,
+}
+
+impl<W: Write + Seek> VgmWriter<W> {
+  /// `clock_rate` is the CPU clock rate of the console region
+  pub fn new(mut writer: W, clock_rate: u32) -> Result<VgmWriter<W>> {
+    writer.write_all(&[0u8; HEADER_LEN as usize])?;
+
+    Ok(VgmWriter {
+      writer,
+      clock_rate,
+      cycles: 0,
+      samples: 0,
+      sample_memory: vec![None; 0x8000],
+      has_fds: false,
+      data_len: 0,
+      is_finished: false,
+    })
+  }
+
+  /// Advances the log time by one CPU cycle
+  pub fn clock(&mut self) {
+    self.cycles += 1;
+  }
+
+  /// Logs a write to 0x4000-0x401F, or to the FDS sound registers at 0x4023 and 0x4040-0x409F. Other addresses
+  /// have no VGM equivalent and are skipped.
+  pub fn write_register(&mut self, address: u16, data: u8) -> Result<()> {
+    let register = match address {
+      0x4000..=0x401F => address - 0x4000,
+      0x4023 => 0x3F,
+      // Wave table
+      0x4040..=0x407F => address,
+      0x4080..=0x409E => address - 0x4080 + 0x20,
+      _ => return Ok(()),
+    };
+    self.has_fds |= address > 0x401F;
+    self.write_waits()?;
+    self.write(&[NES_APU_WRITE, register as u8, data])
+  }
+
+  /// Sends the sample starting at `address` unless the player already has it. The sample memory wraps from 0xFFFF
+  /// to 0x8000 like the DMC address.
+  pub fn write_sample(&mut self, address: u16, bytes: &[u8]) -> Result<()> {
+    let start = usize::from(address & 0x7FFF);
+    let is_sent = bytes.iter().enumerate()
+      .all(|(idx, &byte)| self.sample_memory[(start + idx) % 0x8000] == Some(byte));
+    if is_sent {
+      return Ok(());
+    }
+
+    self.write_waits()?;
+    let (first, wrapped) = bytes.split_at(bytes.len().min(0x8000 - start));
+    for (start, part) in [(start, first), (0, wrapped)] {
+      if part.is_empty() {
+        continue;
+      }
+      let size = part.len() as u32 + 2;
+      self.write(&[DATA_BLOCK, END_OF_DATA, NES_APU_RAM])?;
+      self.write(&size.to_le_bytes())?;
+      self.write(&(0x8000 | start as u16).to_le_bytes())?;
+      self.write(part)?;
+      for (idx, &byte) in part.iter().enumerate() {
+        self.sample_memory[start + idx] = Some(byte);
+      }
+    }
+    Ok(())
+  }
+
+  pub fn finish(&mut self) -> Result<()> {
+    if self.is_finished {
+      return Ok(());
+    }
+    self.is_finished = true;
+
+    self.write_waits()?;
+    self.write(&[END_OF_DATA])?;
+
+    let clock = if self.has_fds { self.clock_rate | FDS_ENABLED } else { self.clock_rate };
+    let header_fields: [(u64, u32); 5] = [
+      (0x04, HEADER_LEN + self.data_len - 4),
+      (0x08, VERSION),
+      (0x18, self.samples as u32),
+      (0x34, HEADER_LEN - 0x34),
+      (0x84, clock),
+    ];
+    self.writer.seek(SeekFrom::Start(0))?;
+    self.writer.write_all(b"Vgm ")?;
+    for (offset, value) in header_fields {
+      self.writer.seek(SeekFrom::Start(offset))?;
+      self.writer.write_all(&value.to_le_bytes())?;
+    }
+    self.writer.seek(SeekFrom::End(0))?;
+    self.writer.flush()
+  }
+
+  // Catches the log time up with the CPU time, rounded down to whole samples
+  fn write_waits(&mut self) -> Result<()> {
+    let target = self.cycles * VGM_SAMPLE_RATE / u64::from(self.clock_rate);
+    while self.samples < target {
+      let wait = (target - self.samples).min(0xFFFF);
+      match wait {
+        735 => self.write(&[WAIT_NTSC_FRAME])?,
+        882 => self.write(&[WAIT_PAL_FRAME])?,
+        1..=16 => self.write(&[WAIT_SHORT + wait as u8 - 1])?,
+        _ => {
+          self.write(&[WAIT_SAMPLES])?;
+          self.write(&(wait as u16).to_le_bytes())?;
+        }
+      }
+      self.samples += wait;
+    }
+    Ok(())
+  }
+
+  fn write(&mut self, bytes: &[u8]) -> Result<()> {
+    self.writer.write_all(bytes)?;
+    self.data_len += bytes.len() as u32;
+    Ok(())
+  }
+
+  #[cfg(test)]
+  pub fn get_ref(&self) -> &W {
+    &self.writer
+  }
+}
+
+impl<W: Write + Seek> Drop for VgmWriter<W> {
+  fn drop(&mut self) {
+    if let Err(e) = self.finish() {
+      eprintln!("Failed to finish VGM file: {}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::io::Cursor;
+
+  use crate::recorder::vgm::VgmWriter;
+
+  fn word(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+  }
+
+  #[test]
+  fn commands_and_header() {
+    let mut vgm = VgmWriter::new(Cursor::new(Vec::new()), 1_789_773).unwrap();
+    vgm.write_register(0x4015, 0x01).unwrap();
+    // One NTSC frame, then a few samples
+    for _ in 0..29_830 {
+      vgm.clock();
+    }
+    vgm.write_register(0x4000, 0xBF).unwrap();
+    for _ in 0..200 {
+      vgm.clock();
+    }
+    vgm.write_register(0x4088, 0x12).unwrap();
+    // Ignored, VGM has no VRC6 support
+    vgm.write_register(0x9000, 0x12).unwrap();
+    vgm.finish().unwrap();
+
+    let bytes = vgm.get_ref().get_ref().clone();
+    assert_eq!(&bytes[0..4], b"Vgm ");
+    assert_eq!(word(&bytes, 0x04) as usize, bytes.len() - 4);
+    assert_eq!(word(&bytes, 0x08), 0x171);
+    assert_eq!(word(&bytes, 0x18), 739);
+    assert_eq!(word(&bytes, 0x34), 0xCC);
+    assert_eq!(word(&bytes, 0x84), 1_789_773 | 0x8000_0000);
+    assert_eq!(bytes[0x100..], [0xB4, 0x15, 0x01, 0x62, 0xB4, 0x00, 0xBF, 0x73, 0xB4, 0x28, 0x12, 0x66]);
+  }
+
+  #[test]
+  fn samples_are_sent_once() {
+    let mut vgm = VgmWriter::new(Cursor::new(Vec::new()), 1_789_773).unwrap();
+    vgm.write_sample(0xFFFF, &[1, 2, 3]).unwrap();
+    vgm.write_sample(0xFFFF, &[1, 2, 3]).unwrap();
+    vgm.write_sample(0x8000, &[2]).unwrap();
+    vgm.finish().unwrap();
+
+    let bytes = vgm.get_ref().get_ref().clone();
+    assert_eq!(bytes[0x100..], [
+      // The sample wraps around to 0x8000
+      0x67, 0x66, 0xC2, 0x03, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0x01,
+      0x67, 0x66, 0xC2, 0x04, 0x00, 0x00, 0x00, 0x00, 0x80, 0x02, 0x03,
+      0x66,
+    ]);
+  }
+}
@@ -0,0 +1,94 @@
+use std::io::{Result, Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 44;
+
+/// 16-bit PCM WAV writer. The chunk sizes are unknown until the end so they are patched on `finish` or drop.
+pub struct WavWriter<W: Write + Seek> {
+  writer: W,
+  data_len: u32,
+  is_finished: bool,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+  pub fn new(mut writer: W, sample_rate: u32, channels: u16) -> Result<WavWriter<W>> {
+    let block_align = channels * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(HEADER_LEN - 8).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&channels.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * u32::from(block_align)).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&0u32.to_le_bytes())?;
+
+    Ok(WavWriter {
+      writer,
+      data_len: 0,
+      is_finished: false,
+    })
+  }
+
+  pub fn write_samples(&mut self, samples: &[i16]) -> Result<()> {
+    let bytes = samples.iter().flat_map(|s| s.to_le_bytes()).collect::<Vec<u8>>();
+    self.writer.write_all(&bytes)?;
+    self.data_len += bytes.len() as u32;
+    Ok(())
+  }
+
+  pub fn finish(&mut self) -> Result<()> {
+    if self.is_finished {
+      return Ok(());
+    }
+    self.is_finished = true;
+
+    self.writer.seek(SeekFrom::Start(4))?;
+    self.writer.write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+    self.writer.seek(SeekFrom::Start(u64::from(HEADER_LEN) - 4))?;
+    self.writer.write_all(&self.data_len.to_le_bytes())?;
+    self.writer.seek(SeekFrom::End(0))?;
+    self.writer.flush()
+  }
+
+  #[cfg(test)]
+  pub fn get_ref(&self) -> &W {
+    &self.writer
+  }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+  fn drop(&mut self) {
+    if let Err(e) = self.finish() {
+      eprintln!("Failed to finish WAV file: {}", e);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use std::io::Cursor;
+
+  use crate::recorder::wav::WavWriter;
+
+  #[test]
+  fn header_sizes_are_patched() {
+    let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44_100, 2).unwrap();
+    wav.write_samples(&[1, -1, 2, -2]).unwrap();
+    wav.finish().unwrap();
+
+    let bytes = wav.get_ref().get_ref();
+    assert_eq!(bytes.len(), 44 + 8);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 36 + 8);
+    assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 44_100);
+    assert_eq!(u32::from_le_bytes(bytes[40..44].try_into().unwrap()), 8);
+    assert_eq!(&bytes[44..46], &1i16.to_le_bytes());
+    assert_eq!(&bytes[46..48], &(-1i16).to_le_bytes());
+  }
+}
@@ -0,0 +1,84 @@
+use std::io::{Result, Write};
+
+use crate::gfx::frame::Frame;
+
+/// YUV4MPEG2 writer with full resolution chroma (`C444`) so no color information is lost to subsampling
+pub struct Y4mWriter<W: Write> {
+  writer: W,
+  width: u32,
+  height: u32,
+  planes: [Vec<u8>; 3],
+}
+
+impl<W: Write> Y4mWriter<W> {
+  pub fn new(mut writer: W, width: u32, height: u32, frame_rate: (u32, u32)) -> Result<Y4mWriter<W>> {
+    writeln!(writer, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444", width, height, frame_rate.0, frame_rate.1)?;
+
+    let plane_len = (width * height) as usize;
+    Ok(Y4mWriter {
+      writer,
+      width,
+      height,
+      planes: [vec![0; plane_len], vec![0; plane_len], vec![0; plane_len]],
+    })
+  }
+
+  pub fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+    assert!(frame.width == self.width && frame.height == self.height, "Frame size changed while recording");
+
+    // Frame rows are bottom-up, Y4M is top-down
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let (luma, cb, cr) = rgb_to_ycbcr(frame.get_pixel(x, self.height - 1 - y));
+        let idx = (y * self.width + x) as usize;
+        self.planes[0][idx] = luma;
+        self.planes[1][idx] = cb;
+        self.planes[2][idx] = cr;
+      }
+    }
+
+    self.writer.write_all(b"FRAME\n")?;
+    for plane in &self.planes {
+      self.writer.write_all(plane)?;
+    }
+    Ok(())
+  }
+
+  pub fn flush(&mut self) -> Result<()> {
+    self.writer.flush()
+  }
+
+  #[cfg(test)]
+  pub fn get_ref(&self) -> &W {
+    &self.writer
+  }
+}
+
+// BT.601 limited range, what encoders assume for Y4M without a color range tag
+fn rgb_to_ycbcr(rgb: [u8; 3]) -> (u8, u8, u8) {
+  let [r, g, b] = rgb.map(f32::from);
+  let y = 16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0;
+  let cb = 128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0;
+  let cr = 128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0;
+  (y.round() as u8, cb.round() as u8, cr.round() as u8)
+}
+
+#[cfg(test)]
+mod test {
+  use crate::gfx::frame::Frame;
+  use crate::recorder::y4m::Y4mWriter;
+
+  #[test]
+  fn frame_layout() {
+    let mut y4m = Y4mWriter::new(Vec::new(), 2, 2, (60, 1)).unwrap();
+    let mut frame = Frame::new(2, 2);
+    // Top left pixel, rows are stored bottom-up
+    frame.set_pixel(0, 1, [255, 255, 255]);
+    y4m.write_frame(&frame).unwrap();
+
+    let header = b"YUV4MPEG2 W2 H2 F60:1 Ip A1:1 C444\nFRAME\n";
+    let bytes = y4m.get_ref();
+    assert_eq!(&bytes[..header.len()], header);
+    assert_eq!(&bytes[header.len()..], &[235, 16, 16, 16, 128, 128, 128, 128, 128, 128, 128, 128]);
+  }
+}